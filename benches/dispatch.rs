@@ -0,0 +1,162 @@
+// dispatch of instructions read from memory against predecoded ones
+// and compiled code, run by `cargo bench [--features jit]`.
+// Predecoding saves only fetch and decode, registers are still read
+// from vm every instruction, so it is about 1.2x - 1.4x faster
+
+use std::{
+    hint::black_box,
+    io::{Cursor, Empty},
+    time::{Duration, Instant},
+};
+
+use virtual_machine::{image, image::Image, VirtualMachine, Word};
+
+// runs of every program, the fastest one is reported
+const RUNS: usize = 5;
+
+type Vm = VirtualMachine<Cursor<Vec<u8>>, Empty>;
+
+// time and instructions of the fastest run of vm set up by `setup`
+fn measure(
+    image: &Image,
+    expected: Word,
+    setup: impl Fn(&mut Vm),
+) -> (Duration, u64) {
+    let mut vm = VirtualMachine::new()
+        .with_input(Cursor::new(Vec::new()))
+        .with_output(std::io::empty());
+    setup(&mut vm);
+    vm.load_image(image).unwrap();
+    let mut best = Duration::MAX;
+    let mut steps = 0;
+    for _ in 0..RUNS {
+        let before = vm.steps();
+        let start = Instant::now();
+        let result = vm.call(black_box(image.get_entry_point()), &[]);
+        let elapsed = start.elapsed();
+        assert_eq!(result.unwrap(), expected);
+        best = best.min(elapsed);
+        steps = vm.steps() - before;
+    }
+    (best, steps)
+}
+
+fn bench(name: &str, image: &Image, expected: Word) {
+    let (memory, steps) = measure(image, expected, |_| ());
+    let (decoded, _) = measure(image, expected, |vm| vm.set_predecode(true));
+    let per_step = |time: Duration| time.as_nanos() as f64 / steps as f64;
+    print!(
+        "{name:<10} {steps:>10} instructions  \
+         memory {:>6.2} ns  predecoded {:>6.2} ns  speedup {:.2}x",
+        per_step(memory),
+        per_step(decoded),
+        memory.as_secs_f64() / decoded.as_secs_f64(),
+    );
+
+    #[cfg(feature = "jit")]
+    {
+        // code, which is not compiled, runs predecoded
+        let (compiled, _) = measure(image, expected, |vm| {
+            vm.set_jit(true).unwrap();
+        });
+        // timings are only reported, share of compiled
+        // instructions is checked by tests/jit.rs
+        print!(
+            "  jit {:>6.2} ns  speedup {:.2}x  over predecoded {:.2}x",
+            per_step(compiled),
+            memory.as_secs_f64() / compiled.as_secs_f64(),
+            decoded.as_secs_f64() / compiled.as_secs_f64(),
+        );
+    }
+    println!();
+}
+
+// counts ax down to zero
+fn countdown(count: Word) -> Image {
+    image! {
+        entry:
+            mov ax, count;
+            mov bx, 0;
+            mov dx, @again;
+        again:
+            dec;
+            jne;
+            ret;
+    }
+}
+
+// naive recursive fibonacci number of n
+fn fibonacci(n: Word) -> Image {
+    image! {
+        entry:
+            mov cx, n;
+            push;
+            mov dx, @fib;
+            call;
+            pop;
+            ret;
+
+        fib:
+            enter 1;
+            load [fp + 2];
+            mov bx, 2;
+            mov dx, @done;
+            jl;
+            dec;
+            mov cx, ax;
+            push;
+            mov dx, @fib;
+            call;
+            pop;
+            store [lp + 0];
+            load [fp + 2];
+            dec;
+            dec;
+            mov cx, ax;
+            push;
+            mov dx, @fib;
+            call;
+            pop;
+            mov bx, ax;
+            load [lp + 0];
+            add;
+        done:
+            leave;
+            ret;
+    }
+}
+
+// x = x * 0.5 + 1.0 repeated count times, converges to 2
+fn reals(count: Word) -> Image {
+    image! {
+        entry:
+            enter 2;
+            mov ax, count;
+            store [lp + 0];
+            mov ax, (0.0f64.to_bits());
+            store [lp + 1];
+        again:
+            load [lp + 1];
+            mov bx, (0.5f64.to_bits());
+            fmul;
+            mov bx, (1.0f64.to_bits());
+            fadd;
+            store [lp + 1];
+            load [lp + 0];
+            dec;
+            store [lp + 0];
+            mov bx, 0;
+            mov dx, @again;
+            jne;
+            load [lp + 1];
+            crtow;
+            leave;
+            ret;
+    }
+}
+
+fn main() {
+    bench("loop", &countdown(10_000_000), 0);
+    bench("recursion", &fibonacci(25), 75025);
+    bench("reals", &reals(1_000_000), 2);
+}
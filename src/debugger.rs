@@ -0,0 +1,386 @@
+use std::{
+    collections::{BTreeMap, BTreeSet},
+    io::{self, Read, Write},
+};
+
+use virtual_machine::{
+    disassembler::Disassembler,
+    image::{format_instruction, Image},
+    op_codes::OpCode,
+    StepOutcome,
+    VirtualMachine,
+    Word,
+};
+
+const HELP: &str = "\
+break <location>      set breakpoint (b)
+delete <location>     clear breakpoint (d)
+info                  list breakpoints
+step [count]          execute instructions (s)
+next                  step over call (n)
+continue              run until breakpoint or end (c)
+finish                run until return from current procedure
+regs                  print registers (r)
+x <location> [count]  examine memory words
+disas [location] [count]
+                      disassemble, around ip by default (l)
+help                  print this help (h)
+quit                  exit debugger (q)
+
+location is label or address, empty line repeats last command.
+Commands are read from stdin, program reads its input from file
+given by `debug --input FILE`, without it program gets end of input";
+
+// lines of disassembly printed by default
+const DISAS_LINES: Word = 8;
+// instructions shown before ip by default
+const DISAS_CONTEXT: usize = 3;
+// words printed by `x` by default and per line
+const EXAMINE_WORDS: Word = 8;
+const WORDS_PER_LINE: Word = 4;
+
+// program reads its own input, so it does not take commands
+type Vm = VirtualMachine<Box<dyn Read>>;
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+enum State {
+    Running,
+    Halted,
+    Trapped,
+}
+
+pub struct Debugger {
+    vm         : Vm,
+    // label -> address
+    labels     : BTreeMap<String, Word>,
+    // address -> label
+    names      : BTreeMap<Word, String>,
+    // instruction starts found by disassembler
+    code       : BTreeSet<Word>,
+    breakpoints: BTreeSet<Word>,
+    state      : State,
+}
+
+impl Debugger {
+    pub fn new(image: &Image, input: Box<dyn Read>) -> Result<Self, String> {
+        let mut vm = VirtualMachine::new().with_input(input);
+        vm.load_image(image).map_err(|err| err.to_string())?;
+
+        let disassembler = Disassembler::new(image);
+        let mut names = disassembler.labels().clone();
+        names
+            .entry(image.get_entry_point())
+            .or_insert_with(|| "entry".to_string());
+
+        let mut labels: BTreeMap<String, Word> = names
+            .iter()
+            .map(|(address, name)| (name.clone(), *address))
+            .collect();
+        for (name, symbol) in image.get_symbols() {
+            labels.insert(name.clone(), symbol.address);
+        }
+
+        let code = (0..image.get_image().len() as Word)
+            .filter(|address| disassembler.is_code(*address))
+            .collect();
+
+        Ok(Self {
+            vm,
+            labels,
+            names,
+            code,
+            breakpoints: BTreeSet::new(),
+            state      : State::Running,
+        })
+    }
+
+    pub fn run(&mut self) {
+        let stdin = io::stdin();
+        let mut last = String::new();
+
+        self.print_location();
+
+        loop {
+            print!("(kdb) ");
+            io::stdout().flush().unwrap();
+
+            let mut line = String::new();
+            match stdin.read_line(&mut line) {
+                Ok(0) | Err(_) => break,
+                Ok(_) => (),
+            }
+
+            let line = match line.trim() {
+                "" => last.clone(),
+                line => line.to_string(),
+            };
+            last = line.clone();
+
+            if !self.command(&line) {
+                break;
+            }
+        }
+    }
+
+    /// returns false when debugger must exit
+    fn command(&mut self, line: &str) -> bool {
+        let mut words = line.split_whitespace();
+        let Some(command) = words.next() else {
+            return true;
+        };
+        let args: Vec<&str> = words.collect();
+
+        let result = match command {
+            "b" | "break" => self.set_breakpoint(&args),
+            "d" | "delete" => self.delete_breakpoint(&args),
+            "info" => {
+                self.print_breakpoints();
+                Ok(())
+            }
+            "s" | "step" => self.step(&args),
+            "n" | "next" => self.next(),
+            "c" | "continue" => self.run_until(|_, _, _| false),
+            "finish" => self.finish(),
+            "r" | "regs" => {
+                println!("{}", self.vm.registers());
+                Ok(())
+            }
+            "x" => self.examine(&args),
+            "l" | "disas" => self.disassemble(&args),
+            "h" | "help" => {
+                println!("{HELP}");
+                Ok(())
+            }
+            "q" | "quit" => return false,
+            _ => Err(format!("unknown command `{command}`, try `help`")),
+        };
+
+        if let Err(message) = result {
+            println!("{message}");
+        }
+
+        true
+    }
+
+    fn location(&self, text: &str) -> Result<Word, String> {
+        if let Some(&address) = self.labels.get(text) {
+            return Ok(address);
+        }
+
+        let parsed = match text.strip_prefix("0x") {
+            Some(hex) => Word::from_str_radix(hex, 16),
+            None => text.parse(),
+        };
+        parsed.map_err(|_| format!("unknown location `{text}`"))
+    }
+
+    fn count(args: &[&str], idx: usize, default: Word) -> Result<Word, String> {
+        match args.get(idx) {
+            Some(text) => text
+                .parse()
+                .map_err(|_| format!("invalid count `{text}`")),
+            None => Ok(default),
+        }
+    }
+
+    fn set_breakpoint(&mut self, args: &[&str]) -> Result<(), String> {
+        let [location] = args else {
+            return Err("usage: break <location>".to_string());
+        };
+        let address = self.location(location)?;
+        self.breakpoints.insert(address);
+        println!("Breakpoint at {}", self.describe(address));
+        Ok(())
+    }
+
+    fn delete_breakpoint(&mut self, args: &[&str]) -> Result<(), String> {
+        let [location] = args else {
+            return Err("usage: delete <location>".to_string());
+        };
+        let address = self.location(location)?;
+        if !self.breakpoints.remove(&address) {
+            return Err(format!("no breakpoint at {}", self.describe(address)));
+        }
+        Ok(())
+    }
+
+    fn print_breakpoints(&self) {
+        if self.breakpoints.is_empty() {
+            println!("No breakpoints");
+        }
+        for address in &self.breakpoints {
+            println!("{}", self.describe(*address));
+        }
+    }
+
+    /// executes one instruction and returns its opcode
+    fn step_one(&mut self) -> Result<Word, String> {
+        if self.state != State::Running {
+            return Err("program is not running".to_string());
+        }
+
+        let opcode = self.vm.memory()[self.vm.ip() as usize];
+        let outcome = match self.vm.step() {
+            StepOutcome::SyscallPending(_) => self.vm.complete_syscall(),
+            outcome => outcome,
+        };
+
+        match outcome {
+            StepOutcome::Continued | StepOutcome::SyscallPending(_) => (),
+            StepOutcome::Halted(value) => {
+                self.state = State::Halted;
+                println!("Program ended with code: {value}");
+            }
+            StepOutcome::Trapped(err) => {
+                self.state = State::Trapped;
+                println!("Execution failed: {err}");
+                if let Some(registers) = err.registers() {
+                    println!("{registers}");
+                }
+            }
+        }
+
+        Ok(opcode)
+    }
+
+    /// steps until `stop` returns true for executed opcode,
+    /// program ends or breakpoint is reached
+    fn run_until(
+        &mut self,
+        mut stop: impl FnMut(&Vm, Word, Word) -> bool,
+    ) -> Result<(), String> {
+        let sp = self.vm.registers().sp;
+        loop {
+            let opcode = self.step_one()?;
+            if self.state != State::Running {
+                return Ok(());
+            }
+            if stop(&self.vm, opcode, sp) {
+                break;
+            }
+            if self.breakpoints.contains(&self.vm.ip()) {
+                println!("Breakpoint reached");
+                break;
+            }
+        }
+        self.print_location();
+        Ok(())
+    }
+
+    fn step(&mut self, args: &[&str]) -> Result<(), String> {
+        let count = Self::count(args, 0, 1)?;
+        if count == 0 {
+            return Err("step count must be positive".to_string());
+        }
+        let mut executed = 0;
+        self.run_until(|_, _, _| {
+            executed += 1;
+            executed >= count
+        })
+    }
+
+    fn next(&mut self) -> Result<(), String> {
+        let registers = self.vm.registers();
+        let opcode = self.vm.memory().get(registers.ip as usize);
+        if opcode != Some(&OpCode::CALL) {
+            return self.step(&[]);
+        }
+        // recursive calls return to the same address with lower sp
+        self.run_until(|vm, _, _| {
+            let current = vm.registers();
+            current.ip == registers.ip + 1 && current.sp == registers.sp
+        })
+    }
+
+    fn finish(&mut self) -> Result<(), String> {
+        self.run_until(|vm, opcode, sp| {
+            opcode == OpCode::RET && vm.registers().sp > sp
+        })
+    }
+
+    fn examine(&self, args: &[&str]) -> Result<(), String> {
+        let Some(location) = args.first() else {
+            return Err("usage: x <location> [count]".to_string());
+        };
+        let start = self.location(location)?;
+        let count = Self::count(args, 1, EXAMINE_WORDS)?;
+        let memory = self.vm.memory();
+        let end = start
+            .saturating_add(count)
+            .min(memory.len() as Word);
+
+        let mut address = start;
+        while address < end {
+            let line_end = (address + WORDS_PER_LINE).min(end);
+            let words: Vec<String> = memory
+                [address as usize..line_end as usize]
+                .iter()
+                .map(|word| format!("0x{word:x}"))
+                .collect();
+            println!("0x{address:04x}: {}", words.join(" "));
+            address = line_end;
+        }
+        Ok(())
+    }
+
+    fn disassemble(&self, args: &[&str]) -> Result<(), String> {
+        let ip = self.vm.ip();
+        let start = match args.first() {
+            Some(location) => self.location(location)?,
+            None => self
+                .code
+                .range(..ip)
+                .rev()
+                .take(DISAS_CONTEXT)
+                .last()
+                .copied()
+                .unwrap_or(ip),
+        };
+        let count = Self::count(args, 1, DISAS_LINES)?;
+
+        let memory = self.vm.memory();
+        let mut address = start;
+        for _ in 0..count {
+            if address as usize >= memory.len() {
+                break;
+            }
+            if let Some(name) = self.names.get(&address) {
+                println!("{name}:");
+            }
+            let (text, length) = Self::instruction(memory, address);
+            let marker = if address == ip {
+                "=>"
+            } else if self.breakpoints.contains(&address) {
+                " *"
+            } else {
+                "  "
+            };
+            println!("{marker} 0x{address:04x}: {text}");
+            address += length;
+        }
+        Ok(())
+    }
+
+    fn print_location(&self) {
+        if self.state != State::Running {
+            return;
+        }
+        let ip = self.vm.ip();
+        let (text, _) = Self::instruction(self.vm.memory(), ip);
+        println!("=> {}: {text}", self.describe(ip));
+    }
+
+    fn instruction(memory: &[Word], address: Word) -> (String, Word) {
+        match format_instruction(memory, address as usize) {
+            Some((text, length)) => (text, length as Word),
+            None => (format!("0x{:x}", memory[address as usize]), 1),
+        }
+    }
+
+    fn describe(&self, address: Word) -> String {
+        match self.names.get(&address) {
+            Some(name) => format!("0x{address:04x} <{name}>"),
+            None => format!("0x{address:04x}"),
+        }
+    }
+}
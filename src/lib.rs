@@ -0,0 +1,42 @@
+//! kondra virtual machine
+//!
+//! Images are built with `Image` (or the `image!` macro) or assembled
+//! from text by `Assembler`, saved and loaded as `.kondra` files and
+//! executed by `VirtualMachine`. Host functions are exposed to programs
+//! through `SyscallHandler`.
+
+mod virtual_machine;
+
+pub use virtual_machine::{
+    assembler,
+    coverage,
+    disassembler,
+    error,
+    heap,
+    image,
+    limits,
+    observer,
+    op_codes,
+    profile,
+    snapshot,
+    syscall,
+    trace,
+    Real,
+    SWord,
+    StepOutcome,
+    VirtualMachine,
+    Word,
+};
+
+pub use assembler::{Assembler, AsmError, SourceMap};
+pub use coverage::Coverage;
+pub use disassembler::{disassemble, Disassembler};
+pub use error::{Registers, VmError};
+pub use image::Image;
+pub use limits::Limits;
+pub use observer::{NoObserver, Observer};
+pub use op_codes::OpCode;
+pub use profile::Profiler;
+pub use snapshot::Snapshot;
+pub use syscall::{SyscallContext, SyscallHandler, Syscalls};
+pub use trace::{TraceFormat, Tracer};
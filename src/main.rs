@@ -1,8 +1,33 @@
-use virtual_machine::{image::Image, VirtualMachine, op_codes::OpCode as OC};
-use std::env;
+use virtual_machine::{
+    assembler::Assembler,
+    coverage::Coverage,
+    disassembler::disassemble,
+    image,
+    image::Image,
+    limits::Limits,
+    observer::Observer,
+    op_codes::OpCode,
+    profile::Profiler,
+    snapshot::Snapshot,
+    trace::{TraceFormat, Tracer},
+    StepOutcome,
+    VirtualMachine,
+    VmError,
+    Word,
+    syscall,
+};
+use debugger::Debugger;
+use std::{
+    env,
+    fs::{self, File},
+    io::{self, BufReader, BufWriter, Read, Stdin, Stdout, Write},
+    ops::Range,
+    process,
+    time::Duration,
+};
 
 
-mod virtual_machine;
+mod debugger;
 
 // all before entry point is data segment
 // all procedures below main
@@ -17,25 +42,64 @@ fn main() {
 
     match args[1].as_str() {
         "run" => {
-            assert_eq!(args.len(), 3);
-            let mut vm = VirtualMachine::new();
-            let mut i = Image::new();
-            if let Err(msg) = 
-                i.load_from_file(args[2].as_str()) 
-            {
-                panic!("{msg}");
-            } else if let Err(msg) = vm.load_image(&i) {
-                panic!("{msg}");
-            } else {
-                match vm.execute() {
-                    Ok(val) => {
-                        println!("Program ended with code: {val}");
-                    },
-                    Err(_) => {
-                        println!("Execution failed");
+            assert!(args.len() >= 3);
+            let options = parse_run_options(&args[2..]);
+            let result = match &options.trace {
+                Some(_) if options.coverage.is_some() =>
+                    panic!("--trace and --coverage can not be combined"),
+                None if options.coverage.is_some() &&
+                        options.resume.is_some() =>
+                    panic!("--coverage needs an image, not a snapshot"),
+                None if options.coverage.is_some() => {
+                    let coverage = Coverage::new();
+                    let mut vm = VirtualMachine::new().with_observer(coverage);
+                    let result = run(&mut vm, &options);
+                    write_coverage(vm.observer(), &options);
+                    result
+                }
+                Some(trace) => {
+                    let tracer = create_tracer(trace);
+                    let mut vm = VirtualMachine::new().with_observer(tracer);
+                    let result = run(&mut vm, &options);
+                    if let Err(err) = vm.observer_mut().flush() {
+                        panic!("Writing trace was failed: {err}");
                     }
+                    result
+                }
+                None => run(&mut VirtualMachine::new(), &options),
+            };
+            report(result);
+        },
+
+        "profile" => {
+            assert!(args.len() >= 3);
+            // --folded FILE writes stacks for flame graph,
+            // other options are options of run
+            let mut folded = None;
+            let mut rest = Vec::new();
+            let mut options = args[2..].iter();
+            while let Some(arg) = options.next() {
+                if arg == "--folded" {
+                    folded = options.next().cloned();
+                    assert!(folded.is_some(), "--folded requires value");
+                } else {
+                    rest.push(arg.clone());
                 }
             }
+            let options = parse_run_options(&rest);
+            assert!(options.trace.is_none(), "profile can not trace");
+
+            let mut vm = VirtualMachine::new().with_observer(Profiler::new());
+            let result = run(&mut vm, &options);
+            let profiler = vm.observer();
+            eprint!("{}", profiler.report(vm.symbols(), vm.memory()));
+            if let Some(path) = folded {
+                let stacks = profiler.folded(vm.symbols());
+                if let Err(err) = fs::write(&path, stacks) {
+                    panic!("{path}: {err}");
+                }
+            }
+            report(result);
         },
 
         "disasm" => {
@@ -46,7 +110,44 @@ fn main() {
             {
                 panic!("{msg}");
             } else {
-                println!("{}", i.get_mnemonics());
+                print!("{}", disassemble(&i));
+            }
+        }
+
+        "debug" => {
+            // --input FILE is read by program instead of stdin,
+            // which is taken by commands of debugger
+            let (input, path): (Box<dyn Read>, &String) = match &args[2..] {
+                [path] => (Box::new(io::empty()), path),
+                [flag, file, path] if flag == "--input" => {
+                    match File::open(file) {
+                        Ok(file) => (Box::new(BufReader::new(file)), path),
+                        Err(err) => panic!("{file}: {err}"),
+                    }
+                }
+                _ => panic!("usage: debug [--input FILE] IMAGE"),
+            };
+            let mut i = Image::new();
+            if let Err(msg) = 
+                i.load_from_file(path.as_str()) 
+            {
+                panic!("{msg}");
+            }
+            match Debugger::new(&i, input) {
+                Ok(mut debugger) => debugger.run(),
+                Err(msg) => panic!("{msg}"),
+            }
+        }
+
+        "asm" => {
+            assert_eq!(args.len(), 4);
+            let source = match fs::read_to_string(args[2].as_str()) {
+                Ok(source) => source,
+                Err(err) => panic!("{err}"),
+            };
+            match Assembler::new().assemble(&source) {
+                Ok(i) => i.save_to_file(args[3].as_str()).unwrap(),
+                Err(err) => panic!("{}: {err}", args[2]),
             }
         }
 
@@ -60,27 +161,334 @@ fn main() {
     }
 }
 
-fn create_hello() -> Image {
-    let mut i = image! {
-        'H''e''l''l''o'' ''W''o''r''l''d''!''\n' 
-        0 
-        0 
-        0 
-        0 
+// instructions between checkpoints by default
+const CHECKPOINT_INTERVAL: u64 = 10_000_000;
+
+struct RunOptions {
+    limits    : Limits,
+    // image, not used with resume
+    path      : Option<String>,
+    // snapshot to continue from
+    resume    : Option<String>,
+    // snapshot written while program runs
+    checkpoint: Option<String>,
+    every     : u64,
+    trace     : Option<TraceOptions>,
+    // executed addresses and branches are written here
+    coverage  : Option<String>,
+    // assembler source of image for lcov coverage
+    source    : Option<String>,
+    predecode : bool,
+    jit       : bool,
+}
+
+struct TraceOptions {
+    // stderr when not set
+    path     : Option<String>,
+    format   : TraceFormat,
+    addresses: Option<Range<Word>>,
+    opcodes  : Vec<Word>,
+}
+
+// options of run before path of image:
+// --fuel N, --time MS, --heap BYTES, --output BYTES, --input BYTES,
+// --resume SNAPSHOT instead of image, input read before snapshot
+// is skipped,
+// --checkpoint SNAPSHOT [--every N] saves state every N instructions
+// and when fuel or time is over,
+// --trace[=FILE] writes executed instructions to stderr or file,
+// --trace-format text|json, --trace-range START..END and
+// --trace-opcode NAME|CODE, which may be repeated, filter them,
+// --coverage FILE writes lcov for --source SOURCE of image or
+// annotated listing of image without it,
+// --predecode executes instructions from predecoded cache,
+// --jit compiles hot code when built with jit feature, the rest
+// is predecoded as with --predecode
+fn parse_run_options(args: &[String]) -> RunOptions {
+    let mut options = RunOptions {
+        limits    : Limits::default(),
+        path      : None,
+        resume    : None,
+        checkpoint: None,
+        every     : CHECKPOINT_INTERVAL,
+        trace     : None,
+        coverage  : None,
+        source    : None,
+        predecode : false,
+        jit       : false,
+    };
+    let mut args = args.iter();
+    while let Some(arg) = args.next() {
+        if !arg.starts_with("--") {
+            assert!(args.next().is_none(), "path of image must be last");
+            options.path = Some(arg.clone());
+            break;
+        }
+
+        if arg == "--trace" || arg.starts_with("--trace=") {
+            let trace = options.trace.get_or_insert_with(TraceOptions::new);
+            trace.path = arg.strip_prefix("--trace=").map(str::to_string);
+            continue;
+        }
+
+        if arg == "--predecode" {
+            options.predecode = true;
+            continue;
+        }
+        if arg == "--jit" {
+            options.jit = true;
+            continue;
+        }
+
+        let Some(value) = args.next() else {
+            panic!("{arg} requires value");
+        };
+        match arg.as_str() {
+            "--resume" => {
+                options.resume = Some(value.clone());
+                continue;
+            }
+            "--checkpoint" => {
+                options.checkpoint = Some(value.clone());
+                continue;
+            }
+            "--coverage" => {
+                options.coverage = Some(value.clone());
+                continue;
+            }
+            "--source" => {
+                options.source = Some(value.clone());
+                continue;
+            }
+            "--trace-format" | "--trace-range" | "--trace-opcode" => {
+                let trace = options.trace.get_or_insert_with(TraceOptions::new);
+                trace.parse(arg, value);
+                continue;
+            }
+            _ => (),
+        }
+
+        let value: u64 = match value.parse() {
+            Ok(value) => value,
+            Err(err) => panic!("{arg} {value}: {err}"),
+        };
+        let limits = &mut options.limits;
+        match arg.as_str() {
+            "--fuel"   => limits.fuel = Some(value),
+            "--time"   => limits.time = Some(Duration::from_millis(value)),
+            "--heap"   => limits.heap_bytes = Some(value),
+            "--output" => limits.output_bytes = Some(value),
+            "--input"  => limits.input_bytes = Some(value),
+            "--every"  => options.every = value.max(1),
+            _ => panic!("unknown option {arg}"),
+        }
+    }
+    options
+}
+
+impl TraceOptions {
+    fn new() -> Self {
+        Self {
+            path     : None,
+            format   : TraceFormat::Text,
+            addresses: None,
+            opcodes  : Vec::new(),
+        }
+    }
+
+    fn parse(&mut self, arg: &str, value: &str) {
+        match arg {
+            "--trace-format" => self.format = match value {
+                "text" => TraceFormat::Text,
+                "json" => TraceFormat::Json,
+                _ => panic!("unknown trace format {value}"),
+            },
+            "--trace-range" => {
+                let Some((start, end)) = value.split_once("..") else {
+                    panic!("{arg} {value}: expected START..END");
+                };
+                self.addresses = Some(number(start)..number(end));
+            }
+            _ => {
+                // all opcodes of mnemonic, like `load` or `mov`
+                let opcodes: Vec<Word> = OpCode::MNEMONICS
+                    .iter()
+                    .filter(|(_, template)| {
+                        template.split(' ').next() == Some(value)
+                    })
+                    .map(|(opcode, _)| *opcode)
+                    .collect();
+                if opcodes.is_empty() {
+                    self.opcodes.push(number(value));
+                } else {
+                    self.opcodes.extend(opcodes);
+                }
+            }
+        }
+    }
+}
+
+fn number(text: &str) -> Word {
+    let parsed = match text.strip_prefix("0x") {
+        Some(hex) => Word::from_str_radix(hex, 16),
+        None => text.parse(),
+    };
+    match parsed {
+        Ok(value) => value,
+        Err(err) => panic!("{text}: {err}"),
+    }
+}
+
+fn create_tracer(options: &TraceOptions) -> Tracer<Box<dyn Write>> {
+    let output: Box<dyn Write> = match &options.path {
+        Some(path) => match File::create(path) {
+            Ok(file) => Box::new(BufWriter::new(file)),
+            Err(err) => panic!("{path}: {err}"),
+        },
+        None => Box::new(io::stderr()),
+    };
+    let mut tracer = Tracer::new(output, options.format);
+    if let Some(addresses) = &options.addresses {
+        tracer = tracer.with_addresses(addresses.clone());
+    }
+    if !options.opcodes.is_empty() {
+        tracer = tracer.with_opcodes(options.opcodes.iter().copied());
+    }
+    tracer
+}
+
+fn write_coverage(coverage: &Coverage, options: &RunOptions) {
+    let (Some(output), Some(path)) = (&options.coverage, &options.path) else {
+        panic!("coverage requires image");
+    };
+    let mut i = Image::new();
+    if let Err(msg) = i.load_from_file(path) {
+        panic!("{msg}");
+    }
+
+    let text = match &options.source {
+        Some(source_path) => {
+            let source = match fs::read_to_string(source_path) {
+                Ok(source) => source,
+                Err(err) => panic!("{source_path}: {err}"),
+            };
+            let assembled = Assembler::new().assemble_with_source_map(&source);
+            let (source_image, lines) = match assembled {
+                Ok(assembled) => assembled,
+                Err(err) => panic!("{source_path}: {err}"),
+            };
+            if source_image.get_image() != i.get_image() {
+                panic!("{source_path} is not source of {path}");
+            }
+            coverage.lcov(source_path, &i, &lines)
+        }
+        None => coverage.listing(&i),
+    };
+    if let Err(err) = fs::write(output, text) {
+        panic!("{output}: {err}");
+    }
+}
+
+// loads image or snapshot and runs it
+fn run<O: Observer>(
+    vm: &mut VirtualMachine<Stdin, Stdout, O>,
+    options: &RunOptions,
+) -> Result<Word, VmError> {
+    vm.set_limits(options.limits);
+    vm.set_predecode(options.predecode);
+    if options.jit {
+        #[cfg(feature = "jit")]
+        if let Err(msg) = vm.set_jit(true) {
+            panic!("{msg}");
+        }
+        #[cfg(not(feature = "jit"))]
+        panic!("--jit requires build with jit feature");
+    }
+    if let Some(path) = &options.resume {
+        match Snapshot::load_from_file(path) {
+            Ok(snapshot) => {
+                // input read before snapshot is not read again
+                let consumed = snapshot.input_bytes();
+                let skipped = io::copy(
+                    &mut io::stdin().lock().take(consumed),
+                    &mut io::sink(),
+                );
+                match skipped {
+                    Ok(count) if count == consumed => vm.restore(&snapshot),
+                    Ok(count) => panic!(
+                        "input has {count} bytes, \
+                         but snapshot read {consumed}"
+                    ),
+                    Err(err) => panic!("{err}"),
+                }
+            }
+            Err(msg) => panic!("{msg}"),
+        }
+    } else {
+        let mut i = Image::new();
+        let Some(path) = &options.path else {
+            panic!("path of image is missing");
+        };
+        if let Err(msg) = i.load_from_file(path) {
+            panic!("{msg}");
+        } else if let Err(msg) = vm.load_image(&i) {
+            panic!("{msg}");
+        }
+    }
+
+    let Some(checkpoint) = &options.checkpoint else {
+        return vm.execute();
     };
 
-    i.set_entry_point_here();
-
-    i.emit_from_other(
-        &image! {
-            OC::MOVE_OP_TO_DX 0
-            OC::MOVE_OP_TO_CX 13
-            OC::MOVE_OP_TO_AX 0
-            OC::SYSCALL
-            OC::MOVE_AX_TO_DX
-            OC::MOVE_OP_TO_AX 2
-            OC::SYSCALL
-        }
-    );
-    i
-}
\ No newline at end of file
+    let save = |vm: &VirtualMachine<Stdin, Stdout, O>| {
+        if let Err(msg) = vm.snapshot().save_to_file(checkpoint) {
+            panic!("{msg}");
+        }
+    };
+    loop {
+        match vm.run_for(options.every) {
+            StepOutcome::Continued => save(vm),
+            StepOutcome::Halted(value) => return Ok(value),
+            StepOutcome::Trapped(err) => {
+                if let VmError::FuelExhausted { .. } |
+                       VmError::TimeLimitExceeded { .. } = err
+                {
+                    save(vm);
+                }
+                return Err(err);
+            }
+            StepOutcome::SyscallPending(_) => unreachable!(),
+        }
+    }
+}
+
+fn report(result: Result<Word, VmError>) {
+    match result {
+        Ok(val) => {
+            println!("Program ended with code: {val}");
+        },
+        Err(err) => {
+            eprintln!("Execution failed: {err}");
+            if let Some(registers) = err.registers() {
+                eprintln!("{registers}");
+            }
+            process::exit(1);
+        }
+    }
+}
+
+fn create_hello() -> Image {
+    let hello = "Hello World!\n";
+    image! {
+        data [message: hello, 0, 0, 0, 0];
+
+        entry:
+            mov dx, @message;
+            mov cx, (hello.chars().count());
+            mov ax, syscall::PRINT;
+            syscall;
+            mov dx, ax;
+            mov ax, syscall::EXIT;
+            syscall;
+    }
+}
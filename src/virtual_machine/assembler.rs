@@ -0,0 +1,629 @@
+use std::{
+    collections::{BTreeMap, HashMap},
+    fmt,
+};
+
+use super::{
+    disassembler::local_label,
+    image::{Image, SectionKind, SymbolKind},
+    lexical_cast::LexicalCast,
+    op_codes::OpCode,
+    Real,
+    Word,
+};
+
+// source syntax:
+//
+//   ; comment till end of line
+//   .data                 - following lines are data only
+//   .code                 - following lines are instructions or data
+//   .entry [label]        - entry point here or at label
+//   .bss words            - zeroed words reserved after image
+//   .memory bytes         - vm memory required to run image
+//   .func label           - label is exported as function
+//   .object label         - label is exported as data
+//   .label label          - label is exported as plain label
+//   name:                 - label, may precede instruction or data
+//   name = 0x10           - label at given address
+//   mov ax, 0x2           - instruction as printed by disassembler
+//   load [dx+0x2]         - offsets are signed and written with sign
+//   "Hello\n", 0, 'c', 1.5, name
+//                         - data: strings, words, chars, reals,
+//                           addresses of labels
+//
+// labels are kept in image as symbols, ones defined in `.data`
+// are data, others are plain labels unless marked by `.func`,
+// `.object` or `.label`. Labels named `loc_<hex address>` by
+// disassembler are not kept unless marked, so images without
+// symbols round trip
+//
+// leading addresses as printed by `Image::get_mnemonics`
+// (`0x0000000000000011: mov dx, 0x0`) are ignored
+
+/// address of instruction -> line of source, which it was assembled from
+pub type SourceMap = BTreeMap<Word, usize>;
+
+const DIRECTIVES: [&str; 8] = [
+    ".data", ".code", ".entry", ".func", ".object", ".label", ".bss",
+    ".memory",
+];
+
+const REGISTERS: [&str; 8] = ["ip", "sp", "fp", "lp", "ax", "bx", "cx", "dx"];
+
+#[derive(Debug)]
+pub struct AsmError {
+    pub line   : usize,
+    pub message: String,
+}
+
+impl fmt::Display for AsmError {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        write!(f, "line {}: {}", self.line, self.message)
+    }
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+enum Section {
+    Data,
+    Code,
+}
+
+#[derive(Debug)]
+enum Value {
+    Word(Word),
+    Label(String),
+}
+
+#[derive(Debug)]
+struct Fixup {
+    address: Word,
+    label  : String,
+    line   : usize,
+}
+
+#[derive(Debug)]
+// target of `.entry` and line of directive
+enum Entry {
+    Address(Word, usize),
+    Label(String, usize),
+}
+
+#[derive(Debug)]
+pub struct Assembler {
+    image  : Image,
+    // name -> address and section of definition
+    labels : HashMap<String, (Word, Section)>,
+    // labels marked by `.func`, `.object`, `.label` and lines
+    // of directives
+    marks  : Vec<(String, SymbolKind, usize)>,
+    fixups : Vec<Fixup>,
+    entry  : Option<Entry>,
+    section: Section,
+    line   : usize,
+    lines  : SourceMap,
+}
+
+impl Default for Assembler {
+    fn default() -> Self {
+        Self::new()
+    }
+}
+
+impl Assembler {
+    pub fn new() -> Self {
+        Self {
+            image  : Image::new(),
+            labels : HashMap::new(),
+            marks  : Vec::new(),
+            fixups : Vec::new(),
+            entry  : None,
+            section: Section::Code,
+            line   : 0,
+            lines  : SourceMap::new(),
+        }
+    }
+
+    pub fn assemble(self, source: &str) -> Result<Image, AsmError> {
+        self
+            .assemble_with_source_map(source)
+            .map(|(image, _)| image)
+    }
+
+    /// assembles image and maps its instructions to lines of source
+    pub fn assemble_with_source_map(
+        mut self,
+        source: &str,
+    ) -> Result<(Image, SourceMap), AsmError> {
+        for (idx, line) in source.lines().enumerate() {
+            self.line = idx + 1;
+            self
+                .assemble_line(line)
+                .map_err(|message| self.error(message))?;
+        }
+
+        for fixup in &self.fixups {
+            match self.labels.get(&fixup.label) {
+                Some(&(address, _)) =>
+                    self.image.write_word(fixup.address, address),
+                None => return Err(AsmError {
+                    line   : fixup.line,
+                    message: format!("undefined label `{}`", fixup.label),
+                }),
+            }
+        }
+
+        self.define_symbols()?;
+
+        let entry_point = match self.entry.take() {
+            None => return Ok((self.image, self.lines)),
+            Some(Entry::Address(address, line)) => (address, line),
+            Some(Entry::Label(label, line)) => match self.labels.get(&label) {
+                Some(&(address, _)) => (address, line),
+                None => return Err(AsmError {
+                    line,
+                    message: format!("undefined label `{label}`"),
+                }),
+            }
+        };
+
+        if self.image.set_entry_point(entry_point.0).is_err() {
+            return Err(AsmError {
+                line   : entry_point.1,
+                message: "entry point is outside of image".to_string(),
+            });
+        }
+
+        Ok((self.image, self.lines))
+    }
+
+    fn define_symbols(&mut self) -> Result<(), AsmError> {
+        let mut kinds: HashMap<&str, SymbolKind> = self
+            .labels
+            .iter()
+            .filter(|(label, (address, _))| {
+                **label != local_label(*address)
+            })
+            .map(|(label, (_, section))| {
+                let kind = match section {
+                    Section::Data => SymbolKind::Data,
+                    Section::Code => SymbolKind::Label,
+                };
+                (label.as_str(), kind)
+            })
+            .collect();
+
+        for (label, kind, line) in &self.marks {
+            match self.labels.get_key_value(label) {
+                Some((label, _)) => {
+                    kinds.insert(label.as_str(), *kind);
+                }
+                None => return Err(AsmError {
+                    line   : *line,
+                    message: format!("undefined label `{label}`"),
+                }),
+            }
+        }
+
+        for (label, kind) in kinds {
+            let address = self.labels[label].0;
+            // labels are unique, so it never fails
+            self.image.define_symbol(label, address, kind).unwrap();
+        }
+
+        Ok(())
+    }
+
+    fn error(&self, message: String) -> AsmError {
+        AsmError { line: self.line, message }
+    }
+
+    fn assemble_line(&mut self, line: &str) -> Result<(), String> {
+        let mut rest = strip_address(strip_comment(line).trim());
+
+        while let Some((label, tail)) = split_label(rest) {
+            let address = self.image.get_image().len() as Word;
+            self.define_label(label, address)?;
+            rest = tail.trim_start();
+        }
+
+        if let Some((label, value)) = split_equate(rest) {
+            return self.define_label(label, parse_word(value)?);
+        }
+
+        if rest.is_empty() {
+            return Ok(());
+        }
+
+        if rest.starts_with('.') {
+            self.directive(rest)
+        } else if self.section == Section::Data || starts_literal(rest) {
+            self.data(rest)
+        } else {
+            self.instruction(rest)
+        }
+    }
+
+    fn define_label(
+        &mut self,
+        label  : &str,
+        address: Word,
+    ) -> Result<(), String> {
+        if REGISTERS.contains(&label) {
+            return Err(format!("register name `{label}` used as label"));
+        }
+        let value = (address, self.section);
+        if self.labels.insert(label.to_string(), value).is_some() {
+            return Err(format!("label `{label}` is already defined"));
+        }
+        Ok(())
+    }
+
+    fn directive(&mut self, text: &str) -> Result<(), String> {
+        let mut parts = text.split_whitespace();
+        let name = parts.next().unwrap_or_default();
+        let argument = parts.next();
+        if parts.next().is_some() {
+            return Err(format!("too many arguments for `{name}`"));
+        }
+
+        match (name, argument) {
+            (".data", None) => {
+                self.section = Section::Data;
+                self.image.begin_section(SectionKind::Data);
+            }
+            (".code", None) => {
+                self.section = Section::Code;
+                self.image.begin_section(SectionKind::Code);
+            }
+            (".entry", None) => {
+                let address = self.image.get_image().len() as Word;
+                self.set_entry(Entry::Address(address, self.line))?;
+            }
+            (".entry", Some(label)) if is_identifier(label) => {
+                self.set_entry(Entry::Label(label.to_string(), self.line))?;
+            }
+            (".func", Some(label)) if is_identifier(label) =>
+                self.mark(label, SymbolKind::Function),
+            (".object", Some(label)) if is_identifier(label) =>
+                self.mark(label, SymbolKind::Data),
+            (".label", Some(label)) if is_identifier(label) =>
+                self.mark(label, SymbolKind::Label),
+            // sizes are not negative
+            (".bss", Some(words)) if !words.starts_with('-') =>
+                self.image.set_bss_size(parse_word(words)?),
+            (".memory", Some(bytes)) if !bytes.starts_with('-') =>
+                self.image.set_memory_requirement(parse_word(bytes)?),
+            _ if DIRECTIVES.contains(&name) =>
+                return Err(format!("invalid arguments for `{name}`")),
+            _ => return Err(format!("unknown directive `{name}`")),
+        }
+
+        Ok(())
+    }
+
+    fn mark(&mut self, label: &str, kind: SymbolKind) {
+        self.marks.push((label.to_string(), kind, self.line));
+    }
+
+    fn set_entry(&mut self, entry: Entry) -> Result<(), String> {
+        if self.entry.is_some() {
+            return Err("entry point is already defined".to_string());
+        }
+        self.entry = Some(entry);
+        Ok(())
+    }
+
+    fn data(&mut self, text: &str) -> Result<(), String> {
+        for item in split_items(text) {
+            let item = item.trim();
+            if item.starts_with('"') {
+                self.image.emit_str(&parse_string(item)?);
+            } else {
+                let address = self.image.emit_opcode(0);
+                self.emit_value(address, parse_value(item)?);
+            }
+        }
+        Ok(())
+    }
+
+    fn instruction(&mut self, text: &str) -> Result<(), String> {
+        let text = normalize(text);
+        let address = self.image.get_image().len() as Word;
+        self.lines.insert(address, self.line);
+
+        // templates without operand go first, so registers
+        // are never taken for labels
+        if let Some((opcode, _)) = OpCode::MNEMONICS
+            .iter()
+            .find(|(_, template)| normalize(template) == text)
+        {
+            self.image.emit_opcode(*opcode);
+            return Ok(());
+        }
+
+        for (opcode, template) in OpCode::MNEMONICS {
+            let template = normalize(template);
+            let markers = ['#', '~'];
+            let Some((prefix, suffix)) = template.split_once(markers) else {
+                continue;
+            };
+            let offset = template.contains('~');
+
+            if text.len() > prefix.len() + suffix.len() &&
+               text.starts_with(prefix) &&
+               text.ends_with(suffix)
+            {
+                let operand = &text[prefix.len()..text.len() - suffix.len()];
+                let value = if offset {
+                    parse_offset(operand)?
+                } else {
+                    parse_value(operand)?
+                };
+                let address =
+                    self.image.emit_opcode_with_operand(*opcode, 0);
+                self.emit_value(address + 1, value);
+                return Ok(());
+            }
+        }
+
+        let missing = OpCode::MNEMONICS.iter().any(|(_, template)| {
+            let template = normalize(template);
+            template
+                .split_once(['#', '~'])
+                .is_some_and(|(prefix, suffix)| {
+                    text == format!("{}{suffix}", prefix.trim_end())
+                })
+        });
+        if missing {
+            return Err(format!("missing operand in `{text}`"));
+        }
+
+        Err(format!("unknown instruction `{text}`"))
+    }
+
+    fn emit_value(&mut self, address: Word, value: Value) {
+        match value {
+            Value::Word(word) => self.image.write_word(address, word),
+            Value::Label(label) => self.fixups.push(Fixup {
+                address,
+                label,
+                line: self.line,
+            }),
+        }
+    }
+}
+
+/// calls `f` for every char outside of string and char literals,
+/// stops when `f` returns false
+fn scan_unquoted(text: &str, mut f: impl FnMut(usize, char) -> bool) {
+    let mut quote: Option<char> = None;
+    let mut escaped = false;
+    for (idx, c) in text.char_indices() {
+        match quote {
+            Some(_) if escaped => escaped = false,
+            Some(_) if c == '\\' => escaped = true,
+            Some(q) if c == q => quote = None,
+            Some(_) => (),
+            None if c == '"' || c == '\'' => quote = Some(c),
+            None => if !f(idx, c) {
+                return;
+            }
+        }
+    }
+}
+
+fn strip_comment(line: &str) -> &str {
+    let mut end = line.len();
+    scan_unquoted(line, |idx, c| {
+        if c == ';' {
+            end = idx;
+            false
+        } else {
+            true
+        }
+    });
+    &line[..end]
+}
+
+fn strip_address(line: &str) -> &str {
+    if let Some((address, rest)) = line.split_once(':') {
+        if let Some(digits) = address.strip_prefix("0x") {
+            if !digits.is_empty() &&
+               digits.chars().all(|c| c.is_ascii_hexdigit())
+            {
+                return rest.trim_start();
+            }
+        }
+    }
+    line
+}
+
+fn split_label(line: &str) -> Option<(&str, &str)> {
+    let (label, rest) = line.split_once(':')?;
+    let label = label.trim_end();
+    if is_identifier(label) {
+        Some((label, rest))
+    } else {
+        None
+    }
+}
+
+fn split_equate(line: &str) -> Option<(&str, &str)> {
+    let (label, value) = line.split_once('=')?;
+    let label = label.trim_end();
+    if is_identifier(label) {
+        Some((label, value.trim_start()))
+    } else {
+        None
+    }
+}
+
+fn split_items(text: &str) -> Vec<&str> {
+    let mut items = Vec::new();
+    let mut start = 0;
+    scan_unquoted(text, |idx, c| {
+        if c == ',' {
+            items.push(&text[start..idx]);
+            start = idx + 1;
+        }
+        true
+    });
+    items.push(&text[start..]);
+    items
+}
+
+/// removes whitespaces outside of literals,
+/// except one separating mnemonic from operands
+fn normalize(text: &str) -> String {
+    let text = text.trim();
+    let (mnemonic, operands) = match text.split_once(char::is_whitespace) {
+        Some((mnemonic, operands)) => (mnemonic, operands.trim_start()),
+        None => return text.to_string(),
+    };
+
+    let mut result = String::from(mnemonic);
+    result.push(' ');
+
+    let mut last = 0;
+    scan_unquoted(operands, |idx, c| {
+        if c.is_whitespace() {
+            result.push_str(&operands[last..idx]);
+            last = idx + c.len_utf8();
+        }
+        true
+    });
+    result.push_str(&operands[last..]);
+
+    result
+}
+
+fn is_identifier(text: &str) -> bool {
+    let mut chars = text.chars();
+    match chars.next() {
+        Some(c) if c.is_ascii_alphabetic() || c == '_' =>
+            chars.all(|c| c.is_ascii_alphanumeric() || c == '_'),
+        _ => false,
+    }
+}
+
+fn starts_literal(text: &str) -> bool {
+    text.starts_with(|c: char| {
+        c.is_ascii_digit() || matches!(c, '"' | '\'' | '-' | '+')
+    })
+}
+
+fn parse_value(text: &str) -> Result<Value, String> {
+    let text = text.trim();
+    if is_identifier(text) {
+        if REGISTERS.contains(&text) {
+            return Err(format!("unexpected register `{text}`"));
+        }
+        return Ok(Value::Label(text.to_string()));
+    }
+    parse_word(text).map(Value::Word)
+}
+
+// offsets are written with explicit sign: `+0x3`, `-2`, `+label`
+fn parse_offset(text: &str) -> Result<Value, String> {
+    match text.strip_prefix('+') {
+        Some(rest) => parse_value(rest),
+        None if text.starts_with('-') => parse_word(text).map(Value::Word),
+        None => Err(format!("offset `{text}` must start with sign")),
+    }
+}
+
+fn parse_word(text: &str) -> Result<Word, String> {
+    let invalid = || format!("invalid literal `{text}`");
+
+    if text.starts_with('\'') {
+        let body = parse_quoted(text, '\'')?;
+        let mut chars = body.chars();
+        return match (chars.next(), chars.next()) {
+            (Some(c), None) => Ok(c as Word),
+            _ => Err(invalid()),
+        };
+    }
+
+    let (negative, unsigned) = match text.as_bytes().first() {
+        Some(b'-') => (true, &text[1..]),
+        Some(b'+') => (false, &text[1..]),
+        _ => (false, text),
+    };
+    let digits = unsigned.replace('_', "");
+
+    let (radix, digits) =
+        if let Some(d) = digits.strip_prefix("0x") {
+            (16, d)
+        } else if let Some(d) = digits.strip_prefix("0b") {
+            (2, d)
+        } else if let Some(d) = digits.strip_prefix("0o") {
+            (8, d)
+        } else if digits.contains('.') {
+            let real: Real = text.replace('_', "").parse().map_err(|_| invalid())?;
+            return real.lexical_cast().ok_or_else(invalid);
+        } else {
+            (10, digits.as_str())
+        };
+
+    let magnitude =
+        Word::from_str_radix(digits, radix).map_err(|_| invalid())?;
+
+    if !negative {
+        Ok(magnitude)
+    } else if magnitude <= 1 << (Word::BITS - 1) {
+        Ok(magnitude.wrapping_neg())
+    } else {
+        Err(invalid())
+    }
+}
+
+fn parse_string(text: &str) -> Result<String, String> {
+    parse_quoted(text, '"')
+}
+
+fn parse_quoted(text: &str, quote: char) -> Result<String, String> {
+    let invalid = || format!("invalid literal {text}");
+
+    let body = text
+        .strip_prefix(quote)
+        .and_then(|t| t.strip_suffix(quote))
+        .ok_or_else(invalid)?;
+
+    let mut result = String::new();
+    let mut chars = body.chars();
+    while let Some(c) = chars.next() {
+        if c == quote {
+            return Err(invalid());
+        }
+        if c != '\\' {
+            result.push(c);
+            continue;
+        }
+        match chars.next().ok_or_else(invalid)? {
+            'n'  => result.push('\n'),
+            't'  => result.push('\t'),
+            'r'  => result.push('\r'),
+            '0'  => result.push('\0'),
+            '\\' => result.push('\\'),
+            '\'' => result.push('\''),
+            '"'  => result.push('"'),
+            'u'  => {
+                let rest = chars.as_str();
+                let code = rest
+                    .strip_prefix('{')
+                    .and_then(|r| r.split_once('}'))
+                    .ok_or_else(invalid)?
+                    .0;
+                let c = u32::from_str_radix(code, 16)
+                    .ok()
+                    .and_then(char::from_u32)
+                    .ok_or_else(invalid)?;
+                result.push(c);
+                chars = rest[code.len() + 2..].chars();
+            }
+            _ => return Err(invalid()),
+        }
+    }
+
+    Ok(result)
+}
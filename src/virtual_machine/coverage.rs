@@ -0,0 +1,184 @@
+use std::{collections::BTreeMap, fmt::Write as _};
+
+use super::{
+    assembler::SourceMap,
+    error::{Registers, VmError},
+    image::Image,
+    lexical_cast::LexicalCast,
+    observer::Observer,
+    OpCode,
+    Real,
+    SWord,
+    Word,
+};
+
+/// outcomes of conditional jump
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq)]
+pub struct Branch {
+    pub taken    : u64,
+    pub not_taken: u64,
+}
+
+/// observer, which records executed addresses and outcomes of
+/// conditional jumps
+#[derive(Debug, Default)]
+pub struct Coverage {
+    hits    : BTreeMap<Word, u64>,
+    branches: BTreeMap<Word, Branch>,
+    // executed instruction
+    ip      : Word,
+    opcode  : Word,
+    // whether condition of executed jump holds, as its target
+    // may be the next instruction
+    taken   : bool,
+}
+
+/// JE ..= FJLE, which jump to dx or go to the next instruction
+pub fn is_conditional_jump(opcode: Word) -> bool {
+    (OpCode::JE..=OpCode::FJLE).contains(&opcode)
+}
+
+impl Coverage {
+    pub fn new() -> Self {
+        Self::default()
+    }
+
+    /// executions of instruction at address
+    pub fn hits(&self, address: Word) -> u64 {
+        self.hits.get(&address).copied().unwrap_or_default()
+    }
+
+    /// outcomes of conditional jump at address, if it was executed
+    pub fn branch(&self, address: Word) -> Option<Branch> {
+        self.branches.get(&address).copied()
+    }
+
+    /// lcov tracefile for instructions of image assembled from
+    /// source at `path` with source map
+    pub fn lcov(&self, path: &str, image: &Image, lines: &SourceMap) -> String {
+        let words = image.get_image();
+        let mut result = format!("TN:\nSF:{path}\n");
+
+        // instructions of line are counted by the first one,
+        // as line is executed from start
+        let mut hits: BTreeMap<usize, u64> = BTreeMap::new();
+        for (address, line) in lines {
+            hits.entry(*line).or_insert_with(|| self.hits(*address));
+        }
+
+        let mut branches = (0, 0);
+        for (address, line) in lines {
+            let opcode = words.get(*address as usize).copied();
+            if !opcode.is_some_and(is_conditional_jump) {
+                continue;
+            }
+            let outcomes = match self.branch(*address) {
+                Some(branch) => [branch.taken, branch.not_taken]
+                    .map(|count| count.to_string()),
+                None => ["-".to_string(), "-".to_string()],
+            };
+            for (idx, outcome) in outcomes.iter().enumerate() {
+                writeln!(result, "BRDA:{line},{address},{idx},{outcome}")
+                    .unwrap();
+                branches.0 += 1;
+                if !matches!(outcome.as_str(), "-" | "0") {
+                    branches.1 += 1;
+                }
+            }
+        }
+        writeln!(result, "BRF:{}\nBRH:{}", branches.0, branches.1).unwrap();
+
+        for (line, count) in &hits {
+            writeln!(result, "DA:{line},{count}").unwrap();
+        }
+        let executed = hits.values().filter(|count| **count > 0).count();
+        writeln!(result, "LF:{}\nLH:{executed}", hits.len()).unwrap();
+        result.push_str("end_of_record\n");
+        result
+    }
+
+    /// listing of image from entry point with executions of every
+    /// instruction, `#####` marks never executed ones
+    pub fn listing(&self, image: &Image) -> String {
+        let words = image.get_image();
+        let mut result = String::new();
+        let listing = image.get_mnemonics_from(image.get_entry_point());
+        for line in listing.lines() {
+            let address = line
+                .split_once(':')
+                .and_then(|(address, _)| address.strip_prefix("0x"))
+                .and_then(|hex| Word::from_str_radix(hex, 16).ok())
+                .unwrap_or_default();
+
+            match self.hits(address) {
+                0 => result.push_str("   #####  "),
+                count => write!(result, "{count:>8}  ").unwrap(),
+            }
+            result.push_str(line);
+
+            let opcode = words.get(address as usize).copied();
+            if opcode.is_some_and(is_conditional_jump) {
+                let branch = self.branch(address).unwrap_or_default();
+                write!(
+                    result,
+                    "  ; taken {}, not taken {}",
+                    branch.taken, branch.not_taken
+                ).unwrap();
+            }
+            result.push('\n');
+        }
+        result
+    }
+}
+
+impl Observer for Coverage {
+    fn before(&mut self, registers: &Registers, memory: &[Word]) {
+        self.ip = registers.ip;
+        self.opcode = memory
+            .get(registers.ip as usize)
+            .copied()
+            .unwrap_or_default();
+        *self.hits.entry(registers.ip).or_default() += 1;
+        self.taken = jump_taken(self.opcode, registers);
+    }
+
+    fn after(&mut self, _registers: &Registers, error: Option<&VmError>) {
+        if error.is_some() || !is_conditional_jump(self.opcode) {
+            return;
+        }
+        let branch = self.branches.entry(self.ip).or_default();
+        if self.taken {
+            branch.taken += 1;
+        } else {
+            branch.not_taken += 1;
+        }
+    }
+}
+
+// condition of conditional jump on ax and bx
+fn jump_taken(opcode: Word, registers: &Registers) -> bool {
+    let (ax, bx) = (registers.ax, registers.bx);
+    let signed = |ax: Word, bx: Word| -> (SWord, SWord) {
+        (ax.lexical_cast().unwrap(), bx.lexical_cast().unwrap())
+    };
+    let real = |ax: Word, bx: Word| -> (Real, Real) {
+        (ax.lexical_cast().unwrap(), bx.lexical_cast().unwrap())
+    };
+    match opcode {
+        OpCode::JE   => ax == bx,
+        OpCode::JNE  => ax != bx,
+        OpCode::JG   => { let (a, b) = signed(ax, bx); a > b }
+        OpCode::JGE  => { let (a, b) = signed(ax, bx); a >= b }
+        OpCode::JL   => { let (a, b) = signed(ax, bx); a < b }
+        OpCode::JLE  => { let (a, b) = signed(ax, bx); a <= b }
+        OpCode::JA   => ax > bx,
+        OpCode::JAE  => ax >= bx,
+        OpCode::JB   => ax < bx,
+        OpCode::JBE  => ax <= bx,
+        OpCode::FJG  => { let (a, b) = real(ax, bx); a > b }
+        OpCode::FJGE => { let (a, b) = real(ax, bx); a >= b }
+        OpCode::FJL  => { let (a, b) = real(ax, bx); a < b }
+        OpCode::FJLE => { let (a, b) = real(ax, bx); a <= b }
+        _ => false,
+    }
+}
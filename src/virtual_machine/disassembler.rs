@@ -0,0 +1,482 @@
+use std::collections::{BTreeMap, BTreeSet, HashMap};
+
+use super::{
+    image::{format_instruction, Image, SectionKind, SymbolKind},
+    into_char::IntoChar,
+    op_codes::OpCode,
+    syscall,
+    Word,
+};
+
+// words per line for data which does not look like a string
+const WORDS_PER_LINE: usize = 8;
+
+// shortest run of chars rendered as string literal
+const MIN_STRING_LENGTH: usize = 2;
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+enum Kind {
+    Data,
+    Instruction,
+    Operand,
+}
+
+/// Splits code sections of image into instructions and data by
+/// following control flow from entry point and renders it as
+/// assembler source, which assembles back into the same image.
+/// Sections of image are kept by `.code` and `.data` directives.
+/// Symbols of image are used as labels
+#[derive(Debug)]
+pub struct Disassembler<'a> {
+    image : &'a Image,
+    kinds : Vec<Kind>,
+    // words of code sections, instructions are found only there
+    code  : Vec<bool>,
+    // symbols and jump targets, first name is used in references
+    names : BTreeMap<Word, Vec<String>>,
+    labels: BTreeMap<Word, String>,
+    // `mov dx, #` instructions whose operand is a jump target
+    refs  : HashMap<Word, Word>,
+}
+
+impl<'a> Disassembler<'a> {
+    pub fn new(image: &'a Image) -> Self {
+        let mut code = vec![false; image.get_image().len()];
+        for section in image.sections() {
+            if section.kind == SectionKind::Code {
+                let start = section.address as usize;
+                code[start..start + section.length as usize].fill(true);
+            }
+        }
+        let mut disassembler = Self {
+            image,
+            kinds : vec![Kind::Data; image.get_image().len()],
+            code,
+            names : BTreeMap::new(),
+            labels: BTreeMap::new(),
+            refs  : HashMap::new(),
+        };
+        disassembler.analyze();
+        disassembler
+    }
+
+    /// whether instruction starts at address
+    pub fn is_code(&self, address: Word) -> bool {
+        self.kinds.get(address as usize) == Some(&Kind::Instruction)
+    }
+
+    pub fn labels(&self) -> &BTreeMap<Word, String> {
+        &self.labels
+    }
+
+    fn analyze(&mut self) {
+        let words = self.image.get_image();
+        let mut targets = BTreeSet::new();
+        let mut queue = vec![self.image.get_entry_point()];
+        // exported functions are reachable from host
+        queue.extend(
+            self.image
+                .get_symbols()
+                .values()
+                .filter(|symbol| symbol.kind == SymbolKind::Function)
+                .map(|symbol| symbol.address)
+        );
+
+        while let Some(start) = queue.pop() {
+            let mut address = start;
+            // known values of ax..dx and where dx was loaded
+            let mut regs: [Option<Word>; 4] = [None; 4];
+            let mut dx_source: Option<Word> = None;
+
+            loop {
+                let idx = address as usize;
+                let Some(&opcode) = words.get(idx) else {
+                    break;
+                };
+                let Some(length) = OpCode::length(opcode) else {
+                    break;
+                };
+                let end = idx + length as usize;
+                if end > words.len() ||
+                   self.kinds[idx..end].iter().any(|k| *k != Kind::Data) ||
+                   !self.code[idx..end].iter().all(|code| *code)
+                {
+                    break;
+                }
+
+                self.kinds[idx] = Kind::Instruction;
+                self.kinds[idx + 1..end].fill(Kind::Operand);
+
+                let mut jump = |regs: &[Option<Word>; 4], source| {
+                    if let Some(target) = regs[3] {
+                        queue.push(target);
+                        targets.insert(target);
+                        if let Some(source) = source {
+                            self.refs.insert(source, target);
+                        }
+                    }
+                };
+
+                match opcode {
+                    OpCode::JMP => {
+                        jump(&regs, dx_source);
+                        break;
+                    }
+                    OpCode::JE   | OpCode::JNE  |
+                    OpCode::JG   | OpCode::JGE  |
+                    OpCode::JL   | OpCode::JLE  |
+                    OpCode::JA   | OpCode::JAE  |
+                    OpCode::JB   | OpCode::JBE  |
+                    OpCode::FJG  | OpCode::FJGE |
+                    OpCode::FJL  | OpCode::FJLE |
+                    OpCode::CALL => jump(&regs, dx_source),
+                    OpCode::RET => break,
+                    // exit
+                    OpCode::SYSCALL if regs[0] == Some(syscall::EXIT) => break,
+                    _ => (),
+                }
+
+                match move_registers(opcode) {
+                    Some((dst, Some(src))) => {
+                        regs[dst] = regs[src];
+                        if dst == 3 {
+                            dx_source = None;
+                        }
+                    }
+                    Some((dst, None)) => {
+                        regs[dst] = Some(words[idx + 1]);
+                        if dst == 3 {
+                            dx_source = Some(address);
+                        }
+                    }
+                    // callee or syscall handler may change any register
+                    None if opcode == OpCode::CALL ||
+                            opcode == OpCode::SYSCALL => {
+                        regs = [None; 4];
+                        dx_source = None;
+                    }
+                    None => {
+                        for &reg in written_registers(opcode) {
+                            regs[reg] = None;
+                        }
+                        if regs[3].is_none() {
+                            dx_source = None;
+                        }
+                    }
+                }
+
+                address = end as Word;
+            }
+        }
+
+        // functions go first, so they name shared addresses
+        let mut symbols: Vec<(&String, SymbolKind, Word)> = self
+            .image
+            .get_symbols()
+            .iter()
+            .map(|(name, symbol)| (name, symbol.kind, symbol.address))
+            .collect();
+        symbols.sort_by_key(|(_, kind, _)| *kind as u32);
+
+        for (name, _, address) in symbols {
+            if self.can_label(address) {
+                self.names.entry(address).or_default().push(name.clone());
+            }
+        }
+
+        for target in targets {
+            if self.can_label(target) && !self.names.contains_key(&target) {
+                self.names.insert(target, vec![local_label(target)]);
+            }
+        }
+
+        self.labels = self
+            .names
+            .iter()
+            .map(|(address, names)| (*address, names[0].clone()))
+            .collect();
+
+        // addresses of symbols loaded in dx are printed as names
+        for (idx, opcode) in words.iter().enumerate() {
+            if self.kinds[idx] == Kind::Instruction &&
+               *opcode == OpCode::MOVE_OP_TO_DX &&
+               self.labels.contains_key(&words[idx + 1])
+            {
+                self.refs.entry(idx as Word).or_insert(words[idx + 1]);
+            }
+        }
+    }
+
+    // labels must not point into the middle of instruction,
+    // label after the last word is placed at the end
+    fn can_label(&self, address: Word) -> bool {
+        match self.kinds.get(address as usize) {
+            Some(kind) => *kind != Kind::Operand,
+            None => address == self.kinds.len() as Word,
+        }
+    }
+
+    pub fn disassemble(&self) -> String {
+        let words = self.image.get_image();
+        let entry_point = self.image.get_entry_point();
+        let mut result = String::new();
+        // whether the current section is code
+        let mut section: Option<bool> = None;
+        let mut idx = 0;
+
+        if self.image.get_bss_size() > 0 {
+            result.push_str(
+                format!(".bss 0x{:x}\n", self.image.get_bss_size()).as_str()
+            );
+        }
+
+        if self.image.get_memory_requirement() > 0 {
+            result.push_str(
+                format!(
+                    ".memory 0x{:x}\n",
+                    self.image.get_memory_requirement()
+                ).as_str()
+            );
+        }
+
+        // symbols, which are not placed as labels, are defined by address,
+        // and kinds, which differ from ones of section, are marked
+        for (name, symbol) in self.image.get_symbols() {
+            let placed = self
+                .names
+                .get(&symbol.address)
+                .is_some_and(|names| names.contains(name));
+            let code = match placed {
+                true => self
+                    .code
+                    .get(symbol.address as usize)
+                    .or(self.code.last())
+                    .copied()
+                    .unwrap_or(true),
+                false => {
+                    result.push_str(
+                        format!("{name} = 0x{:x}\n", symbol.address).as_str()
+                    );
+                    true
+                }
+            };
+            let mark = match (symbol.kind, code) {
+                (SymbolKind::Function, _) => Some(".func"),
+                (SymbolKind::Data, true) => Some(".object"),
+                (SymbolKind::Label, false) => Some(".label"),
+                _ => None,
+            };
+            if let Some(mark) = mark {
+                result.push_str(format!("{mark} {name}\n").as_str());
+            }
+        }
+
+        if !result.is_empty() && !words.is_empty() {
+            result.push('\n');
+        }
+
+        while idx < words.len() {
+            let address = idx as Word;
+            let kind = self.kinds[idx];
+            let code = self.code[idx];
+
+            if section != Some(code) {
+                if section.is_some() {
+                    result.push('\n');
+                }
+                result.push_str(match code {
+                    true => ".code\n",
+                    false => ".data\n",
+                });
+                section = Some(code);
+            }
+
+            if address == entry_point {
+                result.push_str(".entry\n");
+            }
+
+            self.push_names(address, &mut result);
+
+            if kind == Kind::Instruction {
+                let (text, length) = self.format_instruction(idx);
+                result.push_str("    ");
+                result.push_str(text.as_str());
+                result.push('\n');
+                idx += length;
+            } else {
+                let end = self.data_end(idx);
+                self.format_data(&words[idx..end], &mut result);
+                idx = end;
+            }
+        }
+
+        if self.names.contains_key(&(words.len() as Word)) {
+            if section.is_none() {
+                result.push_str(".code\n");
+            }
+            self.push_names(words.len() as Word, &mut result);
+        }
+
+        result
+    }
+
+    fn push_names(&self, address: Word, result: &mut String) {
+        for name in self.names.get(&address).into_iter().flatten() {
+            result.push_str(format!("{name}:\n").as_str());
+        }
+    }
+
+    fn format_instruction(&self, idx: usize) -> (String, usize) {
+        let words = self.image.get_image();
+        let (text, length) = format_instruction(words, idx).unwrap();
+
+        let label = self
+            .refs
+            .get(&(idx as Word))
+            .and_then(|target| self.labels.get(target));
+
+        match (label, OpCode::mnemonic(words[idx])) {
+            (Some(label), Some(template)) =>
+                (template.replace('#', label), length),
+            _ => (text, length),
+        }
+    }
+
+    // end of data run, which is stopped by code, labels, entry point
+    // and section boundary
+    fn data_end(&self, start: usize) -> usize {
+        let entry_point = self.image.get_entry_point() as usize;
+        let mut end = start + 1;
+        while end < self.kinds.len() &&
+              self.kinds[end] == Kind::Data &&
+              self.code[end] == self.code[start] &&
+              end != entry_point &&
+              !self.names.contains_key(&(end as Word))
+        {
+            end += 1;
+        }
+        end
+    }
+
+    fn format_data(&self, data: &[Word], result: &mut String) {
+        let mut idx = 0;
+        while idx < data.len() {
+            let string_length = data[idx..]
+                .iter()
+                .take_while(|word| is_string_char(**word))
+                .count();
+
+            if string_length >= MIN_STRING_LENGTH {
+                result.push_str("    \"");
+                for word in &data[idx..idx + string_length] {
+                    push_escaped(word.into_char().unwrap(), result);
+                }
+                result.push_str("\"\n");
+                idx += string_length;
+                continue;
+            }
+
+            let mut end = idx + 1;
+            while end < data.len() &&
+                  end - idx < WORDS_PER_LINE &&
+                  !starts_string(&data[end..])
+            {
+                end += 1;
+            }
+
+            let line: Vec<String> = data[idx..end]
+                .iter()
+                .map(|word| format!("0x{word:x}"))
+                .collect();
+            result.push_str("    ");
+            result.push_str(line.join(", ").as_str());
+            result.push('\n');
+            idx = end;
+        }
+    }
+}
+
+pub fn disassemble(image: &Image) -> String {
+    Disassembler::new(image).disassemble()
+}
+
+/// name of jump target without symbol, assembler does not
+/// export labels named so, as they are not in original image
+pub(super) fn local_label(address: Word) -> String {
+    format!("loc_{address:x}")
+}
+
+/// registers (0 - ax .. 3 - dx) changed by move instruction,
+/// source register is None for moves of operand
+fn move_registers(opcode: Word) -> Option<(usize, Option<usize>)> {
+    match opcode {
+        OpCode::MOVE_OP_TO_AX => Some((0, None)),
+        OpCode::MOVE_OP_TO_BX => Some((1, None)),
+        OpCode::MOVE_OP_TO_CX => Some((2, None)),
+        OpCode::MOVE_OP_TO_DX => Some((3, None)),
+        OpCode::MOVE_BX_TO_AX => Some((0, Some(1))),
+        OpCode::MOVE_CX_TO_AX => Some((0, Some(2))),
+        OpCode::MOVE_DX_TO_AX => Some((0, Some(3))),
+        OpCode::MOVE_AX_TO_BX => Some((1, Some(0))),
+        OpCode::MOVE_CX_TO_BX => Some((1, Some(2))),
+        OpCode::MOVE_DX_TO_BX => Some((1, Some(3))),
+        OpCode::MOVE_AX_TO_CX => Some((2, Some(0))),
+        OpCode::MOVE_BX_TO_CX => Some((2, Some(1))),
+        OpCode::MOVE_DX_TO_CX => Some((2, Some(3))),
+        OpCode::MOVE_AX_TO_DX => Some((3, Some(0))),
+        OpCode::MOVE_BX_TO_DX => Some((3, Some(1))),
+        OpCode::MOVE_CX_TO_DX => Some((3, Some(2))),
+        _ => None,
+    }
+}
+
+// general registers written by instructions other than moves
+fn written_registers(opcode: Word) -> &'static [usize] {
+    match opcode {
+        OpCode::POP => &[2],
+        OpCode::DIV => &[0, 3],
+        OpCode::INC           | OpCode::DEC           |
+        OpCode::NEG           | OpCode::ADD           |
+        OpCode::SUB           | OpCode::MUL           |
+        OpCode::FNEG          | OpCode::FADD          |
+        OpCode::FSUB          | OpCode::FMUL          |
+        OpCode::FDIV          | OpCode::AND           |
+        OpCode::OR            | OpCode::XOR           |
+        OpCode::NOT           | OpCode::SHL           |
+        OpCode::SHR           | OpCode::CWTOR         |
+        OpCode::CSWTOR        | OpCode::CRTOW         |
+        OpCode::CRTOSW        | OpCode::DEREF         |
+        OpCode::MALLOC        | OpCode::LOAD_DX_OFF   |
+        OpCode::LOAD_FP_OFF   | OpCode::LOAD_LP_OFF   |
+        OpCode::LOAD_DX_BX    | OpCode::MOVE_SP_TO_AX |
+        OpCode::MOVE_FP_TO_AX | OpCode::MOVE_LP_TO_AX => &[0],
+        _ => &[],
+    }
+}
+
+fn is_string_char(word: Word) -> bool {
+    match word.into_char() {
+        Some('\n' | '\t' | '\r') => true,
+        Some(c) => !c.is_control(),
+        None => false,
+    }
+}
+
+fn starts_string(data: &[Word]) -> bool {
+    data
+        .iter()
+        .take(MIN_STRING_LENGTH)
+        .filter(|word| is_string_char(**word))
+        .count() == MIN_STRING_LENGTH
+}
+
+fn push_escaped(c: char, result: &mut String) {
+    match c {
+        '\n' => result.push_str("\\n"),
+        '\t' => result.push_str("\\t"),
+        '\r' => result.push_str("\\r"),
+        '\\' => result.push_str("\\\\"),
+        '"'  => result.push_str("\\\""),
+        c    => result.push(c),
+    }
+}
@@ -0,0 +1,192 @@
+use std::{fmt, io, time::Duration};
+
+use super::Word;
+
+/// state of registers at the moment of fault
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq)]
+pub struct Registers {
+    pub ip: Word,
+    pub sp: Word,
+    pub fp: Word,
+    pub lp: Word,
+    pub ax: Word,
+    pub bx: Word,
+    pub cx: Word,
+    pub dx: Word,
+}
+
+impl fmt::Display for Registers {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        write!(
+            f,
+            "ip=0x{:x} sp=0x{:x} fp=0x{:x} lp=0x{:x} \
+             ax=0x{:x} bx=0x{:x} cx=0x{:x} dx=0x{:x}",
+            self.ip, self.sp, self.fp, self.lp,
+            self.ax, self.bx, self.cx, self.dx,
+        )
+    }
+}
+
+#[derive(Debug)]
+pub enum VmError {
+    InvalidOpcode {
+        opcode   : Word,
+        registers: Registers,
+    },
+    BadAddress {
+        address  : Word,
+        registers: Registers,
+    },
+    // depth is number of calls, which were not returned
+    StackOverflow {
+        depth    : Word,
+        registers: Registers,
+    },
+    StackUnderflow {
+        depth    : Word,
+        registers: Registers,
+    },
+    DivisionByZero {
+        registers: Registers,
+    },
+    BadSyscall {
+        code     : Word,
+        registers: Registers,
+    },
+    InvalidChar {
+        value    : Word,
+        registers: Registers,
+    },
+    DoubleFree {
+        address  : Word,
+        registers: Registers,
+    },
+    InvalidFree {
+        address  : Word,
+        registers: Registers,
+    },
+    Io {
+        error    : io::Error,
+        registers: Registers,
+    },
+    // limits of sandbox, see `Limits`
+    FuelExhausted {
+        limit    : u64,
+        registers: Registers,
+    },
+    TimeLimitExceeded {
+        limit    : Duration,
+        registers: Registers,
+    },
+    HeapLimitExceeded {
+        limit    : Word,
+        registers: Registers,
+    },
+    OutputLimitExceeded {
+        limit    : u64,
+        registers: Registers,
+    },
+    InputLimitExceeded {
+        limit    : u64,
+        registers: Registers,
+    },
+    // image does not fit in memory of vm
+    ImageTooLarge {
+        required : Word,
+        available: Word,
+    },
+    // size of image with bss in bytes does not fit in word
+    BssTooLarge {
+        words: Word,
+    },
+    // symbol is not defined in loaded image
+    UnknownSymbol {
+        name: String,
+    },
+}
+
+impl VmError {
+    /// registers at the moment of fault, none for errors
+    /// which are not raised by executed program
+    pub fn registers(&self) -> Option<&Registers> {
+        match self {
+            Self::InvalidOpcode       { registers, .. } |
+            Self::BadAddress          { registers, .. } |
+            Self::StackOverflow       { registers, .. } |
+            Self::StackUnderflow      { registers, .. } |
+            Self::DivisionByZero      { registers     } |
+            Self::BadSyscall          { registers, .. } |
+            Self::InvalidChar         { registers, .. } |
+            Self::DoubleFree          { registers, .. } |
+            Self::InvalidFree         { registers, .. } |
+            Self::Io                  { registers, .. } |
+            Self::FuelExhausted       { registers, .. } |
+            Self::TimeLimitExceeded   { registers, .. } |
+            Self::HeapLimitExceeded   { registers, .. } |
+            Self::OutputLimitExceeded { registers, .. } |
+            Self::InputLimitExceeded  { registers, .. } => Some(registers),
+            Self::ImageTooLarge       { .. } |
+            Self::BssTooLarge         { .. } |
+            Self::UnknownSymbol       { .. } => None,
+        }
+    }
+
+    /// address of faulting instruction
+    pub fn ip(&self) -> Option<Word> {
+        self.registers().map(|registers| registers.ip)
+    }
+}
+
+impl fmt::Display for VmError {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            Self::InvalidOpcode { opcode, .. } =>
+                write!(f, "invalid opcode 0x{opcode:x}")?,
+            Self::BadAddress { address, .. } =>
+                write!(f, "bad memory address 0x{address:x}")?,
+            Self::StackOverflow { depth, .. } =>
+                write!(f, "stack overflow at call depth {depth}")?,
+            Self::StackUnderflow { depth, .. } =>
+                write!(f, "stack underflow at call depth {depth}")?,
+            Self::DivisionByZero      { .. } =>
+                write!(f, "division by zero")?,
+            Self::BadSyscall { code, .. } =>
+                write!(f, "unknown syscall 0x{code:x}")?,
+            Self::InvalidChar { value, .. } =>
+                write!(f, "word 0x{value:x} is not a valid char")?,
+            Self::DoubleFree { address, .. } =>
+                write!(f, "double free of block 0x{address:x}")?,
+            Self::InvalidFree { address, .. } =>
+                write!(f, "free of unallocated address 0x{address:x}")?,
+            Self::Io { error, .. } =>
+                write!(f, "i/o error: {error}")?,
+            Self::FuelExhausted { limit, .. } =>
+                write!(f, "instruction limit of {limit} exceeded")?,
+            Self::TimeLimitExceeded { limit, .. } =>
+                write!(f, "time limit of {limit:?} exceeded")?,
+            Self::HeapLimitExceeded { limit, .. } =>
+                write!(f, "heap limit of {limit} bytes exceeded")?,
+            Self::OutputLimitExceeded { limit, .. } =>
+                write!(f, "output limit of {limit} bytes exceeded")?,
+            Self::InputLimitExceeded { limit, .. } =>
+                write!(f, "input limit of {limit} bytes exceeded")?,
+            Self::ImageTooLarge { required, available } =>
+                write!(
+                    f,
+                    "image requires 0x{required:x} bytes of memory, \
+                     but vm has 0x{available:x}"
+                )?,
+            Self::BssTooLarge { words } =>
+                write!(f, "bss of 0x{words:x} words exceeds address space")?,
+            Self::UnknownSymbol { name } =>
+                write!(f, "unknown symbol `{name}`")?,
+        }
+
+        match self.ip() {
+            Some(ip) => write!(f, " at ip 0x{ip:x}"),
+            None => Ok(()),
+        }
+    }
+}
+
+impl std::error::Error for VmError {}
@@ -0,0 +1,207 @@
+use std::collections::{BTreeMap, BTreeSet};
+
+use super::Word;
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum FreeError {
+    DoubleFree,
+    InvalidFree,
+}
+
+/// First-fit allocator of words between end of loaded image and stack.
+/// Bookkeeping is kept outside of vm memory, so program
+/// can not corrupt it.
+#[derive(Debug, Clone, Default)]
+pub struct Heap {
+    // end of used part of heap, stack may grow down to it
+    top      : Word,
+    // address -> length of allocated blocks
+    used     : BTreeMap<Word, Word>,
+    // address -> length of free blocks below top
+    free     : BTreeMap<Word, Word>,
+    // addresses of freed blocks, which were not reused yet
+    released : BTreeSet<Word>,
+    // words of allocated blocks
+    allocated: Word,
+}
+
+impl Heap {
+    pub fn new(start: Word) -> Self {
+        // zero address is returned on failure
+        Self {
+            top: start.max(1),
+            ..Default::default()
+        }
+    }
+
+    /// end of used part of heap
+    pub fn top(&self) -> Word {
+        self.top
+    }
+
+    /// words of blocks, which are allocated now
+    pub fn allocated(&self) -> Word {
+        self.allocated
+    }
+
+    /// allocates block of words below limit,
+    /// None when there is no space
+    pub fn allocate(&mut self, length: Word, limit: Word) -> Option<Word> {
+        let length = length.max(1);
+
+        let fit = self
+            .free
+            .iter()
+            .find(|(_, free_length)| **free_length >= length)
+            .map(|(address, free_length)| (*address, *free_length));
+
+        let address = match fit {
+            Some((address, free_length)) => {
+                self.free.remove(&address);
+                if free_length > length {
+                    self.free.insert(address + length, free_length - length);
+                }
+                address
+            }
+            None => {
+                let end = self.top.checked_add(length)?;
+                if end > limit {
+                    return None;
+                }
+                let address = self.top;
+                self.top = end;
+                address
+            }
+        };
+
+        let reused: Vec<Word> = self
+            .released
+            .range(address..address + length)
+            .copied()
+            .collect();
+        for address in reused {
+            self.released.remove(&address);
+        }
+
+        self.used.insert(address, length);
+        self.allocated += length;
+        Some(address)
+    }
+
+    /// releases block, returns its length
+    pub fn free(&mut self, address: Word) -> Result<Word, FreeError> {
+        let Some(length) = self.used.remove(&address) else {
+            return Err(if self.released.contains(&address) {
+                FreeError::DoubleFree
+            } else {
+                FreeError::InvalidFree
+            });
+        };
+
+        self.released.insert(address);
+        self.allocated -= length;
+
+        let mut start = address;
+        let mut end = address + length;
+
+        let prev = self.free.range(..start).next_back();
+        if let Some((&prev, &prev_length)) = prev {
+            if prev + prev_length == start {
+                self.free.remove(&prev);
+                start = prev;
+            }
+        }
+
+        if let Some(next_length) = self.free.remove(&end) {
+            end += next_length;
+        }
+
+        if end == self.top {
+            self.top = start;
+        } else {
+            self.free.insert(start, end - start);
+        }
+
+        Ok(length)
+    }
+
+    /// bookkeeping as words: top, then count and blocks of used,
+    /// free and released lists
+    pub fn to_words(&self) -> Vec<Word> {
+        let mut words = vec![self.top, self.used.len() as Word];
+        for (address, length) in &self.used {
+            words.extend([*address, *length]);
+        }
+        words.push(self.free.len() as Word);
+        for (address, length) in &self.free {
+            words.extend([*address, *length]);
+        }
+        words.push(self.released.len() as Word);
+        words.extend(self.released.iter().copied());
+        words
+    }
+
+    /// restores heap saved by `to_words` in memory of `size` words
+    pub fn from_words(words: &[Word], size: Word) -> Result<Self, String> {
+        let mut words = words.iter().copied();
+        let mut next = || words
+            .next()
+            .ok_or_else(|| "heap state is truncated".to_string());
+
+        let top = next()?;
+        let mut used = BTreeMap::new();
+        for _ in 0..next()? {
+            used.insert(next()?, next()?);
+        }
+        let mut free = BTreeMap::new();
+        for _ in 0..next()? {
+            free.insert(next()?, next()?);
+        }
+        let mut released = BTreeSet::new();
+        for _ in 0..next()? {
+            released.insert(next()?);
+        }
+        if words.next().is_some() {
+            return Err("unexpected words after heap state".to_string());
+        }
+
+        if top == 0 || top > size {
+            return Err(format!("heap top 0x{top:x} is outside of memory"));
+        }
+
+        // blocks must not overlap, so allocated ones are never reused
+        let mut blocks: Vec<(Word, Word)> = used
+            .iter()
+            .chain(&free)
+            .map(|(address, length)| (*address, *length))
+            .collect();
+        blocks.sort_unstable();
+        let mut previous_end = 0;
+        for (address, length) in blocks {
+            let end = address.checked_add(length);
+            // zero address is returned on failure
+            if address == 0 ||
+               length == 0 ||
+               end.is_none_or(|end| end > top)
+            {
+                return Err(format!(
+                    "heap block 0x{address:x} is outside of heap"
+                ));
+            }
+            if address < previous_end {
+                return Err(format!(
+                    "heap block 0x{address:x} overlaps other block"
+                ));
+            }
+            previous_end = address + length;
+        }
+
+        Ok(Self {
+            top,
+            allocated: used.values().sum(),
+            used,
+            free,
+            released,
+        })
+    }
+}
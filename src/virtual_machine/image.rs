@@ -1,20 +1,133 @@
-use super::{byte_casts::{from_bytes, GetBytes}, Memory, OpCode, Word};
-use std::{fs::{File, OpenOptions}, io::{Read, Write}};
-use core::slice::Iter;
+use super::{op_codes::ISA_VERSION, Memory, OpCode, Real, SWord, Word};
+use std::{collections::BTreeMap, fs};
+
+// file layout, all numbers are little-endian:
+//
+//   header:
+//     magic              [u8; 4]
+//     format version     u16
+//     isa version        u16
+//     flags              u32, reserved
+//     memory requirement u64, bytes, 0 - no requirement
+//     entry point        u64
+//     sections count     u32
+//   section table, for every section:
+//     kind               u32
+//     flags              u32
+//     address            u64, words
+//     length             u64, words
+//   payloads of code, data and symbols sections in table order,
+//   bss has no payload
+//
+// code and data sections are placed one after another from address 0
+// in table order, bss sections follow them. Images assembled from
+// source have sections of `.code` and `.data` directives, images
+// built without `Image::begin_section` have data before entry point
+// and code after it, so procedures placed before entry point of
+// such image are in data section and are not predecoded by vm
+//
+// symbols payload, length of section is in words of it:
+//   symbols count        u64
+//   for every symbol:
+//     address            u64
+//     kind               u32
+//     name length        u32, bytes
+//     name               utf-8, padded with zeros to whole words
+//
+// version 1 has no symbols section
+
+pub const MAGIC: [u8; 4] = *b"KNDR";
+pub const FORMAT_VERSION: u16 = 2;
+// oldest version which is still loaded
+pub const MIN_FORMAT_VERSION: u16 = 1;
+
+const HEADER_SIZE: usize = 4 + 2 + 2 + 4 + 8 + 8 + 4;
+const SECTION_ENTRY_SIZE: usize = 4 + 4 + 8 + 8;
+const WORD_SIZE: usize = size_of::<Word>();
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum SectionKind {
+    Code = 1,
+    Data = 2,
+    Bss  = 3,
+    // not loaded in memory
+    Symbols = 4,
+}
+
+pub struct SectionFlags;
+
+impl SectionFlags {
+    pub const READ   : u32 = 0b001;
+    pub const WRITE  : u32 = 0b010;
+    pub const EXECUTE: u32 = 0b100;
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct Section {
+    pub kind   : SectionKind,
+    pub flags  : u32,
+    pub address: Word,
+    pub length : Word,
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum SymbolKind {
+    Function = 1,
+    Data     = 2,
+    Label    = 3,
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct Symbol {
+    pub kind   : SymbolKind,
+    pub address: Word,
+}
+
+/// address in image, which may be not known yet,
+/// operands referring to it are patched when it is placed
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Hash)]
+pub struct Label(usize);
 
 #[derive(Debug)]
 pub struct Image {
-    emit_address     : Word,
-    entry_point      : Word,
-    image            : Memory,
+    emit_address      : Word,
+    entry_point       : Word,
+    image             : Memory,
+    // zeroed words placed right after image
+    bss_size          : Word,
+    // bytes of vm memory required to run image
+    memory_requirement: Word,
+    // kind of words from address till the next start,
+    // empty when sections were never begun
+    section_starts    : Vec<(Word, SectionKind)>,
+    // name -> symbol
+    symbols           : BTreeMap<String, Symbol>,
+    // addresses of labels, None till placed
+    labels            : Vec<Option<Word>>,
+    label_names       : BTreeMap<String, Label>,
+    // words waiting for labels to be placed
+    fixups            : Vec<(Word, Label)>,
+}
+
+impl Default for Image {
+    fn default() -> Self {
+        Self::new()
+    }
 }
 
 impl Image {
     pub fn new() -> Self {
         Self {
-            emit_address : 0,
-            entry_point  : 0,
-            image        : Vec::new(),
+            emit_address      : 0,
+            entry_point       : 0,
+            image             : Vec::new(),
+            bss_size          : 0,
+            memory_requirement: 0,
+            section_starts    : Vec::new(),
+            symbols           : BTreeMap::new(),
+            labels            : Vec::new(),
+            label_names       : BTreeMap::new(),
+            fixups            : Vec::new(),
         }
     }
 
@@ -22,6 +135,13 @@ impl Image {
         self.image.clear();
         self.emit_address = 0;
         self.entry_point = 0;
+        self.bss_size = 0;
+        self.memory_requirement = 0;
+        self.section_starts.clear();
+        self.symbols.clear();
+        self.labels.clear();
+        self.label_names.clear();
+        self.fixups.clear();
     }
 
     pub fn emit_opcode(&mut self, opcode: Word) -> Word {
@@ -30,7 +150,7 @@ impl Image {
 
         self.emit(opcode);
 
-        return start_address;
+        start_address
     }
 
     pub fn emit_opcode_with_operand(
@@ -44,10 +164,27 @@ impl Image {
         self.emit(opcode);
         self.emit(operand);
 
-        return start_address;
+        start_address
+    }
+
+    /// operand is address of label, which may be placed later
+    pub fn emit_opcode_with_label(
+        &mut self,
+        opcode: Word,
+        label: Label,
+    ) -> Word {
+        let start_address = self.emit_opcode_with_operand(opcode, 0);
+        self.write_label(start_address + 1, label);
+        start_address
+    }
+
+    /// emits address of label as data word
+    pub fn emit_label(&mut self, label: Label) -> Word {
+        let address = self.emit_opcode(0);
+        self.write_label(address, label);
+        address
     }
 
-    #[allow(dead_code)]
     pub fn emit_opcode_with_two_operands(
         &mut self, 
         opcode: Word,
@@ -61,7 +198,7 @@ impl Image {
         self.emit(operand1);
         self.emit(operand2);
 
-        return start_address;
+        start_address
     }
 
     pub fn emit_str(&mut self, s: &str) -> Word {
@@ -88,6 +225,11 @@ impl Image {
         start_address
     }
 
+    /// emits word or string
+    pub fn emit_data(&mut self, data: impl ImageData) -> Word {
+        data.emit_into(self)
+    }
+
     pub fn emit_from_other(&mut self, other: &Self) -> Word {
         let word_counter = other.image.len();
         self.prepare_space(word_counter as Word);
@@ -103,7 +245,7 @@ impl Image {
             .clone_from_slice(&other.image);
 
         self.emit_address += word_counter as Word;
-        return start_address;
+        start_address
     }
 
     pub fn write_word(&mut self, address: Word, value: Word) {
@@ -131,6 +273,80 @@ impl Image {
         self.image[address as usize]
     }
 
+    pub fn new_label(&mut self) -> Label {
+        self.labels.push(None);
+        Label(self.labels.len() - 1)
+    }
+
+    /// label with name, created on first use
+    pub fn named_label(&mut self, name: &str) -> Label {
+        if let Some(&label) = self.label_names.get(name) {
+            return label;
+        }
+        let label = self.new_label();
+        self.label_names.insert(name.to_string(), label);
+        label
+    }
+
+    /// binds label to emit address and patches words referring to it
+    pub fn place_label(&mut self, label: Label) -> Result<(), String> {
+        if let Some(address) = self.labels[label.0] {
+            return Err(format!(
+                "label {} is already placed at 0x{address:x}",
+                self.label_name(label)
+            ));
+        }
+
+        let address = self.emit_address;
+        self.labels[label.0] = Some(address);
+
+        let fixups = std::mem::take(&mut self.fixups);
+        for (word, target) in fixups {
+            if target == label {
+                self.write_word(word, address);
+            } else {
+                self.fixups.push((word, target));
+            }
+        }
+
+        Ok(())
+    }
+
+    pub fn get_label_address(&self, label: Label) -> Option<Word> {
+        self.labels[label.0]
+    }
+
+    /// writes address of label in word, now or when label is placed
+    pub fn write_label(&mut self, address: Word, label: Label) {
+        match self.labels[label.0] {
+            Some(target) => self.write_word(address, target),
+            None => {
+                self.write_word(address, 0);
+                self.fixups.push((address, label));
+            }
+        }
+    }
+
+    /// checks, that all used labels are placed
+    pub fn finalize(&self) -> Result<(), String> {
+        match self.fixups.first() {
+            None => Ok(()),
+            Some((address, label)) => Err(format!(
+                "label {} used at 0x{address:x} is never placed",
+                self.label_name(*label)
+            )),
+        }
+    }
+
+    fn label_name(&self, label: Label) -> String {
+        self
+            .label_names
+            .iter()
+            .find(|(_, named)| **named == label)
+            .map(|(name, _)| format!("`{name}`"))
+            .unwrap_or_else(|| format!("#{}", label.0))
+    }
+
     pub fn set_entry_point_here(&mut self) {
         self.entry_point = self.emit_address;
     }
@@ -138,10 +354,10 @@ impl Image {
     pub fn set_entry_point(
         &mut self, 
         entry_point: Word
-    ) -> Result<(), ()> 
+    ) -> Result<(), String> 
     {
         if entry_point >= self.image.len() as Word {
-            Err(())
+            Err(format!("entry point 0x{entry_point:x} is outside of image"))
         } else {
             self.entry_point = entry_point;
             Ok(())
@@ -156,43 +372,331 @@ impl Image {
         self.entry_point
     }
 
-    pub fn save_to_file(&self, path: &str) -> Result<(), &str> {
-        let mut file: File;
-        if let Ok(f) = 
-            OpenOptions::new().write(true).open(path) 
-        {
-            file = f;
-            file.write(self.entry_point.get_bytes()).unwrap();
-            file.write(self.get_image().get_bytes()).unwrap();
-            return Ok(());
-        }
-
-        if let Ok(f) = File::create(path)  {
-            file = f;
-            file.write(self.entry_point.get_bytes()).unwrap();
-            file.write(self.get_image().get_bytes()).unwrap();
-            return Ok(());  
-        }
-        Err("Opening and creating file were failed")
-    }
-
-    pub fn load_from_file(&mut self, path: &str) -> Result<(), &str> {
-        let mut file: File;
-        if let Ok(f) = File::open(path) {
-            file = f;
-            let mut buf: Vec<u8> = Vec::new();
-            file.read_to_end(&mut buf).unwrap();
-            if let Some(words) = from_bytes::<Word>(&buf) {
-                self.entry_point = words[0];
-                self.write_data(0, &words[1..]);
-                self.emit_address = words.len() as Word - 1;
-                Ok(())
-            } else {
-                Err("Failed to read file")
-            }
+    pub fn set_bss_size(&mut self, words_count: Word) {
+        self.bss_size = words_count;
+    }
+
+    pub fn get_bss_size(&self) -> Word {
+        self.bss_size
+    }
+
+    pub fn set_memory_requirement(&mut self, bytes: Word) {
+        self.memory_requirement = bytes;
+    }
+
+    pub fn get_memory_requirement(&self) -> Word {
+        self.memory_requirement
+    }
+
+    /// words emitted from here belong to section of kind,
+    /// which is code or data
+    pub fn begin_section(&mut self, kind: SectionKind) {
+        assert!(
+            matches!(kind, SectionKind::Code | SectionKind::Data),
+            "{kind:?} section has no words in image"
+        );
+        let address = self.emit_address;
+        match self.section_starts.last_mut() {
+            Some((start, last)) if *start == address => *last = kind,
+            _ => self.section_starts.push((address, kind)),
+        }
+    }
+
+    /// names address, which is kept in saved image
+    pub fn define_symbol(
+        &mut self,
+        name: &str,
+        address: Word,
+        kind: SymbolKind,
+    ) -> Result<(), String> {
+        if name.is_empty() {
+            return Err("symbol name is empty".to_string());
+        }
+        if self.symbols.contains_key(name) {
+            return Err(format!("symbol `{name}` is already defined"));
+        }
+        self.symbols.insert(name.to_string(), Symbol { kind, address });
+        Ok(())
+    }
+
+    pub fn get_symbol(&self, name: &str) -> Option<Symbol> {
+        self.symbols.get(name).copied()
+    }
+
+    pub fn get_symbols(&self) -> &BTreeMap<String, Symbol> {
+        &self.symbols
+    }
+
+    /// code and data sections begun by `begin_section`, words before
+    /// the first one are code. Without them all before entry point
+    /// is data and the rest is code
+    pub fn sections(&self) -> Vec<Section> {
+        let length = self.image.len() as Word;
+        let starts = if self.section_starts.is_empty() {
+            let entry_point = self.entry_point.min(length);
+            vec![(0, SectionKind::Data), (entry_point, SectionKind::Code)]
         } else {
-            Err("Opening file was failed")
+            let mut starts = vec![(0, SectionKind::Code)];
+            starts.extend_from_slice(&self.section_starts);
+            starts
+        };
+
+        let mut sections: Vec<Section> = Vec::new();
+        for (idx, &(address, kind)) in starts.iter().enumerate() {
+            let end = starts
+                .get(idx + 1)
+                .map_or(length, |(next, _)| *next)
+                .min(length);
+            if address >= end {
+                continue;
+            }
+            match sections.last_mut() {
+                Some(last) if last.kind == kind =>
+                    last.length = end - last.address,
+                _ => sections.push(Section {
+                    kind,
+                    flags  : match kind {
+                        SectionKind::Code =>
+                            SectionFlags::READ | SectionFlags::EXECUTE,
+                        _ => SectionFlags::READ | SectionFlags::WRITE,
+                    },
+                    address,
+                    length : end - address,
+                }),
+            }
+        }
+
+        if self.bss_size > 0 {
+            sections.push(Section {
+                kind   : SectionKind::Bss,
+                flags  : SectionFlags::READ | SectionFlags::WRITE,
+                address: length,
+                length : self.bss_size,
+            });
+        }
+
+        sections
+    }
+
+    pub fn to_bytes(&self) -> Vec<u8> {
+        let mut sections = self.sections();
+        let symbols = symbols_payload(&self.symbols);
+        if !self.symbols.is_empty() {
+            sections.push(Section {
+                kind   : SectionKind::Symbols,
+                flags  : 0,
+                address: 0,
+                length : (symbols.len() / WORD_SIZE) as Word,
+            });
+        }
+        let mut bytes = Vec::with_capacity(
+            HEADER_SIZE +
+            sections.len() * SECTION_ENTRY_SIZE +
+            self.image.len() * WORD_SIZE
+        );
+
+        bytes.extend_from_slice(&MAGIC);
+        bytes.extend_from_slice(&FORMAT_VERSION.to_le_bytes());
+        bytes.extend_from_slice(&ISA_VERSION.to_le_bytes());
+        bytes.extend_from_slice(&0u32.to_le_bytes());
+        bytes.extend_from_slice(&self.memory_requirement.to_le_bytes());
+        bytes.extend_from_slice(&self.entry_point.to_le_bytes());
+        bytes.extend_from_slice(&(sections.len() as u32).to_le_bytes());
+
+        for section in &sections {
+            bytes.extend_from_slice(&(section.kind as u32).to_le_bytes());
+            bytes.extend_from_slice(&section.flags.to_le_bytes());
+            bytes.extend_from_slice(&section.address.to_le_bytes());
+            bytes.extend_from_slice(&section.length.to_le_bytes());
+        }
+
+        for section in &sections {
+            match section.kind {
+                SectionKind::Bss => continue,
+                SectionKind::Symbols => {
+                    bytes.extend_from_slice(&symbols);
+                    continue;
+                }
+                _ => (),
+            }
+            let start = section.address as usize;
+            let end = start + section.length as usize;
+            for word in &self.image[start..end] {
+                bytes.extend_from_slice(&word.to_le_bytes());
+            }
+        }
+
+        bytes
+    }
+
+    pub(super) fn parse_symbols(
+        &mut self,
+        payload: &[u8],
+    ) -> Result<(), String> {
+        let invalid =
+            |reason: String| format!("invalid symbols section: {reason}");
+        let mut reader = ByteReader { bytes: payload, position: 0 };
+
+        let count = reader.u64().map_err(invalid)?;
+        for _ in 0..count {
+            let address = reader.u64().map_err(invalid)?;
+            let kind = match reader.u32().map_err(invalid)? {
+                1 => SymbolKind::Function,
+                2 => SymbolKind::Data,
+                3 => SymbolKind::Label,
+                kind => return Err(invalid(
+                    format!("unknown symbol kind {kind}")
+                )),
+            };
+            let length = reader.u32().map_err(invalid)? as usize;
+            let padded = length.next_multiple_of(WORD_SIZE);
+            let name = reader.take(padded).map_err(invalid)?;
+            let name = std::str::from_utf8(&name[..length])
+                .map_err(|_| invalid("name is not valid utf-8".to_string()))?;
+            self.define_symbol(name, address, kind).map_err(invalid)?;
+        }
+
+        if reader.position != payload.len() {
+            return Err(invalid("unexpected trailing bytes".to_string()));
+        }
+        Ok(())
+    }
+
+    pub fn from_bytes(bytes: &[u8]) -> Result<Self, String> {
+        let mut reader = ByteReader { bytes, position: 0 };
+
+        if bytes.len() < HEADER_SIZE {
+            return Err(format!(
+                "file is too short for kondra image header: {} bytes",
+                bytes.len()
+            ));
+        }
+
+        if reader.take(MAGIC.len())? != MAGIC {
+            return Err("not a kondra image: bad magic bytes".to_string());
+        }
+
+        let format_version = reader.u16()?;
+        if !(MIN_FORMAT_VERSION..=FORMAT_VERSION).contains(&format_version) {
+            return Err(format!(
+                "unsupported image format version {format_version}, \
+                 expected {MIN_FORMAT_VERSION} to {FORMAT_VERSION}"
+            ));
+        }
+
+        let isa_version = reader.u16()?;
+        if isa_version > ISA_VERSION {
+            return Err(format!(
+                "image requires ISA version {isa_version}, \
+                 supported up to {ISA_VERSION}"
+            ));
+        }
+
+        let _flags = reader.u32()?;
+        let memory_requirement = reader.u64()?;
+        let entry_point = reader.u64()?;
+        let sections_count = reader.u32()?;
+
+        let mut sections = Vec::new();
+        for idx in 0..sections_count {
+            let kind = match reader.u32()? {
+                1 => SectionKind::Code,
+                2 => SectionKind::Data,
+                3 => SectionKind::Bss,
+                4 if format_version >= 2 => SectionKind::Symbols,
+                kind => return Err(format!(
+                    "section {idx} has unknown kind {kind}"
+                )),
+            };
+            let flags = reader.u32()?;
+            let address = reader.u64()?;
+            let length = reader.u64()?;
+            if address.checked_add(length).is_none() {
+                return Err(format!(
+                    "section {idx} does not fit in address space"
+                ));
+            }
+            sections.push(Section { kind, flags, address, length });
+        }
+
+        let mut image = Self::new();
+        image.memory_requirement = memory_requirement;
+
+        for (idx, section) in sections.iter().enumerate() {
+            if section.kind == SectionKind::Bss {
+                continue;
+            }
+            let payload = (section.length as usize)
+                .checked_mul(WORD_SIZE)
+                .and_then(|size| reader.take(size).ok())
+                .ok_or_else(|| format!(
+                    "truncated payload of section {idx} ({:?})",
+                    section.kind
+                ))?;
+            if section.kind == SectionKind::Symbols {
+                image.parse_symbols(payload)?;
+                continue;
+            }
+            // so image is never larger than file
+            if section.address != image.image.len() as Word {
+                return Err(format!(
+                    "section {idx} ({:?}) at 0x{:x} does not follow \
+                     previous sections",
+                    section.kind, section.address
+                ));
+            }
+            image.emit_address = image.image.len() as Word;
+            image.begin_section(section.kind);
+            image.image.extend(
+                payload
+                    .chunks_exact(WORD_SIZE)
+                    .map(|chunk| Word::from_le_bytes(chunk.try_into().unwrap()))
+            );
+        }
+
+        let end = image.image.len() as Word;
+        for section in sections.iter().filter(|s| s.kind == SectionKind::Bss) {
+            let bss_end = end.checked_add(image.bss_size);
+            if bss_end != Some(section.address) {
+                return Err(
+                    "bss section must follow code and data".to_string()
+                );
+            }
+            image.bss_size = image
+                .bss_size
+                .checked_add(section.length)
+                .filter(|size| end.checked_add(*size).is_some())
+                .ok_or("bss section does not fit in address space")?;
+        }
+
+        let remaining = bytes.len() - reader.position;
+        if remaining > 0 {
+            return Err(format!("unexpected {remaining} trailing bytes"));
+        }
+
+        // image without `.entry` starts at 0, which may be data
+        if end > 0 && entry_point >= end {
+            return Err(format!(
+                "entry point 0x{entry_point:x} is outside of image"
+            ));
         }
+
+        image.entry_point = entry_point;
+        image.emit_address = end;
+
+        Ok(image)
+    }
+
+    pub fn save_to_file(&self, path: &str) -> Result<(), &str> {
+        fs::write(path, self.to_bytes())
+            .map_err(|_| "Opening and creating file were failed")
+    }
+
+    pub fn load_from_file(&mut self, path: &str) -> Result<(), String> {
+        let bytes = fs::read(path)
+            .map_err(|err| format!("Opening file was failed: {err}"))?;
+        *self = Self::from_bytes(&bytes)?;
+        Ok(())
     }
 
     fn prepare_space(&mut self, words_count: Word) {
@@ -219,104 +723,25 @@ impl Image {
         self.emit_address += 1;
     }
 
-    #[allow(dead_code, unused_variables)]
     pub fn get_mnemonics(&self) -> String {
         self.get_mnemonics_from(self.entry_point)
     }
 
-    #[allow(dead_code, unused_variables)]
     pub fn get_mnemonics_from(&self, address: Word) -> String {
         let mut result = String::new();
         let mut idx = address as usize;
 
         while idx < self.image.len() {
             result.push_str(format!("0x{idx:0>16x}: ").as_str());
-            match self.image[idx] {
-                OpCode::ADD => result.push_str("add"),
-                OpCode::AND => result.push_str("and"),
-                OpCode::CALL => result.push_str("call"),
-                OpCode::CRTOSW => result.push_str("crtosw"),
-                OpCode::CRTOW => result.push_str("crtow"),
-                OpCode::CSWTOR => result.push_str("cswtor"),
-                OpCode::CWTOR => result.push_str("cwtor"),
-                OpCode::DEC => result.push_str("dec"),
-                OpCode::DEREF => result.push_str("deref"),
-                OpCode::DIV => result.push_str("div"),
-                OpCode::FADD => result.push_str("fadd"),
-                OpCode::FDIV => result.push_str("fdiv"),
-                OpCode::FJG => result.push_str("fjg"),
-                OpCode::FJGE => result.push_str("fjge"),
-                OpCode::FJL => result.push_str("fjl"),
-                OpCode::FJLE => result.push_str("fjle"),
-                OpCode::FMUL => result.push_str("fmul"),
-                OpCode::FNEG => result.push_str("fneg"),
-                OpCode::FSUB => result.push_str("fsub"),
-                OpCode::INC => result.push_str("inc"),
-                OpCode::JA => result.push_str("ja"),
-                OpCode::JAE => result.push_str("jae"),
-                OpCode::JB => result.push_str("jb"),
-                OpCode::JBE => result.push_str("jbe"),
-                OpCode::JE => result.push_str("je"),
-                OpCode::JG => result.push_str("jg"),
-                OpCode::JGE => result.push_str("jge"),
-                OpCode::JL => result.push_str("jl"),
-                OpCode::JLE => result.push_str("jle"),
-                OpCode::JMP => result.push_str("jmp"),
-                OpCode::JNE => result.push_str("jne"),
-                OpCode::MOVE_AX_TO_BX => result.push_str("mov bx, ax"),
-                OpCode::MOVE_AX_TO_CX => result.push_str("mov cx, ax"),
-                OpCode::MOVE_AX_TO_DX => result.push_str("mov dx, ax"),
-                OpCode::MOVE_BX_TO_AX => result.push_str("mov ax, bx"),
-                OpCode::MOVE_BX_TO_CX => result.push_str("mov cx, bx"),
-                OpCode::MOVE_BX_TO_DX => result.push_str("mov dx, bx"),
-                OpCode::MOVE_CX_TO_AX => result.push_str("mov ax, cx"),
-                OpCode::MOVE_CX_TO_BX => result.push_str("mov bx, cx"),
-                OpCode::MOVE_CX_TO_DX => result.push_str("mov dx, cx"),
-                OpCode::MOVE_DX_TO_AX => result.push_str("mov ax, dx"),
-                OpCode::MOVE_DX_TO_BX => result.push_str("mov bx, dx"),
-                OpCode::MOVE_DX_TO_CX => result.push_str("mov cx, dx"),
-                OpCode::MOVE_OP_TO_AX => {
-                    result.push_str("mov ax, ");
-                    idx += 1;
-                    result.push_str(
-                        format!("0x{:x}", self.image[idx]).as_str()
-                    )
-                },
-                OpCode::MOVE_OP_TO_BX => {
-                    result.push_str("mov bx, ");
-                    idx += 1;
-                    result.push_str(
-                        format!("0x{:x}", self.image[idx]).as_str()
-                    )
-                },
-                OpCode::MOVE_OP_TO_CX => {
-                    result.push_str("mov cx, ");
-                    idx += 1;
-                    result.push_str(
-                        format!("0x{:x}", self.image[idx]).as_str()
-                    )
-                },
-                OpCode::MOVE_OP_TO_DX => {
-                    result.push_str("mov dx, ");
-                    idx += 1;
-                    result.push_str(
-                        format!("0x{:x}", self.image[idx]).as_str()
-                    )
-                },
-                OpCode::MUL => result.push_str("mul"),
-                OpCode::NEG => result.push_str("neg"),
-                OpCode::NOT => result.push_str("not"),
-                OpCode::OR => result.push_str("or"),
-                OpCode::POP => result.push_str("pop"),
-                OpCode::PUSH => result.push_str("push"),
-                OpCode::RET => result.push_str("ret"),
-                OpCode::SHL => result.push_str("shl"),
-                OpCode::SHR => result.push_str("shr"),
-                OpCode::SUB => result.push_str("sub"),
-                OpCode::SYSCALL => result.push_str("syscall"),
-                OpCode::XOR => result.push_str("xor"),
-                0 => result.push('0'),
-                _ => panic!("Unknown opcode")
+            match format_instruction(&self.image, idx) {
+                Some((text, length)) => {
+                    result.push_str(text.as_str());
+                    idx += length - 1;
+                }
+                None if self.image[idx] == 0 => result.push('0'),
+                None => result.push_str(
+                    format!("0x{:x}", self.image[idx]).as_str()
+                ),
             }
             result.push('\n');
             idx += 1;
@@ -327,17 +752,427 @@ impl Image {
 
 }
 
+/// value stored in one word, reals are stored by bits
+pub trait IntoWord {
+    fn into_word(self) -> Word;
+}
+
+macro_rules! impl_into_word {
+    ( $( $T:ty ),* ) => {
+        $(
+            impl IntoWord for $T {
+                fn into_word(self) -> Word {
+                    self as Word
+                }
+            }
+        )*
+    };
+}
+
+impl_into_word!(Word, SWord, u32, i32, u8, usize, char);
+
+impl IntoWord for Real {
+    fn into_word(self) -> Word {
+        self.to_bits()
+    }
+}
+
+/// data emitted by `Image::emit_data`
+pub trait ImageData {
+    fn emit_into(self, image: &mut Image) -> Word;
+}
+
+impl<T: IntoWord> ImageData for T {
+    fn emit_into(self, image: &mut Image) -> Word {
+        image.emit_opcode(self.into_word())
+    }
+}
+
+impl ImageData for &str {
+    fn emit_into(self, image: &mut Image) -> Word {
+        image.emit_str(self)
+    }
+}
+
+/// text of instruction at address and number of words it takes
+pub fn format_instruction(
+    words: &[Word], 
+    address: usize
+) -> Option<(String, usize)> {
+    let opcode = *words.get(address)?;
+    let text = OpCode::mnemonic(opcode)?;
+    let length = OpCode::length(opcode)? as usize;
+    if address + length > words.len() {
+        return None;
+    }
+
+    let mut result = String::new();
+    let mut operands = words[address + 1..address + length].iter();
+    for c in text.chars() {
+        match c {
+            '#' => result.push_str(
+                format!("0x{:x}", operands.next()?).as_str()
+            ),
+            '~' => {
+                let offset = *operands.next()? as SWord;
+                let sign = if offset < 0 { '-' } else { '+' };
+                result.push_str(
+                    format!("{sign}0x{:x}", offset.unsigned_abs()).as_str()
+                )
+            }
+            c => result.push(c),
+        }
+    }
+
+    Some((result, length))
+}
+
+// also used by snapshots
+pub(super) fn symbols_payload(
+    symbols: &BTreeMap<String, Symbol>,
+) -> Vec<u8> {
+    let mut bytes = Vec::new();
+    bytes.extend_from_slice(&(symbols.len() as u64).to_le_bytes());
+    for (name, symbol) in symbols {
+        bytes.extend_from_slice(&symbol.address.to_le_bytes());
+        bytes.extend_from_slice(&(symbol.kind as u32).to_le_bytes());
+        bytes.extend_from_slice(&(name.len() as u32).to_le_bytes());
+        bytes.extend_from_slice(name.as_bytes());
+        bytes.resize(bytes.len().next_multiple_of(WORD_SIZE), 0);
+    }
+    bytes
+}
+
+pub(super) struct ByteReader<'a> {
+    pub bytes   : &'a [u8],
+    pub position: usize,
+}
+
+impl<'a> ByteReader<'a> {
+    pub fn take(&mut self, count: usize) -> Result<&'a [u8], String> {
+        let end = self.position.saturating_add(count);
+        if end > self.bytes.len() {
+            return Err(format!(
+                "unexpected end of file at byte {}",
+                self.bytes.len()
+            ));
+        }
+        let result = &self.bytes[self.position..end];
+        self.position = end;
+        Ok(result)
+    }
+
+    pub fn u16(&mut self) -> Result<u16, String> {
+        Ok(u16::from_le_bytes(self.take(2)?.try_into().unwrap()))
+    }
+
+    pub fn u32(&mut self) -> Result<u32, String> {
+        Ok(u32::from_le_bytes(self.take(4)?.try_into().unwrap()))
+    }
+
+    pub fn u64(&mut self) -> Result<u64, String> {
+        Ok(u64::from_le_bytes(self.take(8)?.try_into().unwrap()))
+    }
+}
+
+/// builds image from instructions in assembler syntax:
+///
+/// ```ignore
+/// let image = image! {
+///     data [hello: "Hello World!\n", 0];
+///     entry:
+///     main:
+///         mov dx, @hello;
+///         mov cx, 13;
+///         load [dx+1];
+///         mov ax, (2 * ARG);
+///         syscall;
+/// };
+/// ```
+///
+/// - `entry:` - entry point is here
+/// - `name:` - label, `@name` - its address
+/// - `data [..]` - comma separated strings, words, `@labels`
+///   and labels of items, placed in data section
+/// - operands are expressions, which are converted by `IntoWord`,
+///   unsuffixed integers are `i32`, so big ones need `u64` suffix
+///
+/// labels are created by `Image::named_label`. Labels placed twice
+/// or never placed panic with their names, unknown mnemonics
+/// are compile errors
 #[macro_export]
 macro_rules! image {
-    ( $( $opcode:expr ) * ) => {
-        {
-            let mut i = Image::new();
+    ( #[code] $i:ident ) => {};
+
+    ( #[code] $i:ident ; $( $rest:tt )* ) => {
+        $crate::image!( #[code] $i $( $rest )* );
+    };
 
-            $(
-                i.emit_opcode($opcode as u64);
-            )*
+    ( #[code] $i:ident entry : $( $rest:tt )* ) => {
+        $i.set_entry_point_here();
+        $crate::image!( #[code] $i $( $rest )* );
+    };
+
+    ( #[code] $i:ident $name:ident : $( $rest:tt )* ) => {
+        $crate::image!( #[place] $i $name );
+        $crate::image!( #[code] $i $( $rest )* );
+    };
+
+    ( #[code] $i:ident data [ $( $items:tt )* ] $( $rest:tt )* ) => {
+        $i.begin_section($crate::image::SectionKind::Data);
+        $crate::image!( #[data] $i $( $items )* );
+        $i.begin_section($crate::image::SectionKind::Code);
+        $crate::image!( #[code] $i $( $rest )* );
+    };
+
+    // register to register moves
+    ( #[code] $i:ident mov $d:ident , ax ; $( $rest:tt )* ) => {
+        $i.emit_opcode($crate::image!( #[mov] $d ax ));
+        $crate::image!( #[code] $i $( $rest )* );
+    };
 
+    ( #[code] $i:ident mov $d:ident , bx ; $( $rest:tt )* ) => {
+        $i.emit_opcode($crate::image!( #[mov] $d bx ));
+        $crate::image!( #[code] $i $( $rest )* );
+    };
+
+    ( #[code] $i:ident mov $d:ident , cx ; $( $rest:tt )* ) => {
+        $i.emit_opcode($crate::image!( #[mov] $d cx ));
+        $crate::image!( #[code] $i $( $rest )* );
+    };
+
+    ( #[code] $i:ident mov $d:ident , dx ; $( $rest:tt )* ) => {
+        $i.emit_opcode($crate::image!( #[mov] $d dx ));
+        $crate::image!( #[code] $i $( $rest )* );
+    };
+
+    ( #[code] $i:ident mov $d:ident , sp ; $( $rest:tt )* ) => {
+        $i.emit_opcode($crate::image!( #[mov] $d sp ));
+        $crate::image!( #[code] $i $( $rest )* );
+    };
+
+    ( #[code] $i:ident mov $d:ident , fp ; $( $rest:tt )* ) => {
+        $i.emit_opcode($crate::image!( #[mov] $d fp ));
+        $crate::image!( #[code] $i $( $rest )* );
+    };
+
+    ( #[code] $i:ident mov $d:ident , lp ; $( $rest:tt )* ) => {
+        $i.emit_opcode($crate::image!( #[mov] $d lp ));
+        $crate::image!( #[code] $i $( $rest )* );
+    };
+
+    // moves of operand go after register moves,
+    // as failed match of expression is not retried with other rules
+    ( #[code] $i:ident mov $d:ident , @ $l:ident ; $( $rest:tt )* ) => {
+        let label = $i.named_label(stringify!($l));
+        $i.emit_opcode_with_label($crate::image!( #[mov] $d # ), label);
+        $crate::image!( #[code] $i $( $rest )* );
+    };
+
+    ( #[code] $i:ident mov $d:ident , $v:expr ; $( $rest:tt )* ) => {
+        $crate::image!( #[operand] $i $crate::image!( #[mov] $d # ), $v );
+        $crate::image!( #[code] $i $( $rest )* );
+    };
+
+    ( #[code] $i:ident enter $v:expr ; $( $rest:tt )* ) => {
+        $crate::image!( #[operand] $i $crate::OpCode::ENTER, $v );
+        $crate::image!( #[code] $i $( $rest )* );
+    };
+
+    ( #[code] $i:ident load [ $( $a:tt )* ] ; $( $rest:tt )* ) => {
+        $crate::image!( #[load] $i $( $a )* );
+        $crate::image!( #[code] $i $( $rest )* );
+    };
+
+    ( #[code] $i:ident store [ $( $a:tt )* ] ; $( $rest:tt )* ) => {
+        $crate::image!( #[store] $i $( $a )* );
+        $crate::image!( #[code] $i $( $rest )* );
+    };
+
+    ( #[code] $i:ident $m:ident ; $( $rest:tt )* ) => {
+        $i.emit_opcode($crate::image!( #[opcode] $m ));
+        $crate::image!( #[code] $i $( $rest )* );
+    };
+
+    ( #[code] $i:ident $m:tt $( $rest:tt )* ) => {
+        compile_error!(concat!(
+            "invalid instruction `", stringify!($m), "`, \
+             instructions are terminated by `;`"
+        ));
+    };
+
+    ( #[operand] $i:ident $opcode:expr, $v:expr ) => {
+        $i.emit_opcode_with_operand(
+            $opcode,
+            $crate::image::IntoWord::into_word($v),
+        );
+    };
+
+    ( #[load] $i:ident dx + bx ) => {
+        $i.emit_opcode($crate::OpCode::LOAD_DX_BX);
+    };
+    ( #[load] $i:ident $r:ident ) => {
+        $i.emit_opcode_with_operand($crate::image!( #[offset load] $r ), 0);
+    };
+    ( #[load] $i:ident $r:ident $( $off:tt )+ ) => {
+        $crate::image!(
+            #[operand] $i
+            $crate::image!( #[offset load] $r ),
+            $crate::image!( #[offset] $( $off )+ )
+        );
+    };
+
+    ( #[store] $i:ident dx ) => {
+        $i.emit_opcode($crate::OpCode::STORE);
+    };
+    ( #[store] $i:ident dx + bx ) => {
+        $i.emit_opcode($crate::OpCode::STORE_DX_BX);
+    };
+    ( #[store] $i:ident $r:ident ) => {
+        $i.emit_opcode_with_operand($crate::image!( #[offset store] $r ), 0);
+    };
+    ( #[store] $i:ident $r:ident $( $off:tt )+ ) => {
+        $crate::image!(
+            #[operand] $i
+            $crate::image!( #[offset store] $r ),
+            $crate::image!( #[offset] $( $off )+ )
+        );
+    };
+
+    ( #[offset] + $( $off:tt )+ ) => {
+        $crate::image::IntoWord::into_word($( $off )+)
+    };
+    ( #[offset] - $( $off:tt )+ ) => {
+        $crate::image::IntoWord::into_word($( $off )+).wrapping_neg()
+    };
+
+    ( #[offset load] dx ) => { $crate::OpCode::LOAD_DX_OFF };
+    ( #[offset load] fp ) => { $crate::OpCode::LOAD_FP_OFF };
+    ( #[offset load] lp ) => { $crate::OpCode::LOAD_LP_OFF };
+    ( #[offset store] dx ) => { $crate::OpCode::STORE_DX_OFF };
+    ( #[offset store] fp ) => { $crate::OpCode::STORE_FP_OFF };
+    ( #[offset store] lp ) => { $crate::OpCode::STORE_LP_OFF };
+    ( #[offset $op:ident] $r:tt ) => {
+        compile_error!(concat!(
+            "invalid address register `", stringify!($r), "`"
+        ))
+    };
+
+    ( #[data] $i:ident ) => {};
+
+    ( #[data] $i:ident , $( $rest:tt )* ) => {
+        $crate::image!( #[data] $i $( $rest )* );
+    };
+
+    ( #[data] $i:ident $name:ident : $( $rest:tt )* ) => {
+        $crate::image!( #[place] $i $name );
+        $crate::image!( #[data] $i $( $rest )* );
+    };
+
+    ( #[data] $i:ident @ $l:ident $( $rest:tt )* ) => {
+        let label = $i.named_label(stringify!($l));
+        $i.emit_label(label);
+        $crate::image!( #[data] $i $( $rest )* );
+    };
+
+    ( #[data] $i:ident $v:expr $( , $( $rest:tt )* )? ) => {
+        $i.emit_data($v);
+        $crate::image!( #[data] $i $( $( $rest )* )? );
+    };
+
+    ( #[place] $i:ident $name:ident ) => {
+        let label = $i.named_label(stringify!($name));
+        if let Err(err) = $i.place_label(label) {
+            panic!("{err}");
+        }
+    };
+
+    ( #[mov] ax # ) => { $crate::OpCode::MOVE_OP_TO_AX };
+    ( #[mov] bx # ) => { $crate::OpCode::MOVE_OP_TO_BX };
+    ( #[mov] cx # ) => { $crate::OpCode::MOVE_OP_TO_CX };
+    ( #[mov] dx # ) => { $crate::OpCode::MOVE_OP_TO_DX };
+    ( #[mov] ax bx ) => { $crate::OpCode::MOVE_BX_TO_AX };
+    ( #[mov] ax cx ) => { $crate::OpCode::MOVE_CX_TO_AX };
+    ( #[mov] ax dx ) => { $crate::OpCode::MOVE_DX_TO_AX };
+    ( #[mov] bx ax ) => { $crate::OpCode::MOVE_AX_TO_BX };
+    ( #[mov] bx cx ) => { $crate::OpCode::MOVE_CX_TO_BX };
+    ( #[mov] bx dx ) => { $crate::OpCode::MOVE_DX_TO_BX };
+    ( #[mov] cx ax ) => { $crate::OpCode::MOVE_AX_TO_CX };
+    ( #[mov] cx bx ) => { $crate::OpCode::MOVE_BX_TO_CX };
+    ( #[mov] cx dx ) => { $crate::OpCode::MOVE_DX_TO_CX };
+    ( #[mov] dx ax ) => { $crate::OpCode::MOVE_AX_TO_DX };
+    ( #[mov] dx bx ) => { $crate::OpCode::MOVE_BX_TO_DX };
+    ( #[mov] dx cx ) => { $crate::OpCode::MOVE_CX_TO_DX };
+    ( #[mov] ax sp ) => { $crate::OpCode::MOVE_SP_TO_AX };
+    ( #[mov] ax fp ) => { $crate::OpCode::MOVE_FP_TO_AX };
+    ( #[mov] ax lp ) => { $crate::OpCode::MOVE_LP_TO_AX };
+    ( #[mov] sp ax ) => { $crate::OpCode::MOVE_AX_TO_SP };
+    ( #[mov] fp ax ) => { $crate::OpCode::MOVE_AX_TO_FP };
+    ( #[mov] lp ax ) => { $crate::OpCode::MOVE_AX_TO_LP };
+    ( #[mov] $d:tt $s:tt ) => {
+        compile_error!(concat!(
+            "invalid move to `", stringify!($d), "`"
+        ))
+    };
+
+    ( #[opcode] push    ) => { $crate::OpCode::PUSH };
+    ( #[opcode] pop     ) => { $crate::OpCode::POP };
+    ( #[opcode] inc     ) => { $crate::OpCode::INC };
+    ( #[opcode] dec     ) => { $crate::OpCode::DEC };
+    ( #[opcode] neg     ) => { $crate::OpCode::NEG };
+    ( #[opcode] add     ) => { $crate::OpCode::ADD };
+    ( #[opcode] sub     ) => { $crate::OpCode::SUB };
+    ( #[opcode] mul     ) => { $crate::OpCode::MUL };
+    ( #[opcode] div     ) => { $crate::OpCode::DIV };
+    ( #[opcode] fneg    ) => { $crate::OpCode::FNEG };
+    ( #[opcode] fadd    ) => { $crate::OpCode::FADD };
+    ( #[opcode] fsub    ) => { $crate::OpCode::FSUB };
+    ( #[opcode] fmul    ) => { $crate::OpCode::FMUL };
+    ( #[opcode] fdiv    ) => { $crate::OpCode::FDIV };
+    ( #[opcode] and     ) => { $crate::OpCode::AND };
+    ( #[opcode] or      ) => { $crate::OpCode::OR };
+    ( #[opcode] xor     ) => { $crate::OpCode::XOR };
+    ( #[opcode] not     ) => { $crate::OpCode::NOT };
+    ( #[opcode] shl     ) => { $crate::OpCode::SHL };
+    ( #[opcode] shr     ) => { $crate::OpCode::SHR };
+    ( #[opcode] jmp     ) => { $crate::OpCode::JMP };
+    ( #[opcode] je      ) => { $crate::OpCode::JE };
+    ( #[opcode] jne     ) => { $crate::OpCode::JNE };
+    ( #[opcode] jg      ) => { $crate::OpCode::JG };
+    ( #[opcode] jge     ) => { $crate::OpCode::JGE };
+    ( #[opcode] jl      ) => { $crate::OpCode::JL };
+    ( #[opcode] jle     ) => { $crate::OpCode::JLE };
+    ( #[opcode] ja      ) => { $crate::OpCode::JA };
+    ( #[opcode] jae     ) => { $crate::OpCode::JAE };
+    ( #[opcode] jb      ) => { $crate::OpCode::JB };
+    ( #[opcode] jbe     ) => { $crate::OpCode::JBE };
+    ( #[opcode] fjg     ) => { $crate::OpCode::FJG };
+    ( #[opcode] fjge    ) => { $crate::OpCode::FJGE };
+    ( #[opcode] fjl     ) => { $crate::OpCode::FJL };
+    ( #[opcode] fjle    ) => { $crate::OpCode::FJLE };
+    ( #[opcode] call    ) => { $crate::OpCode::CALL };
+    ( #[opcode] ret     ) => { $crate::OpCode::RET };
+    ( #[opcode] syscall ) => { $crate::OpCode::SYSCALL };
+    ( #[opcode] cwtor   ) => { $crate::OpCode::CWTOR };
+    ( #[opcode] cswtor  ) => { $crate::OpCode::CSWTOR };
+    ( #[opcode] crtow   ) => { $crate::OpCode::CRTOW };
+    ( #[opcode] crtosw  ) => { $crate::OpCode::CRTOSW };
+    ( #[opcode] deref   ) => { $crate::OpCode::DEREF };
+    ( #[opcode] malloc  ) => { $crate::OpCode::MALLOC };
+    ( #[opcode] free    ) => { $crate::OpCode::FREE };
+    ( #[opcode] leave   ) => { $crate::OpCode::LEAVE };
+    ( #[opcode] $m:tt ) => {
+        compile_error!(concat!("unknown mnemonic `", stringify!($m), "`"))
+    };
+
+    ( $( $item:tt )* ) => {
+        {
+            let mut i = $crate::image::Image::new();
+            $crate::image!( #[code] i $( $item )* );
+            if let Err(err) = i.finalize() {
+                panic!("{err}");
+            }
             i
         }
     };
-}
\ No newline at end of file
+}
@@ -0,0 +1,200 @@
+use super::{op_codes::OpCode, Word};
+
+/// instruction with its inline operand, as it is kept in predecoded
+/// stream and executed by vm
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub(super) enum Instruction {
+    Push,
+    Pop,
+
+    Inc,
+    Dec,
+    Neg,
+    Add,
+    Sub,
+    Mul,
+    Div,
+    FNeg,
+    FAdd,
+    FSub,
+    FMul,
+    FDiv,
+    And,
+    Or,
+    Xor,
+    Not,
+    Shl,
+    Shr,
+
+    Jmp,
+    Je,
+    Jne,
+    Jg,
+    Jge,
+    Jl,
+    Jle,
+    Ja,
+    Jae,
+    Jb,
+    Jbe,
+    FJg,
+    FJge,
+    FJl,
+    FJle,
+
+    Call,
+    Ret,
+    Syscall,
+
+    MoveOpToAx(Word),
+    MoveOpToBx(Word),
+    MoveOpToCx(Word),
+    MoveOpToDx(Word),
+    MoveBxToAx,
+    MoveCxToAx,
+    MoveDxToAx,
+    MoveAxToBx,
+    MoveCxToBx,
+    MoveDxToBx,
+    MoveAxToCx,
+    MoveBxToCx,
+    MoveDxToCx,
+    MoveAxToDx,
+    MoveBxToDx,
+    MoveCxToDx,
+
+    Cwtor,
+    Cswtor,
+    Crtow,
+    Crtosw,
+
+    Deref,
+
+    Malloc,
+    Free,
+
+    Store,
+    LoadDxOff(Word),
+    StoreDxOff(Word),
+    LoadFpOff(Word),
+    StoreFpOff(Word),
+    LoadLpOff(Word),
+    StoreLpOff(Word),
+    LoadDxBx,
+    StoreDxBx,
+
+    Enter(Word),
+    Leave,
+
+    MoveSpToAx,
+    MoveFpToAx,
+    MoveLpToAx,
+    MoveAxToSp,
+    MoveAxToFp,
+    MoveAxToLp,
+
+    // opcode, which is not known, traps when executed
+    Invalid(Word),
+}
+
+impl Instruction {
+    /// instruction of opcode, operand is ignored when
+    /// it has none, see `OpCode::has_operand`
+    #[inline(always)]
+    pub(super) fn new(opcode: Word, operand: Word) -> Self {
+        match opcode {
+            OpCode::PUSH          => Self::Push,
+            OpCode::POP           => Self::Pop,
+            OpCode::INC           => Self::Inc,
+            OpCode::DEC           => Self::Dec,
+            OpCode::NEG           => Self::Neg,
+            OpCode::ADD           => Self::Add,
+            OpCode::SUB           => Self::Sub,
+            OpCode::MUL           => Self::Mul,
+            OpCode::DIV           => Self::Div,
+            OpCode::FNEG          => Self::FNeg,
+            OpCode::FADD          => Self::FAdd,
+            OpCode::FSUB          => Self::FSub,
+            OpCode::FMUL          => Self::FMul,
+            OpCode::FDIV          => Self::FDiv,
+            OpCode::AND           => Self::And,
+            OpCode::OR            => Self::Or,
+            OpCode::XOR           => Self::Xor,
+            OpCode::NOT           => Self::Not,
+            OpCode::SHL           => Self::Shl,
+            OpCode::SHR           => Self::Shr,
+            OpCode::JMP           => Self::Jmp,
+            OpCode::JE            => Self::Je,
+            OpCode::JNE           => Self::Jne,
+            OpCode::JG            => Self::Jg,
+            OpCode::JGE           => Self::Jge,
+            OpCode::JL            => Self::Jl,
+            OpCode::JLE           => Self::Jle,
+            OpCode::JA            => Self::Ja,
+            OpCode::JAE           => Self::Jae,
+            OpCode::JB            => Self::Jb,
+            OpCode::JBE           => Self::Jbe,
+            OpCode::FJG           => Self::FJg,
+            OpCode::FJGE          => Self::FJge,
+            OpCode::FJL           => Self::FJl,
+            OpCode::FJLE          => Self::FJle,
+            OpCode::CALL          => Self::Call,
+            OpCode::RET           => Self::Ret,
+            OpCode::SYSCALL       => Self::Syscall,
+            OpCode::MOVE_OP_TO_AX => Self::MoveOpToAx(operand),
+            OpCode::MOVE_OP_TO_BX => Self::MoveOpToBx(operand),
+            OpCode::MOVE_OP_TO_CX => Self::MoveOpToCx(operand),
+            OpCode::MOVE_OP_TO_DX => Self::MoveOpToDx(operand),
+            OpCode::MOVE_BX_TO_AX => Self::MoveBxToAx,
+            OpCode::MOVE_CX_TO_AX => Self::MoveCxToAx,
+            OpCode::MOVE_DX_TO_AX => Self::MoveDxToAx,
+            OpCode::MOVE_AX_TO_BX => Self::MoveAxToBx,
+            OpCode::MOVE_CX_TO_BX => Self::MoveCxToBx,
+            OpCode::MOVE_DX_TO_BX => Self::MoveDxToBx,
+            OpCode::MOVE_AX_TO_CX => Self::MoveAxToCx,
+            OpCode::MOVE_BX_TO_CX => Self::MoveBxToCx,
+            OpCode::MOVE_DX_TO_CX => Self::MoveDxToCx,
+            OpCode::MOVE_AX_TO_DX => Self::MoveAxToDx,
+            OpCode::MOVE_BX_TO_DX => Self::MoveBxToDx,
+            OpCode::MOVE_CX_TO_DX => Self::MoveCxToDx,
+            OpCode::CWTOR         => Self::Cwtor,
+            OpCode::CSWTOR        => Self::Cswtor,
+            OpCode::CRTOW         => Self::Crtow,
+            OpCode::CRTOSW        => Self::Crtosw,
+            OpCode::DEREF         => Self::Deref,
+            OpCode::MALLOC        => Self::Malloc,
+            OpCode::FREE          => Self::Free,
+            OpCode::STORE         => Self::Store,
+            OpCode::LOAD_DX_OFF   => Self::LoadDxOff(operand),
+            OpCode::STORE_DX_OFF  => Self::StoreDxOff(operand),
+            OpCode::LOAD_FP_OFF   => Self::LoadFpOff(operand),
+            OpCode::STORE_FP_OFF  => Self::StoreFpOff(operand),
+            OpCode::LOAD_LP_OFF   => Self::LoadLpOff(operand),
+            OpCode::STORE_LP_OFF  => Self::StoreLpOff(operand),
+            OpCode::LOAD_DX_BX    => Self::LoadDxBx,
+            OpCode::STORE_DX_BX   => Self::StoreDxBx,
+            OpCode::ENTER         => Self::Enter(operand),
+            OpCode::LEAVE         => Self::Leave,
+            OpCode::MOVE_SP_TO_AX => Self::MoveSpToAx,
+            OpCode::MOVE_FP_TO_AX => Self::MoveFpToAx,
+            OpCode::MOVE_LP_TO_AX => Self::MoveLpToAx,
+            OpCode::MOVE_AX_TO_SP => Self::MoveAxToSp,
+            OpCode::MOVE_AX_TO_FP => Self::MoveAxToFp,
+            OpCode::MOVE_AX_TO_LP => Self::MoveAxToLp,
+            opcode                => Self::Invalid(opcode),
+        }
+    }
+
+    /// whether instruction may jump by address in dx,
+    /// calls are not jumps
+    #[cfg(feature = "jit")]
+    pub(super) fn is_jump(self) -> bool {
+        matches!(
+            self,
+            Self::Jmp | Self::Je | Self::Jne |
+            Self::Jg | Self::Jge | Self::Jl | Self::Jle |
+            Self::Ja | Self::Jae | Self::Jb | Self::Jbe |
+            Self::FJg | Self::FJge | Self::FJl | Self::FJle
+        )
+    }
+}
@@ -0,0 +1,821 @@
+use std::{
+    ffi::{c_int, c_void},
+    io,
+    mem::{self, offset_of},
+    ptr,
+};
+
+use super::{error::Registers, OpCode, Word};
+
+#[cfg(not(all(target_arch = "x86_64", target_os = "linux")))]
+compile_error!("jit feature is supported only on x86-64 linux");
+
+// visits of address, after which block starting there is compiled
+const HOT_THRESHOLD: u32 = 16;
+// instructions of the shortest block, which does not loop to itself,
+// entering compiled code costs about as much as interpreting
+// of few instructions
+const MIN_BLOCK: usize = 8;
+// instructions of the longest block
+const MAX_BLOCK: usize = 256;
+// bytes of executable memory, all blocks are dropped when it is full
+const CODE_SIZE: usize = 1 << 20;
+
+const PROT_READ : c_int = 1;
+const PROT_WRITE: c_int = 2;
+const PROT_EXEC : c_int = 4;
+const MAP_PRIVATE  : c_int = 0x02;
+const MAP_ANONYMOUS: c_int = 0x20;
+
+extern "C" {
+    fn mmap(
+        addr: *mut c_void,
+        len: usize,
+        prot: c_int,
+        flags: c_int,
+        fd: c_int,
+        offset: i64,
+    ) -> *mut c_void;
+    fn mprotect(addr: *mut c_void, len: usize, prot: c_int) -> c_int;
+    fn munmap(addr: *mut c_void, len: usize) -> c_int;
+}
+
+// host registers
+const RAX: u8 = 0;
+const RCX: u8 = 1;
+const RDX: u8 = 2;
+const RDI: u8 = 7;
+const R12: u8 = 12;
+const R13: u8 = 13;
+const R14: u8 = 14;
+const R15: u8 = 15;
+
+// registers of vm kept in host ones while block runs
+const AX: u8 = 8;
+const BX: u8 = 9;
+const CX: u8 = 10;
+const DX: u8 = 11;
+// instructions executed by block, instructions it may execute,
+// memory of vm and state passed to block
+const STEPS : u8 = R12;
+const BUDGET: u8 = R13;
+const MEMORY: u8 = R14;
+const STATE : u8 = R15;
+
+// conditions of jcc
+const B : u8 = 0x2;
+const AE: u8 = 0x3;
+const E : u8 = 0x4;
+const NE: u8 = 0x5;
+const BE: u8 = 0x6;
+const A : u8 = 0x7;
+const S : u8 = 0x8;
+const L : u8 = 0xC;
+const GE: u8 = 0xD;
+const LE: u8 = 0xE;
+const G : u8 = 0xF;
+
+// registers and memory passed to compiled block,
+// ax..dx, ip and steps are written back
+#[repr(C)]
+struct State {
+    ax    : Word,
+    bx    : Word,
+    cx    : Word,
+    dx    : Word,
+    ip    : Word,
+    sp    : Word,
+    fp    : Word,
+    lp    : Word,
+    memory: *mut Word,
+    size  : Word,
+    // stores below guard leave compiled code
+    guard : Word,
+    budget: u64,
+    steps : u64,
+}
+
+type Native = unsafe extern "sysv64" fn(*mut State);
+
+/// compiler of hot blocks of code to x86-64, blocks end with jumps
+/// or before instructions, which are left to interpreter
+#[derive(Debug)]
+pub(super) struct Jit {
+    code   : CodeMemory,
+    // bytes of code taken by compiled blocks
+    used   : usize,
+    entries: Vec<Entry>,
+    blocks : Vec<Block>,
+    // end of the highest compiled block
+    end    : Word,
+    // instructions executed by compiled code
+    steps  : u64,
+}
+
+#[derive(Debug, Clone, Copy)]
+enum Entry {
+    // visits of address
+    Cold(u32),
+    // offset of block in code
+    Compiled(usize),
+    // first instruction can not be compiled
+    Rejected,
+}
+
+#[derive(Debug)]
+struct Block {
+    start: Word,
+    // words of block, it is dropped when they change
+    words: Vec<Word>,
+}
+
+impl Jit {
+    pub(super) fn new(size: Word) -> Result<Self, String> {
+        Ok(Self {
+            code   : CodeMemory::new(CODE_SIZE)?,
+            used   : 0,
+            entries: vec![Entry::Cold(0); size as usize],
+            blocks : Vec::new(),
+            end    : 0,
+            steps  : 0,
+        })
+    }
+
+    pub(super) fn steps(&self) -> u64 {
+        self.steps
+    }
+
+    fn clear(&mut self, size: Word) {
+        self.entries.clear();
+        self.entries.resize(size as usize, Entry::Cold(0));
+        self.blocks.clear();
+        self.used = 0;
+        self.end = 0;
+    }
+
+    /// drops blocks, which overlap written words
+    #[inline]
+    pub(super) fn invalidate(&mut self, start: Word, end: Word) {
+        // most of writes are to stack and heap above blocks
+        if start >= self.end {
+            return;
+        }
+        self.drop_blocks(start, end);
+    }
+
+    #[cold]
+    fn drop_blocks(&mut self, start: Word, end: Word) {
+        self.blocks.retain(|block| {
+            let block_end = block.start + block.words.len() as Word;
+            let overlaps = block.start < end && start < block_end;
+            if overlaps {
+                self.entries[block.start as usize] = Entry::Cold(0);
+            }
+            !overlaps
+        });
+    }
+
+    /// drops blocks, which words differ from memory,
+    /// used when memory was written without `invalidate`
+    pub(super) fn refresh(&mut self, memory: &[Word]) {
+        if memory.len() != self.entries.len() {
+            self.clear(memory.len() as Word);
+            return;
+        }
+        self.blocks.retain(|block| {
+            let start = block.start as usize;
+            let same = memory[start..start + block.words.len()] == block.words;
+            if !same {
+                self.entries[start] = Entry::Cold(0);
+            }
+            same
+        });
+    }
+
+    /// runs block at offset from ip, returns executed instructions,
+    /// zero when the first instruction is left to interpreter. Stores
+    /// below guard or compiled blocks are left to interpreter too
+    pub(super) fn run(
+        &mut self,
+        offset: usize,
+        registers: &mut Registers,
+        memory: &mut [Word],
+        guard: Word,
+        budget: u64,
+    ) -> u64 {
+        let mut state = State {
+            ax    : registers.ax,
+            bx    : registers.bx,
+            cx    : registers.cx,
+            dx    : registers.dx,
+            ip    : registers.ip,
+            sp    : registers.sp,
+            fp    : registers.fp,
+            lp    : registers.lp,
+            memory: memory.as_mut_ptr(),
+            size  : memory.len() as Word,
+            guard : guard.max(self.end),
+            budget,
+            steps : 0,
+        };
+        // SAFETY: block was written by `compile`, it accesses memory
+        // only below size and stops after budget
+        unsafe {
+            let native: Native = mem::transmute(self.code.ptr.add(offset));
+            native(&mut state);
+        }
+
+        registers.ax = state.ax;
+        registers.bx = state.bx;
+        registers.cx = state.cx;
+        registers.dx = state.dx;
+        registers.ip = state.ip;
+        self.steps += state.steps;
+        state.steps
+    }
+
+    /// offset of block at address, which is compiled when it is hot,
+    /// dx is the current one
+    #[inline]
+    pub(super) fn block(
+        &mut self,
+        address: Word,
+        dx: Word,
+        memory: &[Word],
+    ) -> Option<usize> {
+        match self.entries.get_mut(address as usize)? {
+            Entry::Compiled(offset) => Some(*offset),
+            Entry::Rejected => None,
+            Entry::Cold(visits) if *visits + 1 < HOT_THRESHOLD => {
+                *visits += 1;
+                None
+            }
+            Entry::Cold(_) => self.compile(address, dx, memory),
+        }
+    }
+
+    #[cold]
+    fn compile(
+        &mut self,
+        address: Word,
+        dx: Word,
+        memory: &[Word],
+    ) -> Option<usize> {
+        let Some((code, end)) = compile(memory, address, dx) else {
+            self.entries[address as usize] = Entry::Rejected;
+            return None;
+        };
+        if self.used + code.len() > CODE_SIZE {
+            self.clear(memory.len() as Word);
+        }
+
+        let offset = self.used;
+        self.code.write(offset, &code);
+        self.used += code.len();
+        self.end = self.end.max(end);
+        self.entries[address as usize] = Entry::Compiled(offset);
+        self.blocks.push(Block {
+            start: address,
+            words: memory[address as usize..end as usize].to_vec(),
+        });
+        Some(offset)
+    }
+}
+
+// instructions, which compiled code executes
+fn is_supported(opcode: Word) -> bool {
+    matches!(
+        opcode,
+        OpCode::INC..=OpCode::SHR |
+        OpCode::JMP..=OpCode::FJLE |
+        OpCode::MOVE_OP_TO_AX..=OpCode::DEREF |
+        OpCode::STORE..=OpCode::STORE_DX_BX |
+        OpCode::LOAD_LP_OFF |
+        OpCode::STORE_LP_OFF |
+        OpCode::MOVE_SP_TO_AX..=OpCode::MOVE_LP_TO_AX
+    )
+}
+
+fn is_jump(opcode: Word) -> bool {
+    (OpCode::JMP..=OpCode::FJLE).contains(&opcode)
+}
+
+// block starting at address and its end, None when it is not worth
+// compiling. Block likely loops, when it jumps to start with dx,
+// which it sets or keeps from now
+fn compile(
+    memory: &[Word],
+    start: Word,
+    dx: Word,
+) -> Option<(Vec<u8>, Word)> {
+    let mut instructions = Vec::new();
+    let mut address = start;
+    let mut dx = Some(dx);
+    while instructions.len() < MAX_BLOCK {
+        let Some(&opcode) = memory.get(address as usize) else { break };
+        if !is_supported(opcode) {
+            break;
+        }
+        let has_operand = OpCode::has_operand(opcode);
+        let operand = if has_operand {
+            match memory.get(address as usize + 1) {
+                Some(&operand) => operand,
+                None => break,
+            }
+        } else {
+            0
+        };
+        instructions.push((address, opcode, operand));
+        address += 1 + has_operand as Word;
+        match opcode {
+            OpCode::MOVE_OP_TO_DX => dx = Some(operand),
+            OpCode::DIV |
+            OpCode::MOVE_AX_TO_DX |
+            OpCode::MOVE_BX_TO_DX |
+            OpCode::MOVE_CX_TO_DX => dx = None,
+            _ => (),
+        }
+        if is_jump(opcode) {
+            break;
+        }
+    }
+    let jumps = instructions
+        .last()
+        .is_some_and(|&(_, opcode, _)| is_jump(opcode));
+    let loops = jumps && dx == Some(start);
+    if instructions.is_empty() || instructions.len() < MIN_BLOCK && !loops {
+        return None;
+    }
+
+    let count = instructions.len() as i32;
+    let mut e = Emitter::default();
+    let top = e.label();
+    let leave = e.label();
+    let exhausted = e.label();
+    // instructions left to interpreter: label, address and
+    // instructions executed before
+    let mut bails = Vec::new();
+
+    for reg in [R12, R13, R14, R15] {
+        e.bytes(&[0x41, 0x50 + (reg & 7)]);
+    }
+    e.mov(STATE, RDI);
+    for (reg, offset) in [
+        (AX, offset_of!(State, ax)),
+        (BX, offset_of!(State, bx)),
+        (CX, offset_of!(State, cx)),
+        (DX, offset_of!(State, dx)),
+        (MEMORY, offset_of!(State, memory)),
+        (BUDGET, offset_of!(State, budget)),
+    ] {
+        e.load_state(reg, offset);
+    }
+    e.rr(&[0x31], STEPS, STEPS);
+
+    // the whole block runs only within budget
+    e.place(top);
+    e.mov(RAX, BUDGET);
+    e.rr(&[0x29], STEPS, RAX);
+    e.unary(0x81, 7, RAX);
+    e.bytes(&count.to_le_bytes());
+    e.jcc(B, exhausted);
+
+    for (idx, &(address, opcode, operand)) in instructions.iter().enumerate() {
+        let mut bail = || {
+            let label = e.label();
+            bails.push((label, address, idx as i32));
+            label
+        };
+        match opcode {
+            OpCode::INC => e.unary(0xFF, 0, AX),
+            OpCode::DEC => e.unary(0xFF, 1, AX),
+            OpCode::NEG => e.unary(0xF7, 3, AX),
+            OpCode::NOT => e.unary(0xF7, 2, AX),
+            OpCode::ADD => e.rr(&[0x01], BX, AX),
+            OpCode::SUB => e.rr(&[0x29], BX, AX),
+            OpCode::AND => e.rr(&[0x21], BX, AX),
+            OpCode::OR  => e.rr(&[0x09], BX, AX),
+            OpCode::XOR => e.rr(&[0x31], BX, AX),
+            OpCode::MUL => e.rr(&[0x0F, 0xAF], AX, BX),
+
+            OpCode::DIV => {
+                let label = bail();
+                e.rr(&[0x85], BX, BX);
+                e.jcc(E, label);
+                e.mov(RAX, AX);
+                e.rr(&[0x31], RDX, RDX);
+                e.unary(0xF7, 6, BX);
+                e.mov(AX, RAX);
+                e.mov(DX, RDX);
+            }
+
+            // shift counts are masked like wrapping_shl
+            OpCode::SHL | OpCode::SHR => {
+                e.mov(RCX, BX);
+                let ext = if opcode == OpCode::SHL { 4 } else { 5 };
+                e.unary(0xD3, ext, AX);
+            }
+
+            // flips sign bit
+            OpCode::FNEG => {
+                e.rr(&[0x0F, 0xBA], 7, AX);
+                e.bytes(&[63]);
+            }
+
+            OpCode::FADD | OpCode::FSUB | OpCode::FMUL | OpCode::FDIV => {
+                let op = match opcode {
+                    OpCode::FADD => 0x58,
+                    OpCode::FMUL => 0x59,
+                    OpCode::FSUB => 0x5C,
+                    _ => 0x5E,
+                };
+                e.movq_to_xmm(0, AX);
+                e.movq_to_xmm(1, BX);
+                e.bytes(&[0xF2, 0x0F, op, 0xC1]);
+                e.movq_from_xmm(AX, 0);
+            }
+
+            // words with the highest bit are left to interpreter
+            OpCode::CWTOR => {
+                let label = bail();
+                e.rr(&[0x85], AX, AX);
+                e.jcc(S, label);
+                e.cvtsi2sd(AX);
+                e.movq_from_xmm(AX, 0);
+            }
+
+            OpCode::CSWTOR => {
+                e.cvtsi2sd(AX);
+                e.movq_from_xmm(AX, 0);
+            }
+
+            // out of range reals and NaN give i64::MIN, which
+            // is left to interpreter to saturate
+            OpCode::CRTOW | OpCode::CRTOSW => {
+                let label = bail();
+                e.movq_to_xmm(0, AX);
+                e.cvttsd2si(RAX);
+                if opcode == OpCode::CRTOW {
+                    e.rr(&[0x85], RAX, RAX);
+                    e.jcc(S, label);
+                } else {
+                    e.mov_imm(RCX, 1 << 63);
+                    e.rr(&[0x39], RCX, RAX);
+                    e.jcc(E, label);
+                }
+                e.mov(AX, RAX);
+            }
+
+            OpCode::MOVE_OP_TO_AX => e.mov_imm(AX, operand),
+            OpCode::MOVE_OP_TO_BX => e.mov_imm(BX, operand),
+            OpCode::MOVE_OP_TO_CX => e.mov_imm(CX, operand),
+            OpCode::MOVE_OP_TO_DX => e.mov_imm(DX, operand),
+            OpCode::MOVE_BX_TO_AX => e.mov(AX, BX),
+            OpCode::MOVE_CX_TO_AX => e.mov(AX, CX),
+            OpCode::MOVE_DX_TO_AX => e.mov(AX, DX),
+            OpCode::MOVE_AX_TO_BX => e.mov(BX, AX),
+            OpCode::MOVE_CX_TO_BX => e.mov(BX, CX),
+            OpCode::MOVE_DX_TO_BX => e.mov(BX, DX),
+            OpCode::MOVE_AX_TO_CX => e.mov(CX, AX),
+            OpCode::MOVE_BX_TO_CX => e.mov(CX, BX),
+            OpCode::MOVE_DX_TO_CX => e.mov(CX, DX),
+            OpCode::MOVE_AX_TO_DX => e.mov(DX, AX),
+            OpCode::MOVE_BX_TO_DX => e.mov(DX, BX),
+            OpCode::MOVE_CX_TO_DX => e.mov(DX, CX),
+            OpCode::MOVE_SP_TO_AX => e.load_state(AX, offset_of!(State, sp)),
+            OpCode::MOVE_FP_TO_AX => e.load_state(AX, offset_of!(State, fp)),
+            OpCode::MOVE_LP_TO_AX => e.load_state(AX, offset_of!(State, lp)),
+
+            // address in rax, bad ones are left to interpreter
+            OpCode::DEREF |
+            OpCode::LOAD_DX_OFF |
+            OpCode::LOAD_FP_OFF |
+            OpCode::LOAD_LP_OFF |
+            OpCode::LOAD_DX_BX => {
+                let label = bail();
+                match opcode {
+                    OpCode::DEREF => {
+                        e.rr(&[0x85], AX, AX);
+                        e.jcc(E, label);
+                        e.mov(RAX, AX);
+                    }
+                    OpCode::LOAD_DX_BX => {
+                        e.mov(RAX, DX);
+                        e.rr(&[0x01], BX, RAX);
+                    }
+                    _ => e.address(opcode, operand),
+                }
+                e.cmp_state(RAX, offset_of!(State, size));
+                e.jcc(AE, label);
+                e.memory(0x8B, AX);
+            }
+
+            // stores, which may hit compiled code, are left
+            // to interpreter
+            OpCode::STORE |
+            OpCode::STORE_DX_OFF |
+            OpCode::STORE_FP_OFF |
+            OpCode::STORE_LP_OFF |
+            OpCode::STORE_DX_BX => {
+                let label = bail();
+                match opcode {
+                    OpCode::STORE => e.mov(RAX, DX),
+                    OpCode::STORE_DX_BX => {
+                        e.mov(RAX, DX);
+                        e.rr(&[0x01], BX, RAX);
+                    }
+                    _ => e.address(opcode, operand),
+                }
+                e.cmp_state(RAX, offset_of!(State, guard));
+                e.jcc(B, label);
+                e.cmp_state(RAX, offset_of!(State, size));
+                e.jcc(AE, label);
+                e.memory(0x89, AX);
+            }
+
+            // ends block, loops to its start without leaving
+            // compiled code
+            _ => {
+                e.unary(0x81, 0, STEPS);
+                e.bytes(&count.to_le_bytes());
+                let taken = e.label();
+                if opcode != OpCode::JMP {
+                    let condition = match opcode {
+                        OpCode::JE  => E,
+                        OpCode::JNE => NE,
+                        OpCode::JG  => G,
+                        OpCode::JGE => GE,
+                        OpCode::JL  => L,
+                        OpCode::JLE => LE,
+                        OpCode::JA  => A,
+                        OpCode::JAE => AE,
+                        OpCode::JB  => B,
+                        OpCode::JBE => BE,
+                        // ucomisd is unordered for NaN, like `>`
+                        OpCode::FJG | OpCode::FJL => A,
+                        _ => AE,
+                    };
+                    if opcode < OpCode::FJG {
+                        e.rr(&[0x39], BX, AX);
+                    } else {
+                        e.movq_to_xmm(0, AX);
+                        e.movq_to_xmm(1, BX);
+                        let operands = match opcode {
+                            OpCode::FJG | OpCode::FJGE => 0xC1,
+                            _ => 0xC8,
+                        };
+                        e.bytes(&[0x66, 0x0F, 0x2E, operands]);
+                    }
+                    e.jcc(condition, taken);
+                    e.mov_imm(RAX, address + 1);
+                    e.jmp(leave);
+                }
+                e.place(taken);
+                e.mov(RAX, DX);
+                e.mov_imm(RCX, start);
+                e.rr(&[0x39], RCX, RAX);
+                e.jcc(E, top);
+                e.jmp(leave);
+            }
+        }
+    }
+
+    if !jumps {
+        e.unary(0x81, 0, STEPS);
+        e.bytes(&count.to_le_bytes());
+        e.mov_imm(RAX, address);
+        e.jmp(leave);
+    }
+
+    e.place(exhausted);
+    e.mov_imm(RAX, start);
+    e.jmp(leave);
+
+    for (label, address, executed) in bails {
+        e.place(label);
+        e.unary(0x81, 0, STEPS);
+        e.bytes(&executed.to_le_bytes());
+        e.mov_imm(RAX, address);
+        e.jmp(leave);
+    }
+
+    // new ip in rax
+    e.place(leave);
+    e.store_state(offset_of!(State, ip), RAX);
+    e.store_state(offset_of!(State, ax), AX);
+    e.store_state(offset_of!(State, bx), BX);
+    e.store_state(offset_of!(State, cx), CX);
+    e.store_state(offset_of!(State, dx), DX);
+    e.store_state(offset_of!(State, steps), STEPS);
+    for reg in [R15, R14, R13, R12] {
+        e.bytes(&[0x41, 0x58 + (reg & 7)]);
+    }
+    e.bytes(&[0xC3]);
+
+    Some((e.finish(), address))
+}
+
+#[derive(Debug, Clone, Copy)]
+struct Label(usize);
+
+// x86-64 encoder of instructions used by compiled blocks
+#[derive(Default)]
+struct Emitter {
+    code  : Vec<u8>,
+    labels: Vec<Option<usize>>,
+    // rel32 and its label
+    fixups: Vec<(usize, Label)>,
+}
+
+impl Emitter {
+    fn bytes(&mut self, bytes: &[u8]) {
+        self.code.extend_from_slice(bytes);
+    }
+
+    // rex.w with extensions of reg and rm
+    fn rex(&mut self, reg: u8, rm: u8) {
+        self.code.push(0x48 | (reg >> 3) << 2 | rm >> 3);
+    }
+
+    // instruction with both operands in registers
+    fn rr(&mut self, opcode: &[u8], reg: u8, rm: u8) {
+        self.rex(reg, rm);
+        self.bytes(opcode);
+        self.code.push(0xC0 | (reg & 7) << 3 | rm & 7);
+    }
+
+    // instruction with opcode extension in reg field
+    fn unary(&mut self, opcode: u8, ext: u8, rm: u8) {
+        self.rr(&[opcode], ext, rm);
+    }
+
+    fn mov(&mut self, dst: u8, src: u8) {
+        self.rr(&[0x89], src, dst);
+    }
+
+    fn mov_imm(&mut self, dst: u8, value: Word) {
+        self.code.push(0x48 | dst >> 3);
+        self.code.push(0xB8 + (dst & 7));
+        self.bytes(&value.to_le_bytes());
+    }
+
+    // opcode with reg and [STATE + offset]
+    fn state(&mut self, opcode: u8, reg: u8, offset: usize) {
+        self.rex(reg, STATE);
+        self.bytes(&[opcode, 0x40 | (reg & 7) << 3 | STATE & 7]);
+        self.code.push(offset as u8);
+    }
+
+    fn load_state(&mut self, reg: u8, offset: usize) {
+        self.state(0x8B, reg, offset);
+    }
+
+    fn store_state(&mut self, offset: usize, reg: u8) {
+        self.state(0x89, reg, offset);
+    }
+
+    fn cmp_state(&mut self, reg: u8, offset: usize) {
+        self.state(0x3B, reg, offset);
+    }
+
+    // opcode with reg and [MEMORY + rax * 8]
+    fn memory(&mut self, opcode: u8, reg: u8) {
+        self.rex(reg, MEMORY);
+        self.bytes(&[opcode, (reg & 7) << 3 | 0b100]);
+        self.code.push(0b11 << 6 | RAX << 3 | MEMORY & 7);
+    }
+
+    // rax = base of load or store + operand
+    fn address(&mut self, opcode: Word, operand: Word) {
+        match opcode {
+            OpCode::LOAD_FP_OFF | OpCode::STORE_FP_OFF => {
+                self.load_state(RAX, offset_of!(State, fp));
+            }
+            OpCode::LOAD_LP_OFF | OpCode::STORE_LP_OFF => {
+                self.load_state(RAX, offset_of!(State, lp));
+            }
+            _ => self.mov(RAX, DX),
+        }
+        self.mov_imm(RCX, operand);
+        self.rr(&[0x01], RCX, RAX);
+    }
+
+    fn movq_to_xmm(&mut self, xmm: u8, reg: u8) {
+        self.code.push(0x66);
+        self.rr(&[0x0F, 0x6E], xmm, reg);
+    }
+
+    fn movq_from_xmm(&mut self, reg: u8, xmm: u8) {
+        self.code.push(0x66);
+        self.rr(&[0x0F, 0x7E], xmm, reg);
+    }
+
+    // xmm0 = reg as f64
+    fn cvtsi2sd(&mut self, reg: u8) {
+        self.code.push(0xF2);
+        self.rr(&[0x0F, 0x2A], 0, reg);
+    }
+
+    // reg = xmm0 truncated to i64
+    fn cvttsd2si(&mut self, reg: u8) {
+        self.code.push(0xF2);
+        self.rr(&[0x0F, 0x2C], reg, 0);
+    }
+
+    fn label(&mut self) -> Label {
+        self.labels.push(None);
+        Label(self.labels.len() - 1)
+    }
+
+    fn place(&mut self, label: Label) {
+        self.labels[label.0] = Some(self.code.len());
+    }
+
+    fn jcc(&mut self, condition: u8, label: Label) {
+        self.bytes(&[0x0F, 0x80 | condition]);
+        self.rel32(label);
+    }
+
+    fn jmp(&mut self, label: Label) {
+        self.code.push(0xE9);
+        self.rel32(label);
+    }
+
+    fn rel32(&mut self, label: Label) {
+        self.fixups.push((self.code.len(), label));
+        self.bytes(&[0; 4]);
+    }
+
+    fn finish(mut self) -> Vec<u8> {
+        for (position, label) in self.fixups {
+            let target = self.labels[label.0].expect("label is placed");
+            let rel = target as i64 - (position + 4) as i64;
+            self.code[position..position + 4]
+                .copy_from_slice(&(rel as i32).to_le_bytes());
+        }
+        self.code
+    }
+}
+
+// anonymous mapping, which is writable only while code is written
+#[derive(Debug)]
+struct CodeMemory {
+    ptr: *mut u8,
+    len: usize,
+}
+
+// mapping is owned by one jit
+unsafe impl Send for CodeMemory {}
+
+impl CodeMemory {
+    fn new(len: usize) -> Result<Self, String> {
+        // SAFETY: new anonymous mapping does not alias anything
+        let ptr = unsafe {
+            mmap(
+                ptr::null_mut(),
+                len,
+                PROT_READ | PROT_EXEC,
+                MAP_PRIVATE | MAP_ANONYMOUS,
+                -1,
+                0,
+            )
+        };
+        if ptr as isize == -1 {
+            let err = io::Error::last_os_error();
+            return Err(format!("mmap of jit code: {err}"));
+        }
+        Ok(Self { ptr: ptr.cast(), len })
+    }
+
+    fn protect(&mut self, prot: c_int) {
+        // SAFETY: range is the whole mapping
+        let result = unsafe { mprotect(self.ptr.cast(), self.len, prot) };
+        if result != 0 {
+            let err = io::Error::last_os_error();
+            panic!("mprotect of jit code: {err}");
+        }
+    }
+
+    fn write(&mut self, offset: usize, code: &[u8]) {
+        assert!(offset + code.len() <= self.len);
+        self.protect(PROT_READ | PROT_WRITE);
+        // SAFETY: range is inside of writable mapping
+        unsafe {
+            ptr::copy_nonoverlapping(
+                code.as_ptr(),
+                self.ptr.add(offset),
+                code.len(),
+            );
+        }
+        self.protect(PROT_READ | PROT_EXEC);
+    }
+}
+
+impl Drop for CodeMemory {
+    fn drop(&mut self) {
+        // SAFETY: mapping is not used after drop
+        unsafe {
+            munmap(self.ptr.cast(), self.len);
+        }
+    }
+}
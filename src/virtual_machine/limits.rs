@@ -0,0 +1,97 @@
+use std::{
+    io::{self, Read, Write},
+    time::Duration,
+};
+
+use super::Word;
+
+// steps between checks of wall-clock time
+pub(crate) const TIME_CHECK_INTERVAL: u64 = 1024;
+
+/// sandbox limits of vm, None means unlimited.
+/// Every exceeded limit traps with its own `VmError`
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq)]
+pub struct Limits {
+    /// executed instructions
+    pub fuel        : Option<u64>,
+    /// wall-clock time since first step after loading of image,
+    /// checked every 1024 instructions
+    pub time        : Option<Duration>,
+    /// bytes of heap blocks, which are allocated at the same time
+    pub heap_bytes  : Option<Word>,
+    /// bytes written to output by syscalls
+    pub output_bytes: Option<u64>,
+    /// bytes read from input by syscalls
+    pub input_bytes : Option<u64>,
+}
+
+impl Limits {
+    /// no limits, same as default
+    pub fn none() -> Self {
+        Self::default()
+    }
+}
+
+// input of syscall, which counts read bytes
+pub(crate) struct LimitedReader<'a, R> {
+    pub inner   : &'a mut R,
+    pub count   : &'a mut u64,
+    pub limit   : Option<u64>,
+    pub exceeded: bool,
+}
+
+impl<R: Read> Read for LimitedReader<'_, R> {
+    fn read(&mut self, buf: &mut [u8]) -> io::Result<usize> {
+        let mut buf = buf;
+        if let Some(limit) = self.limit {
+            let left = limit.saturating_sub(*self.count);
+            if left == 0 && !buf.is_empty() {
+                self.exceeded = true;
+                return Err(io::Error::other("input limit exceeded"));
+            }
+            let length = buf.len().min(left.try_into().unwrap_or(usize::MAX));
+            buf = &mut buf[..length];
+        }
+        let read = self.inner.read(buf)?;
+        *self.count += read as u64;
+        Ok(read)
+    }
+}
+
+// output of syscall, which counts written bytes,
+// bytes over limit are never written
+pub(crate) struct LimitedWriter<'a, W> {
+    pub inner   : &'a mut W,
+    pub count   : &'a mut u64,
+    pub limit   : Option<u64>,
+    pub exceeded: bool,
+}
+
+impl<W: Write> Write for LimitedWriter<'_, W> {
+    fn write(&mut self, buf: &[u8]) -> io::Result<usize> {
+        let mut buf = buf;
+        if let Some(limit) = self.limit {
+            let left = limit.saturating_sub(*self.count);
+            let mut length =
+                buf.len().min(left.try_into().unwrap_or(usize::MAX));
+            // output is not cut inside of utf-8 character
+            while length < buf.len() && length > 0 &&
+                  buf[length] & 0xc0 == 0x80
+            {
+                length -= 1;
+            }
+            if length == 0 && !buf.is_empty() {
+                self.exceeded = true;
+                return Err(io::Error::other("output limit exceeded"));
+            }
+            buf = &buf[..length];
+        }
+        let written = self.inner.write(buf)?;
+        *self.count += written as u64;
+        Ok(written)
+    }
+
+    fn flush(&mut self) -> io::Result<()> {
+        self.inner.flush()
+    }
+}
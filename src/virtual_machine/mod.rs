@@ -1,15 +1,40 @@
 mod lexical_cast;
 mod into_char;
-mod byte_casts;
+mod instruction;
+#[cfg(feature = "jit")]
+mod jit;
+pub mod assembler;
+pub mod coverage;
+pub mod disassembler;
+pub mod error;
+pub mod heap;
 pub mod image;
+pub mod limits;
+pub mod observer;
+pub mod snapshot;
+pub mod trace;
 pub mod op_codes;
-
-use std::io::{self, Write};
-
-use image::Image;
-use into_char::IntoChar;
+pub mod profile;
+pub mod syscall;
+
+use std::{
+    collections::BTreeMap,
+    io::{self, Read, Stdin, Stdout, Write},
+    time::Instant,
+};
+
+use error::{Registers, VmError};
+use heap::{FreeError, Heap};
+use image::{Image, SectionKind, Symbol};
+use instruction::Instruction;
+#[cfg(feature = "jit")]
+use jit::Jit;
+use limits::{Limits, LimitedReader, LimitedWriter, TIME_CHECK_INTERVAL};
+use observer::{NoObserver, Observer};
+use snapshot::Snapshot;
 use lexical_cast::LexicalCast;
 use op_codes::*;
+use syscall::{SyscallContext, SyscallHandler, Syscalls};
 
 pub type Word  = u64;
 pub type SWord = i64;
@@ -17,6 +42,9 @@ pub type Real  = f64;
 
 const DEFAULT_MEM_SIZE: Word = 0x10000;
 
+// words kept free between stack and heap or image
+pub const STACK_GUARD: Word = 16;
+
 type Memory = Vec<Word>;
 
 /// ip - instraction pointer;
@@ -24,9 +52,18 @@ type Memory = Vec<Word>;
 /// fp - frame pointer;
 /// lp - local variable pointer
 /// ax & bx - registers for operations
+/// input & output - streams of console syscalls
+/// observer - hooks around executed instructions
 #[derive(Debug)]
-pub struct VirtualMachine {
+pub struct VirtualMachine<R = Stdin, W = Stdout, O = NoObserver> {
     memory     : Memory,
+    heap       : Heap,
+    syscalls   : Syscalls,
+    // symbols of loaded image
+    symbols    : BTreeMap<String, Symbol>,
+    input      : R,
+    output     : W,
+    observer   : O,
 
     ip         : Word, 
     sp         : Word,
@@ -38,10 +75,49 @@ pub struct VirtualMachine {
     dx         : Word,
 
     max_address: Word,
+    // words of stack, None - stack may grow till heap
+    stack_limit: Option<Word>,
+    // calls, which were not returned yet
+    depth      : Word,
+
+    limits     : Limits,
+    // executed instructions since loading of image
+    steps      : u64,
+    // time of first step after loading of image
+    started    : Option<Instant>,
+    // bytes passed through console streams by syscalls
+    output_bytes: u64,
+    input_bytes : u64,
+
+    // decoded instructions by address, None when word was not
+    // decoded yet, empty when predecode is off
+    decoded    : Vec<Option<Instruction>>,
+    // words from it on were not decoded
+    decoded_end: usize,
+    // compiler of hot code, None when it is off
+    #[cfg(feature = "jit")]
+    jit        : Option<Jit>,
+
+    yield_on_syscall: bool,
+}
+
+/// result of executing instructions
+#[derive(Debug)]
+pub enum StepOutcome {
+    /// program may continue
+    Continued,
+    /// program ended, ax is returned
+    Halted(Word),
+    /// program failed, ip points to faulting instruction
+    Trapped(VmError),
+    /// ip points to syscall with given code, which waits
+    /// for `complete_syscall`
+    SyscallPending(Word),
 }
 
 impl VirtualMachine {
 
+    /// vm with console syscalls bound to stdin and stdout
     pub fn with_memory(memory_size: Word) -> Self {
         let max_address = 
             memory_size / size_of::<Word>() as Word;
@@ -56,14 +132,124 @@ impl VirtualMachine {
             cx    : 0,
             dx    : 0,
             memory: vec![0; max_address as usize],
+            heap  : Heap::default(),
+            syscalls: Syscalls::with_defaults(),
+            symbols: BTreeMap::new(),
+            input : io::stdin(),
+            output: io::stdout(),
+            observer: NoObserver,
+            stack_limit: None,
+            depth : 0,
+            limits: Limits::default(),
+            steps : 0,
+            started: None,
+            output_bytes: 0,
+            input_bytes : 0,
+            decoded: Vec::new(),
+            decoded_end: 0,
+            #[cfg(feature = "jit")]
+            jit   : None,
+            yield_on_syscall: false,
         }
     }
 
     pub fn new() -> Self {
         Self::with_memory(DEFAULT_MEM_SIZE)
     }
+}
+
+impl Default for VirtualMachine {
+    fn default() -> Self {
+        Self::new()
+    }
+}
+
+impl<R: Read, W: Write, O: Observer> VirtualMachine<R, W, O> {
 
-    pub fn memory(&self) -> &Memory {
+    /// replaces stream read by console syscalls
+    pub fn with_input<I: Read>(self, input: I) -> VirtualMachine<I, W, O> {
+        self.map_parts(|_, output, observer| (input, output, observer))
+    }
+
+    /// replaces stream written by console syscalls
+    pub fn with_output<V: Write>(
+        self,
+        output: V,
+    ) -> VirtualMachine<R, V, O> {
+        self.map_parts(|input, _, observer| (input, output, observer))
+    }
+
+    /// replaces hooks called around executed instructions
+    pub fn with_observer<P: Observer>(
+        self,
+        observer: P,
+    ) -> VirtualMachine<R, W, P> {
+        self.map_parts(|input, output, _| (input, output, observer))
+    }
+
+    fn map_parts<I, V, P>(
+        self,
+        f: impl FnOnce(R, W, O) -> (I, V, P),
+    ) -> VirtualMachine<I, V, P> {
+        let (input, output, observer) =
+            f(self.input, self.output, self.observer);
+        VirtualMachine {
+            memory          : self.memory,
+            heap            : self.heap,
+            syscalls        : self.syscalls,
+            symbols         : self.symbols,
+            input,
+            output,
+            observer,
+            ip              : self.ip,
+            sp              : self.sp,
+            fp              : self.fp,
+            lp              : self.lp,
+            ax              : self.ax,
+            bx              : self.bx,
+            cx              : self.cx,
+            dx              : self.dx,
+            max_address     : self.max_address,
+            stack_limit     : self.stack_limit,
+            depth           : self.depth,
+            limits          : self.limits,
+            steps           : self.steps,
+            started         : self.started,
+            output_bytes    : self.output_bytes,
+            input_bytes     : self.input_bytes,
+            decoded         : self.decoded,
+            decoded_end     : self.decoded_end,
+            #[cfg(feature = "jit")]
+            jit             : self.jit,
+            yield_on_syscall: self.yield_on_syscall,
+        }
+    }
+
+    pub fn input(&self) -> &R {
+        &self.input
+    }
+
+    pub fn input_mut(&mut self) -> &mut R {
+        &mut self.input
+    }
+
+    pub fn output(&self) -> &W {
+        &self.output
+    }
+
+    pub fn output_mut(&mut self) -> &mut W {
+        &mut self.output
+    }
+
+    pub fn observer(&self) -> &O {
+        &self.observer
+    }
+
+    pub fn observer_mut(&mut self) -> &mut O {
+        &mut self.observer
+    }
+
+    pub fn memory(&self) -> &[Word] {
         &self.memory
     }
 
@@ -87,421 +273,1215 @@ impl VirtualMachine {
         self.lp
     }
 
-    pub fn load_image(&mut self, image: &Image) -> Result<(), &str>
-    {
-        if image.get_image().len() > self.max_address as usize {
-            return Err("image's size too large");
+    pub fn registers(&self) -> Registers {
+        Registers {
+            ip: self.ip,
+            sp: self.sp,
+            fp: self.fp,
+            lp: self.lp,
+            ax: self.ax,
+            bx: self.bx,
+            cx: self.cx,
+            dx: self.dx,
         }
+    }
 
+    fn set_registers(&mut self, registers: Registers) {
+        self.ip = registers.ip;
+        self.sp = registers.sp;
+        self.fp = registers.fp;
+        self.lp = registers.lp;
+        self.ax = registers.ax;
+        self.bx = registers.bx;
+        self.cx = registers.cx;
+        self.dx = registers.dx;
+    }
+
+    /// sets handler called by SYSCALL with code in ax,
+    /// returns previous handler of the code
+    pub fn register_syscall(
+        &mut self,
+        code: Word,
+        handler: impl SyscallHandler + 'static,
+    ) -> Option<Box<dyn SyscallHandler>> {
+        self.syscalls.register(code, handler)
+    }
+
+    pub fn unregister_syscall(
+        &mut self,
+        code: Word,
+    ) -> Option<Box<dyn SyscallHandler>> {
+        self.syscalls.unregister(code)
+    }
+
+    pub fn load_image(&mut self, image: &Image) -> Result<(), VmError>
+    {
         let i = image.get_image();
+        let word_size = size_of::<Word>() as Word;
+        // bss size set by builder is not checked
+        let required = (i.len() as Word).checked_add(image.get_bss_size());
+        let Some((required, bytes)) = required.and_then(|words| {
+            words.checked_mul(word_size).map(|bytes| (words, bytes))
+        }) else {
+            return Err(VmError::BssTooLarge { words: image.get_bss_size() });
+        };
+        let available = self.max_address * word_size;
+
+        if required > self.max_address ||
+           image.get_memory_requirement() > available
+        {
+            return Err(VmError::ImageTooLarge {
+                required: image.get_memory_requirement().max(bytes),
+                available,
+            });
+        }
 
         self
             .memory[..i.len()]
             .clone_from_slice(i);
 
+        self
+            .memory[i.len()..required as usize]
+            .fill(0);
+
         self.ip = image.get_entry_point();
+        self.heap = Heap::new(required);
+        self.invalidate_all();
+        if self.predecode() {
+            let code = image
+                .sections()
+                .into_iter()
+                .filter(|section| section.kind == SectionKind::Code);
+            for section in code {
+                let end = section.address + section.length;
+                for address in section.address..end {
+                    // operand may be missing at the end of memory
+                    let _ = self.decode(address);
+                }
+            }
+        }
+        self.symbols = image.get_symbols().clone();
+        self.reset();
 
         Ok(())
     }
 
-    pub fn execute(&mut self) -> Result<Word, ()> {
+    /// resets stack registers and counters of limits,
+    /// done by `load_image`
+    pub fn reset(&mut self) {
         self.sp = self.max_address;
         self.fp = self.sp;
-        self.lp = self.sp - 1;
+        self.lp = self.sp.wrapping_sub(1);
+        self.depth = 0;
+        self.steps = 0;
+        self.started = None;
+        self.output_bytes = 0;
+        self.input_bytes = 0;
+    }
 
-        while self.ip < self.max_address {
+    /// copy of full state of program, see `Snapshot`
+    pub fn snapshot(&self) -> Snapshot {
+        Snapshot {
+            registers   : self.registers(),
+            depth       : self.depth,
+            steps       : self.steps,
+            output_bytes: self.output_bytes,
+            input_bytes : self.input_bytes,
+            memory      : self.memory.clone(),
+            heap        : self.heap.clone(),
+            symbols     : self.symbols.clone(),
+        }
+    }
 
-            match self.memory[self.ip as usize] {
+    /// continues program from snapshot instead of loaded image,
+    /// memory of vm takes size of snapshot. Streams, syscalls and
+    /// limits of vm are kept, time limit is counted from now
+    pub fn restore(&mut self, snapshot: &Snapshot) {
+        self.memory = snapshot.memory.clone();
+        self.max_address = self.memory.len() as Word;
+        self.heap = snapshot.heap.clone();
+        self.symbols = snapshot.symbols.clone();
+        self.set_registers(snapshot.registers);
+        self.depth = snapshot.depth;
+        self.steps = snapshot.steps;
+        self.started = None;
+        self.output_bytes = snapshot.output_bytes;
+        self.input_bytes = snapshot.input_bytes;
+        self.invalidate_all();
+        if self.predecode() {
+            self.set_predecode(true);
+        }
+    }
 
-                OpCode::PUSH => {
-                    self.sp -= 1;
-                    self.memory[self.sp as usize] = self.cx;
-                }
+    pub fn set_limits(&mut self, limits: Limits) {
+        self.limits = limits;
+    }
 
-                OpCode::POP => {
-                    self.cx = self.memory[self.sp as usize];
-                    self.sp += 1;
-                }
+    pub fn limits(&self) -> &Limits {
+        &self.limits
+    }
 
-                OpCode::INC => {
-                    self.ax = self.ax.wrapping_add(1);
-                }
+    /// number of executed instructions, counted for fuel
+    pub fn steps(&self) -> u64 {
+        self.steps
+    }
 
-                OpCode::DEC => {
-                    self.ax = self.ax.wrapping_sub(1);
-                }
+    /// bytes written to output by syscalls
+    pub fn output_bytes(&self) -> u64 {
+        self.output_bytes
+    }
 
-                OpCode::NEG => {
-                    self.ax = self.ax.wrapping_neg();
-                }
+    /// bytes read from input by syscalls
+    pub fn input_bytes(&self) -> u64 {
+        self.input_bytes
+    }
 
-                OpCode::ADD => {
-                    self.ax = self.ax.wrapping_add(self.bx);
-                }
+    // traps when fuel or time of sandbox is over
+    fn check_limits(&mut self) -> Result<(), VmError> {
+        if let Some(limit) = self.limits.fuel {
+            if self.steps >= limit {
+                return Err(VmError::FuelExhausted {
+                    limit,
+                    registers: self.registers(),
+                });
+            }
+        }
 
-                OpCode::SUB => {
-                    self.ax = self.ax.wrapping_sub(self.bx);
-                }
+        if let Some(limit) = self.limits.time {
+            let started = *self.started.get_or_insert_with(Instant::now);
+            if self.steps.is_multiple_of(TIME_CHECK_INTERVAL) &&
+               started.elapsed() > limit
+            {
+                return Err(VmError::TimeLimitExceeded {
+                    limit,
+                    registers: self.registers(),
+                });
+            }
+        }
 
-                OpCode::MUL => {
-                    self.ax = self.ax.wrapping_mul(self.bx);
-                }
-                
-                OpCode::DIV => {
-                    self.dx = self.ax.wrapping_rem(self.bx);
-                    self.ax = self.ax.wrapping_div(self.bx);
-                }
+        Ok(())
+    }
 
-                OpCode::FNEG => {
-                    let ax: Real = self.ax.lexical_cast().unwrap();
-                    self.ax = (-ax).lexical_cast().unwrap();
-                }
+    /// limits stack to given number of words, heap may grow
+    /// only till the stack then. Without limit stack and heap
+    /// may take all free memory
+    pub fn set_stack_limit(&mut self, words: Option<Word>) {
+        self.stack_limit = words;
+    }
 
-                OpCode::FADD => {
-                    let ax: Real;
-                    let bx: Real;
-                    ax = self.ax.lexical_cast().unwrap();
-                    bx = self.bx.lexical_cast().unwrap();
-                    self.ax = (ax + bx).lexical_cast().unwrap();
-                }
+    pub fn stack_limit(&self) -> Option<Word> {
+        self.stack_limit
+    }
 
-                OpCode::FSUB => {
-                    let ax: Real;
-                    let bx: Real;
-                    ax = self.ax.lexical_cast().unwrap();
-                    bx = self.bx.lexical_cast().unwrap();
-                    self.ax = (ax - bx).lexical_cast().unwrap();
-                }
+    /// number of calls, which were not returned yet
+    pub fn call_depth(&self) -> Word {
+        self.depth
+    }
 
-                OpCode::FMUL => {
-                    let ax: Real;
-                    let bx: Real;
-                    ax = self.ax.lexical_cast().unwrap();
-                    bx = self.bx.lexical_cast().unwrap();
-                    self.ax = (ax * bx).lexical_cast().unwrap();
-                }
+    // lowest address, which stack may take,
+    // guard words above heap are never taken
+    fn stack_bottom(&self) -> Word {
+        let heap_end = self.heap.top().saturating_add(STACK_GUARD);
+        match self.stack_limit {
+            Some(limit) => self.max_address.saturating_sub(limit).max(heap_end),
+            None => heap_end,
+        }
+    }
 
-                OpCode::FDIV => {
-                    let ax: Real;
-                    let bx: Real;
-                    ax = self.ax.lexical_cast().unwrap();
-                    bx = self.bx.lexical_cast().unwrap();
-                    self.ax = (ax / bx).lexical_cast().unwrap();
-                }
+    // end of memory available for heap
+    fn heap_limit(&self) -> Word {
+        let stack_bottom = match self.stack_limit {
+            Some(limit) => self.max_address.saturating_sub(limit),
+            None => self.sp,
+        };
+        stack_bottom.saturating_sub(STACK_GUARD)
+    }
 
-                OpCode::AND => {
-                    self.ax &= self.bx;
-                }
+    pub fn is_halted(&self) -> bool {
+        self.ip >= self.max_address
+    }
 
-                OpCode::OR => {
-                    self.ax |= self.bx;
-                }
+    /// when set, instructions are decoded once with their operands
+    /// and executed from cache, which is invalidated by writes to
+    /// decoded words. Code of loaded image is decoded at once
+    pub fn set_predecode(&mut self, enabled: bool) {
+        self.decoded = if enabled {
+            vec![None; self.max_address as usize]
+        } else {
+            Vec::new()
+        };
+        self.invalidate_all();
+    }
 
-                OpCode::XOR => {
-                    self.ax ^= self.bx;
-                }
+    pub fn predecode(&self) -> bool {
+        !self.decoded.is_empty()
+    }
 
-                OpCode::NOT => {
-                    self.ax = !self.ax;
-                }
+    /// when set, hot blocks of code are compiled to x86-64 and run
+    /// by `execute` instead of interpreter, while observer is not
+    /// active. They are entered from predecoded stream, so predecode
+    /// is set too. Fails when executable memory can not be mapped
+    #[cfg(feature = "jit")]
+    pub fn set_jit(&mut self, enabled: bool) -> Result<(), String> {
+        self.jit = match enabled {
+            true => Some(Jit::new(self.max_address)?),
+            false => None,
+        };
+        if enabled && !self.predecode() {
+            self.set_predecode(true);
+        }
+        Ok(())
+    }
 
-                OpCode::SHL => {
-                    self.ax <<= self.bx;
-                }
+    #[cfg(feature = "jit")]
+    pub fn jit(&self) -> bool {
+        self.jit.is_some()
+    }
 
-                OpCode::SHR => {
-                    self.ax >>= self.bx;
-                }
+    /// instructions executed by compiled code
+    #[cfg(feature = "jit")]
+    pub fn jit_steps(&self) -> u64 {
+        self.jit.as_ref().map_or(0, Jit::steps)
+    }
 
-                OpCode::JMP => {
-                    self.ip = self.dx;
-                    continue;
-                }
+    // reads instruction at address with its operand
+    #[inline(always)]
+    fn read_instruction(&self, address: Word) -> Result<Instruction, VmError> {
+        let opcode = self.read(address)?;
+        let operand = if OpCode::has_operand(opcode) {
+            self.read(address.wrapping_add(1))?
+        } else {
+            0
+        };
+        Ok(Instruction::new(opcode, operand))
+    }
 
-                OpCode::JE => {
-                    if self.ax == self.bx {
-                        self.ip = self.dx;
-                        continue;
-                    }
-                }
+    // reads instruction at address into cache
+    fn decode(&mut self, address: Word) -> Result<Instruction, VmError> {
+        let instruction = self.read_instruction(address)?;
+        if let Some(decoded) = self.decoded.get_mut(address as usize) {
+            *decoded = Some(instruction);
+            self.decoded_end = self.decoded_end.max(address as usize + 1);
+        }
+        Ok(instruction)
+    }
 
-                OpCode::JNE => {
-                    if self.ax != self.bx {
-                        self.ip = self.dx;
-                        continue;
-                    }
-                }
+    // instruction at ip, from cache when predecode is on
+    #[inline(always)]
+    fn fetch(&mut self) -> Result<Instruction, VmError> {
+        if self.decoded.is_empty() {
+            return self.read_instruction(self.ip);
+        }
+        match self.decoded.get(self.ip as usize) {
+            Some(&Some(instruction)) => Ok(instruction),
+            _ => self.decode(self.ip),
+        }
+    }
 
-                OpCode::JG => {
-                    let ax: SWord = self.ax.lexical_cast().unwrap();
-                    let bx: SWord = self.ax.lexical_cast().unwrap();
-                    if ax > bx {
-                        self.ip = self.dx;
-                        continue;
-                    }
-                }
+    // words in range may be opcodes or operands of decoded
+    // instructions, so instruction before range is dropped too
+    fn invalidate(&mut self, start: Word, end: Word) {
+        #[cfg(feature = "jit")]
+        if let Some(jit) = &mut self.jit {
+            jit.invalidate(start, end);
+        }
+        let start = start.saturating_sub(1) as usize;
+        let end = (end as usize).min(self.decoded_end);
+        if start < end {
+            self.decoded[start..end].fill(None);
+        }
+    }
 
-                OpCode::JGE => {
-                    let ax: SWord = self.ax.lexical_cast().unwrap();
-                    let bx: SWord = self.ax.lexical_cast().unwrap();
-                    if ax >= bx {
-                        self.ip = self.dx;
-                        continue;
-                    }
-                }
+    fn invalidate_all(&mut self) {
+        #[cfg(feature = "jit")]
+        if let Some(jit) = &mut self.jit {
+            jit.refresh(&self.memory);
+        }
+        let end = self.decoded_end.min(self.decoded.len());
+        self.decoded[..end].fill(None);
+        self.decoded_end = 0;
+    }
 
-                OpCode::JL => {
-                    let ax: SWord = self.ax.lexical_cast().unwrap();
-                    let bx: SWord = self.ax.lexical_cast().unwrap();
-                    if ax < bx {
-                        self.ip = self.dx;
-                        continue;
-                    }
-                }
+    /// when set, `step` stops before every syscall and returns
+    /// `StepOutcome::SyscallPending`
+    pub fn set_yield_on_syscall(&mut self, value: bool) {
+        self.yield_on_syscall = value;
+    }
 
-                OpCode::JLE => {
-                    let ax: SWord = self.ax.lexical_cast().unwrap();
-                    let bx: SWord = self.ax.lexical_cast().unwrap();
-                    if ax <= bx {
-                        self.ip = self.dx;
-                        continue;
-                    }
-                }
+    /// runs program from current state until it ends
+    pub fn execute(&mut self) -> Result<Word, VmError> {
+        // same as repeated `step`, but limits are checked only when
+        // they may be exceeded, syscalls are never yielded here
+        loop {
+            if self.is_halted() {
+                return Ok(self.ax);
+            }
+            self.check_limits()?;
 
-                OpCode::JA => {
-                    if self.ax > self.bx {
-                        self.ip = self.dx;
-                        continue;
-                    }
-                }
+            let budget = self.budget();
+            if !O::ACTIVE && self.predecode() {
+                self.execute_stream(budget)?;
+                continue;
+            }
 
-                OpCode::JAE => {
-                    if self.ax >= self.bx {
-                        self.ip = self.dx;
-                        continue;
-                    }
+            for _ in 0..budget {
+                self.interpret()?;
+                if self.is_halted() {
+                    return Ok(self.ax);
                 }
+            }
+        }
+    }
 
-                OpCode::JB => {
-                    if self.ax < self.bx {
-                        self.ip = self.dx;
-                        continue;
-                    }
-                }
+    // executes instruction at ip with observer hooks
+    #[inline(always)]
+    fn interpret(&mut self) -> Result<(), VmError> {
+        self.observer.before(&self.registers(), &self.memory);
+        let result = self.execute_instruction();
+        self.steps += 1;
+        self.observer.after(&self.registers(), result.as_ref().err());
+        result
+    }
 
-                OpCode::JBE => {
-                    if self.ax <= self.bx {
-                        self.ip = self.dx;
-                        continue;
-                    }
-                }
+    // executes budget of predecoded instructions or till program ends
+    // without observer, steps are counted once at the end. Next ip is
+    // kept in local, so it is not read back from memory every step,
+    // inlined into `execute` the loop loses its registers.
+    // Compiled code is looked up at start and at targets of backward
+    // jumps, which start loops. Calls and returns are not counted,
+    // code between them is rarely compilable
+    #[inline(never)]
+    fn execute_stream(&mut self, budget: u64) -> Result<(), VmError> {
+        let mut steps = 0;
+        #[cfg(feature = "jit")]
+        let jit = self.jit.is_some();
+        #[cfg(feature = "jit")]
+        if jit {
+            steps += self.run_compiled(budget);
+        }
+        let mut ip = self.ip;
+        let result = loop {
+            if steps == budget {
+                break Ok(());
+            }
+            // ip is out of memory, program ended
+            let Some(&decoded) = self.decoded.get(ip as usize) else {
+                break Ok(());
+            };
+            steps += 1;
+            let instruction = match decoded {
+                Some(instruction) => instruction,
+                None => match self.decode(ip) {
+                    Ok(instruction) => instruction,
+                    Err(err) => break Err(err),
+                },
+            };
+            let next = match self.execute_decoded(ip, instruction) {
+                Ok(next) => next,
+                Err(err) => break Err(err),
+            };
+            self.ip = next;
+            #[cfg(feature = "jit")]
+            if jit && next <= ip && instruction.is_jump() {
+                steps += self.run_compiled(budget - steps);
+            }
+            ip = self.ip;
+        };
+        self.steps += steps;
+        result
+    }
 
-                OpCode::FJG => {
-                    let ax: Real = self.ax.lexical_cast().unwrap();
-                    let bx: Real = self.ax.lexical_cast().unwrap();
-                    if ax > bx {
-                        self.ip = self.dx;
-                        continue;
-                    }
-                }
+    // runs compiled blocks from ip while there are ones, returns
+    // executed instructions, which are added to steps by caller
+    #[cfg(feature = "jit")]
+    #[inline(never)]
+    fn run_compiled(&mut self, budget: u64) -> u64 {
+        let mut steps = 0;
+        while steps < budget {
+            match self.run_block(budget - steps) {
+                0 => break,
+                run => steps += run,
+            }
+        }
+        steps
+    }
 
-                OpCode::FJGE => {
-                    let ax: Real = self.ax.lexical_cast().unwrap();
-                    let bx: Real = self.ax.lexical_cast().unwrap();
-                    if ax >= bx {
-                        self.ip = self.dx;
-                        continue;
-                    }
-                }
+    // runs compiled block at ip, returns executed instructions
+    #[cfg(feature = "jit")]
+    fn run_block(&mut self, budget: u64) -> u64 {
+        let Some(jit) = &mut self.jit else {
+            return 0;
+        };
+        let Some(offset) = jit.block(self.ip, self.dx, &self.memory) else {
+            return 0;
+        };
+        let mut registers = Registers {
+            ip: self.ip,
+            sp: self.sp,
+            fp: self.fp,
+            lp: self.lp,
+            ax: self.ax,
+            bx: self.bx,
+            cx: self.cx,
+            dx: self.dx,
+        };
+        // stores of compiled code do not invalidate predecode cache,
+        // so they leave it below decoded words and operand after them
+        let guard = self.decoded_end as Word + 1;
+        let memory = &mut self.memory;
+        let steps = jit.run(offset, &mut registers, memory, guard, budget);
+        self.set_registers(registers);
+        steps
+    }
 
-                OpCode::FJL => {
-                    let ax: Real = self.ax.lexical_cast().unwrap();
-                    let bx: Real = self.ax.lexical_cast().unwrap();
-                    if ax < bx {
-                        self.ip = self.dx;
-                        continue;
-                    }
-                }
+    // instructions, which may run before limits are checked again
+    fn budget(&self) -> u64 {
+        let mut budget = match self.limits.fuel {
+            Some(limit) => limit - self.steps,
+            None => u64::MAX,
+        };
+        if self.limits.time.is_some() {
+            let left = TIME_CHECK_INTERVAL - self.steps % TIME_CHECK_INTERVAL;
+            budget = budget.min(left);
+        }
+        budget
+    }
 
-                OpCode::FJLE => {
-                    let ax: Real = self.ax.lexical_cast().unwrap();
-                    let bx: Real = self.ax.lexical_cast().unwrap();
-                    if ax <= bx {
-                        self.ip = self.dx;
-                        continue;
-                    }
-                }
+    /// calls procedure of loaded image and returns its ax.
+    ///
+    /// Calling convention: arguments are pushed in reverse order,
+    /// so the first one is the nearest to the return address,
+    /// then return address is pushed by CALL. Procedure starts
+    /// with `enter n`, after which `[fp+2]` is the first argument,
+    /// `[fp+3]` the second and so on, locals are `[lp+0]` ..
+    /// `[lp+n-1]`. It returns by `leave` and RET with result in ax
+    /// and does not have to pop arguments.
+    ///
+    /// Return address is `address()`, which ends the call as halt
+    /// does. Registers ip, sp, fp, lp and call depth are restored
+    /// after the call, even a failed one, memory and heap are kept,
+    /// so procedures may be called one after another.
+    pub fn call(
+        &mut self,
+        address: Word,
+        args: &[Word],
+    ) -> Result<Word, VmError> {
+        if address >= self.max_address {
+            return Err(VmError::BadAddress {
+                address,
+                registers: self.registers(),
+            });
+        }
 
-                OpCode::CALL => {
-                    self.sp -= 1;
-                    self.memory[self.sp as usize] = self.ip + 1;
-                    self.ip = self.dx;
-                    continue;
-                }
+        let saved = self.registers();
+        let depth = self.depth;
+        let result = self.enter_call(address, args);
 
-                OpCode::RET => {
-                    self.ip = self.memory[self.sp as usize];
-                    self.sp += 1;
-                    continue;
-                }
+        self.depth = depth;
+        self.ip = saved.ip;
+        self.sp = saved.sp;
+        self.fp = saved.fp;
+        self.lp = saved.lp;
+        result
+    }
 
-                OpCode::SYSCALL => {
-                    self.syscall();
-                }
+    /// calls procedure by name of symbol of loaded image
+    pub fn call_symbol(
+        &mut self,
+        name: &str,
+        args: &[Word],
+    ) -> Result<Word, VmError> {
+        let Some(symbol) = self.symbol(name) else {
+            return Err(VmError::UnknownSymbol {
+                name: name.to_string(),
+            });
+        };
+        self.call(symbol.address, args)
+    }
 
-                OpCode::MOVE_OP_TO_AX => {
-                    self.ip += 1;
-                    self.ax = self.memory[self.ip as usize];
-                }
+    pub fn symbol(&self, name: &str) -> Option<Symbol> {
+        self.symbols.get(name).copied()
+    }
 
-                OpCode::MOVE_OP_TO_BX => {
-                    self.ip += 1;
-                    self.bx = self.memory[self.ip as usize];
-                }
+    /// symbols of loaded image or restored snapshot
+    pub fn symbols(&self) -> &BTreeMap<String, Symbol> {
+        &self.symbols
+    }
 
-                OpCode::MOVE_OP_TO_CX => {
-                    self.ip += 1;
-                    self.cx = self.memory[self.ip as usize];
-                }
+    fn enter_call(
+        &mut self,
+        address: Word,
+        args: &[Word],
+    ) -> Result<Word, VmError> {
+        for &arg in args.iter().rev() {
+            self.push(arg)?;
+        }
+        self.push(self.max_address)?;
+        self.depth += 1;
+        self.ip = address;
+        self.execute()
+    }
 
-                OpCode::MOVE_OP_TO_DX => {
-                    self.ip += 1;
-                    self.dx = self.memory[self.ip as usize];
-                }
+    /// executes at most `count` instructions,
+    /// `Continued` means that budget is exhausted
+    pub fn run_for(&mut self, count: u64) -> StepOutcome {
+        for _ in 0..count {
+            match self.step() {
+                StepOutcome::Continued => (),
+                outcome => return outcome,
+            }
+        }
+        if self.is_halted() {
+            StepOutcome::Halted(self.ax)
+        } else {
+            StepOutcome::Continued
+        }
+    }
+
+    /// executes single instruction at ip
+    pub fn step(&mut self) -> StepOutcome {
+        if self.is_halted() {
+            return StepOutcome::Halted(self.ax);
+        }
+
+        if let Err(err) = self.check_limits() {
+            return StepOutcome::Trapped(err);
+        }
 
-                OpCode::MOVE_BX_TO_AX => {
-                    self.ax = self.bx;
+        if self.yield_on_syscall &&
+           self.memory[self.ip as usize] == OpCode::SYSCALL
+        {
+            return StepOutcome::SyscallPending(self.ax);
+        }
+
+        self.observer.before(&self.registers(), &self.memory);
+        let result = self.execute_instruction();
+        self.steps += 1;
+        self.observer.after(&self.registers(), result.as_ref().err());
+        self.outcome(result)
+    }
+
+    /// executes syscall, which `step` stopped at
+    pub fn complete_syscall(&mut self) -> StepOutcome {
+        let opcode = self.memory.get(self.ip as usize).copied();
+        if opcode != Some(OpCode::SYSCALL) {
+            return StepOutcome::Trapped(VmError::InvalidOpcode {
+                opcode   : opcode.unwrap_or_default(),
+                registers: self.registers(),
+            });
+        }
+
+        self.observer.before(&self.registers(), &self.memory);
+        let result = self.syscall().map(|()| self.ip += 1);
+        self.steps += 1;
+        self.observer.after(&self.registers(), result.as_ref().err());
+        self.outcome(result)
+    }
+
+    fn outcome(&self, result: Result<(), VmError>) -> StepOutcome {
+        match result {
+            Ok(()) if self.is_halted() => StepOutcome::Halted(self.ax),
+            Ok(()) => StepOutcome::Continued,
+            Err(err) => StepOutcome::Trapped(err),
+        }
+    }
+
+    fn execute_instruction(&mut self) -> Result<(), VmError> {
+        let instruction = self.fetch()?;
+        self.ip = self.execute_decoded(self.ip, instruction)?;
+        Ok(())
+    }
+
+    // executes instruction at ip, which is decoded already, returns
+    // address of the next one. Ip is passed in register, it is not
+    // changed here, but must be set for errors and syscalls
+    #[inline(always)]
+    fn execute_decoded(
+        &mut self,
+        ip: Word,
+        instruction: Instruction,
+    ) -> Result<Word, VmError> {
+        match instruction {
+
+            Instruction::Push => {
+                self.push(self.cx)?;
+            }
+
+            Instruction::Pop => {
+                self.cx = self.pop()?;
+            }
+
+            Instruction::Inc => {
+                self.ax = self.ax.wrapping_add(1);
+            }
+
+            Instruction::Dec => {
+                self.ax = self.ax.wrapping_sub(1);
+            }
+
+            Instruction::Neg => {
+                self.ax = self.ax.wrapping_neg();
+            }
+
+            Instruction::Add => {
+                self.ax = self.ax.wrapping_add(self.bx);
+            }
+
+            Instruction::Sub => {
+                self.ax = self.ax.wrapping_sub(self.bx);
+            }
+
+            Instruction::Mul => {
+                self.ax = self.ax.wrapping_mul(self.bx);
+            }
+            
+            Instruction::Div => {
+                if self.bx == 0 {
+                    return Err(VmError::DivisionByZero {
+                        registers: self.registers(),
+                    });
                 }
+                self.dx = self.ax.wrapping_rem(self.bx);
+                self.ax = self.ax.wrapping_div(self.bx);
+            }
+
+            Instruction::FNeg => {
+                let ax: Real = self.ax.lexical_cast().unwrap();
+                self.ax = (-ax).lexical_cast().unwrap();
+            }
+
+            Instruction::FAdd => {
+                let ax: Real = self.ax.lexical_cast().unwrap();
+                let bx: Real = self.bx.lexical_cast().unwrap();
+                self.ax = (ax + bx).lexical_cast().unwrap();
+            }
+
+            Instruction::FSub => {
+                let ax: Real = self.ax.lexical_cast().unwrap();
+                let bx: Real = self.bx.lexical_cast().unwrap();
+                self.ax = (ax - bx).lexical_cast().unwrap();
+            }
+
+            Instruction::FMul => {
+                let ax: Real = self.ax.lexical_cast().unwrap();
+                let bx: Real = self.bx.lexical_cast().unwrap();
+                self.ax = (ax * bx).lexical_cast().unwrap();
+            }
+
+            Instruction::FDiv => {
+                let ax: Real = self.ax.lexical_cast().unwrap();
+                let bx: Real = self.bx.lexical_cast().unwrap();
+                self.ax = (ax / bx).lexical_cast().unwrap();
+            }
+
+            Instruction::And => {
+                self.ax &= self.bx;
+            }
+
+            Instruction::Or => {
+                self.ax |= self.bx;
+            }
+
+            Instruction::Xor => {
+                self.ax ^= self.bx;
+            }
+
+            Instruction::Not => {
+                self.ax = !self.ax;
+            }
+
+            Instruction::Shl => {
+                self.ax = self.ax.wrapping_shl(self.bx as u32);
+            }
 
-                OpCode::MOVE_CX_TO_AX => {
-                    self.ax = self.cx;
+            Instruction::Shr => {
+                self.ax = self.ax.wrapping_shr(self.bx as u32);
+            }
+
+            Instruction::Jmp => {
+                return Ok(self.dx);
+            }
+
+            Instruction::Je => {
+                if self.ax == self.bx {
+                    return Ok(self.dx);
                 }
+            }
 
-                OpCode::MOVE_DX_TO_AX => {
-                    self.ax = self.dx;
+            Instruction::Jne => {
+                if self.ax != self.bx {
+                    return Ok(self.dx);
                 }
+            }
 
-                OpCode::MOVE_AX_TO_BX => {
-                    self.bx = self.ax;
+            Instruction::Jg => {
+                let ax: SWord = self.ax.lexical_cast().unwrap();
+                let bx: SWord = self.bx.lexical_cast().unwrap();
+                if ax > bx {
+                    return Ok(self.dx);
                 }
+            }
 
-                OpCode::MOVE_CX_TO_BX => {
-                    self.bx = self.cx;
+            Instruction::Jge => {
+                let ax: SWord = self.ax.lexical_cast().unwrap();
+                let bx: SWord = self.bx.lexical_cast().unwrap();
+                if ax >= bx {
+                    return Ok(self.dx);
                 }
+            }
 
-                OpCode::MOVE_DX_TO_BX => {
-                    self.bx = self.dx;
+            Instruction::Jl => {
+                let ax: SWord = self.ax.lexical_cast().unwrap();
+                let bx: SWord = self.bx.lexical_cast().unwrap();
+                if ax < bx {
+                    return Ok(self.dx);
                 }
+            }
 
-                OpCode::MOVE_AX_TO_CX => {
-                    self.cx = self.ax;
+            Instruction::Jle => {
+                let ax: SWord = self.ax.lexical_cast().unwrap();
+                let bx: SWord = self.bx.lexical_cast().unwrap();
+                if ax <= bx {
+                    return Ok(self.dx);
                 }
+            }
 
-                OpCode::MOVE_BX_TO_CX => {
-                    self.cx = self.bx;
+            Instruction::Ja => {
+                if self.ax > self.bx {
+                    return Ok(self.dx);
                 }
+            }
 
-                OpCode::MOVE_DX_TO_CX => {
-                    self.cx = self.dx;
+            Instruction::Jae => {
+                if self.ax >= self.bx {
+                    return Ok(self.dx);
                 }
+            }
 
-                OpCode::MOVE_AX_TO_DX => {
-                    self.dx = self.ax;
+            Instruction::Jb => {
+                if self.ax < self.bx {
+                    return Ok(self.dx);
                 }
+            }
 
-                OpCode::MOVE_BX_TO_DX => {
-                    self.dx = self.bx;
+            Instruction::Jbe => {
+                if self.ax <= self.bx {
+                    return Ok(self.dx);
                 }
+            }
 
-                OpCode::MOVE_CX_TO_DX => {
-                    self.dx = self.cx;
+            Instruction::FJg => {
+                let ax: Real = self.ax.lexical_cast().unwrap();
+                let bx: Real = self.bx.lexical_cast().unwrap();
+                if ax > bx {
+                    return Ok(self.dx);
                 }
+            }
 
-                OpCode::CWTOR => {
-                    let val = self.ax as Real;
-                    self.ax = val.lexical_cast().unwrap();
+            Instruction::FJge => {
+                let ax: Real = self.ax.lexical_cast().unwrap();
+                let bx: Real = self.bx.lexical_cast().unwrap();
+                if ax >= bx {
+                    return Ok(self.dx);
                 }
+            }
 
-                OpCode::CSWTOR => {
-                    let sw_val: SWord = self.ax.lexical_cast().unwrap();
-                    let r_val = sw_val as Real;
-                    self.ax = r_val.lexical_cast().unwrap();
+            Instruction::FJl => {
+                let ax: Real = self.ax.lexical_cast().unwrap();
+                let bx: Real = self.bx.lexical_cast().unwrap();
+                if ax < bx {
+                    return Ok(self.dx);
                 }
+            }
 
-                OpCode::CRTOW => {
-                    let val: Real = self.ax.lexical_cast().unwrap();
-                    self.ax = val as Word;
+            Instruction::FJle => {
+                let ax: Real = self.ax.lexical_cast().unwrap();
+                let bx: Real = self.bx.lexical_cast().unwrap();
+                if ax <= bx {
+                    return Ok(self.dx);
                 }
+            }
+
+            Instruction::Call => {
+                self.push(ip + 1)?;
+                self.depth += 1;
+                return Ok(self.dx);
+            }
+
+            Instruction::Ret => {
+                let ip = self.pop()?;
+                self.depth = self.depth.saturating_sub(1);
+                return Ok(ip);
+            }
+
+            Instruction::Syscall => {
+                // handler may move ip
+                self.syscall()?;
+                return Ok(self.ip + 1);
+            }
+
+            Instruction::MoveOpToAx(value) => {
+                self.ax = value;
+                return Ok(ip + 2);
+            }
+
+            Instruction::MoveOpToBx(value) => {
+                self.bx = value;
+                return Ok(ip + 2);
+            }
+
+            Instruction::MoveOpToCx(value) => {
+                self.cx = value;
+                return Ok(ip + 2);
+            }
+
+            Instruction::MoveOpToDx(value) => {
+                self.dx = value;
+                return Ok(ip + 2);
+            }
+
+            Instruction::MoveBxToAx => {
+                self.ax = self.bx;
+            }
+
+            Instruction::MoveCxToAx => {
+                self.ax = self.cx;
+            }
+
+            Instruction::MoveDxToAx => {
+                self.ax = self.dx;
+            }
+
+            Instruction::MoveAxToBx => {
+                self.bx = self.ax;
+            }
+
+            Instruction::MoveCxToBx => {
+                self.bx = self.cx;
+            }
+
+            Instruction::MoveDxToBx => {
+                self.bx = self.dx;
+            }
 
-                OpCode::CRTOSW => {
-                    let r_val: Real = self.ax.lexical_cast().unwrap();
-                    let sw_val = r_val as SWord;
-                    self.ax = sw_val.lexical_cast().unwrap();
+            Instruction::MoveAxToCx => {
+                self.cx = self.ax;
+            }
+
+            Instruction::MoveBxToCx => {
+                self.cx = self.bx;
+            }
+
+            Instruction::MoveDxToCx => {
+                self.cx = self.dx;
+            }
+
+            Instruction::MoveAxToDx => {
+                self.dx = self.ax;
+            }
+
+            Instruction::MoveBxToDx => {
+                self.dx = self.bx;
+            }
+
+            Instruction::MoveCxToDx => {
+                self.dx = self.cx;
+            }
+
+            Instruction::Cwtor => {
+                let val = self.ax as Real;
+                self.ax = val.lexical_cast().unwrap();
+            }
+
+            Instruction::Cswtor => {
+                let sw_val: SWord = self.ax.lexical_cast().unwrap();
+                let r_val = sw_val as Real;
+                self.ax = r_val.lexical_cast().unwrap();
+            }
+
+            Instruction::Crtow => {
+                let val: Real = self.ax.lexical_cast().unwrap();
+                self.ax = val as Word;
+            }
+
+            Instruction::Crtosw => {
+                let r_val: Real = self.ax.lexical_cast().unwrap();
+                let sw_val = r_val as SWord;
+                self.ax = sw_val.lexical_cast().unwrap();
+            }
+
+            Instruction::Deref => {
+                if self.ax == 0 {
+                    return Err(VmError::BadAddress {
+                        address  : 0,
+                        registers: self.registers(),
+                    });
                 }
+                self.ax = self.read(self.ax)?;
+            }
 
-                OpCode::DEREF => {
-                    if self.ax == 0 {
-                        panic!("Zero pointer dereference")
+            Instruction::Malloc => {
+                let word_size = size_of::<Word>() as Word;
+                let words = self.cx.div_ceil(word_size);
+                if let Some(limit) = self.limits.heap_bytes {
+                    let allocated = self.heap.allocated() + words.max(1);
+                    if allocated.saturating_mul(word_size) > limit {
+                        return Err(VmError::HeapLimitExceeded {
+                            limit,
+                            registers: self.registers(),
+                        });
+                    }
+                }
+                match self.heap.allocate(words, self.heap_limit()) {
+                    Some(address) => {
+                        let end = address + words.max(1);
+                        self.memory[address as usize..end as usize].fill(0);
+                        self.invalidate(address, end);
+                        if O::ACTIVE {
+                            for word in address..end {
+                                self.observer.write(word, 0);
+                            }
+                        }
+                        self.ax = address;
                     }
-                    self.ax = self.memory[self.ax as usize];
+                    None => self.ax = 0,
                 }
+            }
 
-                opcode => panic!("Unknown opcode: {opcode}")
-            }
-
-            self.ip += 1;
-        }
-
-        Ok(self.ax)
-    } 
-
-    fn syscall(&mut self) {
-        match self.ax {
-            // print string in console
-            // every char in unicode and stores in Word
-            // dx - address of first char
-            // cx - length of string
-            // ax - num of printed bytes
-            0 => {
-                self.ax = 0;
-                while self.cx > 0 {
-                    print!(
-                        "{}", 
-                        self
-                            .memory[self.dx as usize]
-                            .into_char()
-                            .unwrap()
-                    );
-                    self.dx += 1;
-                    self.cx -= 1;
-                    self.ax += 1;
+            Instruction::Free => {
+                if let Err(err) = self.heap.free(self.ax) {
+                    let registers = self.registers();
+                    return Err(match err {
+                        FreeError::DoubleFree => VmError::DoubleFree {
+                            address: self.ax,
+                            registers,
+                        },
+                        FreeError::InvalidFree => VmError::InvalidFree {
+                            address: self.ax,
+                            registers,
+                        },
+                    });
                 }
-                io::stdout().flush().unwrap();
-            }
-
-            // get line from console
-            // dx - address of buffer
-            // ax - num of gotten bytes
-            1 => {
-                self.ax = 0;
-                let mut buf = String::new();
-                io::stdin().read_line(&mut buf).unwrap();
-                for c in buf.chars() {
-                    self.memory[self.dx as usize] = c as Word;
-                    self.dx += 1;
-                    self.ax += 1;
+            }
+
+            Instruction::Store => {
+                self.write(self.dx, self.ax)?;
+            }
+
+            Instruction::LoadDxOff(offset) => {
+                self.ax = self.read(self.dx.wrapping_add(offset))?;
+                return Ok(ip + 2);
+            }
+
+            Instruction::StoreDxOff(offset) => {
+                self.write(self.dx.wrapping_add(offset), self.ax)?;
+                return Ok(ip + 2);
+            }
+
+            Instruction::LoadFpOff(offset) => {
+                self.ax = self.read(self.fp.wrapping_add(offset))?;
+                return Ok(ip + 2);
+            }
+
+            Instruction::StoreFpOff(offset) => {
+                self.write(self.fp.wrapping_add(offset), self.ax)?;
+                return Ok(ip + 2);
+            }
+
+            Instruction::LoadLpOff(offset) => {
+                self.ax = self.read(self.lp.wrapping_add(offset))?;
+                return Ok(ip + 2);
+            }
+
+            Instruction::StoreLpOff(offset) => {
+                self.write(self.lp.wrapping_add(offset), self.ax)?;
+                return Ok(ip + 2);
+            }
+
+            Instruction::Enter(size) => {
+                self.push(self.fp)?;
+                self.fp = self.sp;
+                self.push(self.lp)?;
+                if self.sp.saturating_sub(size) < self.stack_bottom() {
+                    return Err(self.stack_overflow());
                 }
+                self.sp -= size;
+                self.lp = self.sp;
+                return Ok(ip + 2);
             }
 
-            // end of program
-            // dx - return value
-            2 => {
-                self.ax = self.dx;
-                self.ip = self.max_address;
+            Instruction::Leave => {
+                self.sp = self.fp.wrapping_sub(1);
+                self.lp = self.pop()?;
+                self.fp = self.pop()?;
+            }
+
+            Instruction::MoveSpToAx => {
+                self.ax = self.sp;
+            }
+
+            Instruction::MoveFpToAx => {
+                self.ax = self.fp;
             }
 
-            _ => panic!("Unknown syscall")
+            Instruction::MoveLpToAx => {
+                self.ax = self.lp;
+            }
+
+            Instruction::MoveAxToSp => {
+                self.sp = self.ax;
+            }
+
+            Instruction::MoveAxToFp => {
+                self.fp = self.ax;
+            }
+
+            Instruction::MoveAxToLp => {
+                self.lp = self.ax;
+            }
+
+            Instruction::LoadDxBx => {
+                self.ax = self.read(self.dx.wrapping_add(self.bx))?;
+            }
+
+            Instruction::StoreDxBx => {
+                self.write(self.dx.wrapping_add(self.bx), self.ax)?;
+            }
+
+            Instruction::Invalid(opcode) => return Err(VmError::InvalidOpcode {
+                opcode,
+                registers: self.registers(),
+            })
+        }
+
+        Ok(ip + 1)
+    }
+
+    fn read(&self, address: Word) -> Result<Word, VmError> {
+        match self.memory.get(address as usize) {
+            Some(&value) => Ok(value),
+            None => Err(VmError::BadAddress {
+                address,
+                registers: self.registers(),
+            }),
+        }
+    }
+
+    fn write(&mut self, address: Word, value: Word) -> Result<(), VmError> {
+        if address >= self.max_address {
+            return Err(VmError::BadAddress {
+                address,
+                registers: self.registers(),
+            });
+        }
+        self.memory[address as usize] = value;
+        self.invalidate(address, address + 1);
+        self.observer.write(address, value);
+        Ok(())
+    }
+
+    #[inline]
+    fn push(&mut self, value: Word) -> Result<(), VmError> {
+        if self.sp > self.max_address {
+            return Err(self.stack_underflow());
+        }
+        if self.sp <= self.stack_bottom() {
+            return Err(self.stack_overflow());
+        }
+        self.sp -= 1;
+        self.memory[self.sp as usize] = value;
+        self.invalidate(self.sp, self.sp + 1);
+        self.observer.write(self.sp, value);
+        Ok(())
+    }
+
+    #[inline]
+    fn pop(&mut self) -> Result<Word, VmError> {
+        if self.sp >= self.max_address {
+            return Err(self.stack_underflow());
+        }
+        let value = self.memory[self.sp as usize];
+        self.sp += 1;
+        Ok(value)
+    }
+
+    fn stack_overflow(&self) -> VmError {
+        VmError::StackOverflow {
+            depth    : self.depth,
+            registers: self.registers(),
+        }
+    }
+
+    fn stack_underflow(&self) -> VmError {
+        VmError::StackUnderflow {
+            depth    : self.depth,
+            registers: self.registers(),
         }
     }
 
-}
\ No newline at end of file
+    fn syscall(&mut self) -> Result<(), VmError> {
+        let mut registers = self.registers();
+        let Some(handler) = self.syscalls.get_mut(self.ax) else {
+            return Err(VmError::BadSyscall {
+                code: self.ax,
+                registers,
+            });
+        };
+
+        let mut input = LimitedReader {
+            inner   : &mut self.input,
+            count   : &mut self.input_bytes,
+            limit   : self.limits.input_bytes,
+            exceeded: false,
+        };
+        let mut output = LimitedWriter {
+            inner   : &mut self.output,
+            count   : &mut self.output_bytes,
+            limit   : self.limits.output_bytes,
+            exceeded: false,
+        };
+        let mut context = SyscallContext {
+            registers: &mut registers,
+            input    : &mut input,
+            output   : &mut output,
+            memory   : &mut self.memory,
+            written  : None,
+            writes   : O::ACTIVE.then(Vec::new),
+        };
+        let result = handler.call(&mut context);
+        let written = context.written;
+        let writes = context.writes.take().unwrap_or_default();
+        let (input_exceeded, output_exceeded) =
+            (input.exceeded, output.exceeded);
+
+        // words written by handler are dropped from caches on every
+        // return, even a failed call may have written some
+        if let Some((start, end)) = written {
+            self.invalidate(start, end);
+        }
+        for (address, value) in writes {
+            self.observer.write(address, value);
+        }
+
+        // limits are reported instead of i/o errors they caused
+        if let (true, Some(limit)) = (input_exceeded, self.limits.input_bytes) {
+            return Err(VmError::InputLimitExceeded {
+                limit,
+                registers: self.registers(),
+            });
+        }
+        if let (true, Some(limit)) =
+            (output_exceeded, self.limits.output_bytes)
+        {
+            return Err(VmError::OutputLimitExceeded {
+                limit,
+                registers: self.registers(),
+            });
+        }
+        result?;
+
+        self.set_registers(registers);
+        Ok(())
+    }
+
+}
@@ -0,0 +1,30 @@
+use super::{
+    error::{Registers, VmError},
+    Word,
+};
+
+/// hooks called by vm around every executed instruction.
+/// Vm is generic over observer, so `NoObserver` costs nothing
+pub trait Observer {
+    /// false for observers without hooks, instructions may run
+    /// without calls of them then, like in compiled code
+    const ACTIVE: bool = true;
+
+    /// called before instruction at `registers.ip` is executed
+    fn before(&mut self, _registers: &Registers, _memory: &[Word]) {}
+
+    /// word written by executed instruction, including words
+    /// written by syscalls and zeroed by MALLOC
+    fn write(&mut self, _address: Word, _value: Word) {}
+
+    /// called after instruction, error is set when it trapped
+    fn after(&mut self, _registers: &Registers, _error: Option<&VmError>) {}
+}
+
+/// observer of vm without hooks
+#[derive(Debug, Clone, Copy, Default)]
+pub struct NoObserver;
+
+impl Observer for NoObserver {
+    const ACTIVE: bool = false;
+}
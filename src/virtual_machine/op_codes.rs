@@ -1,11 +1,12 @@
 use super::Word;
 
+// bumped when instructions are added or changed
+pub const ISA_VERSION: u16 = 4;
+
 pub struct OpCode;
 
 impl OpCode {
-    #[allow(dead_code)]
     pub const CODE_MASK: Word = 0b000011111111;
-    #[allow(dead_code)]
     pub const TYPE_MASK: Word = 0b111000000000;
 
     // stack operations, operand in cx
@@ -99,8 +100,169 @@ impl OpCode {
 
     // heap operations
     // cx - number of bytes
-    // ax - address
-    pub const MALLOC        : Word = 60;
-    pub const FREE        : Word = 60;
+    // ax - address, 0 when memory is exhausted
+    pub const MALLOC       : Word = 60;
+    pub const FREE         : Word = 68;
+
+    // loads and stores
+    // ax - value
+    // operand - signed offset from base
+    // *dx = ax
+    pub const STORE        : Word = 61;
+    // ax = *(dx + op)
+    pub const LOAD_DX_OFF  : Word = 62;
+    // *(dx + op) = ax
+    pub const STORE_DX_OFF : Word = 63;
+    // ax = *(fp + op)
+    pub const LOAD_FP_OFF  : Word = 64;
+    // *(fp + op) = ax
+    pub const STORE_FP_OFF : Word = 65;
+    // ax = *(dx + bx)
+    pub const LOAD_DX_BX   : Word = 66;
+    // *(dx + bx) = ax
+    pub const STORE_DX_BX  : Word = 67;
+    // ax = *(lp + op)
+    pub const LOAD_LP_OFF  : Word = 71;
+    // *(lp + op) = ax
+    pub const STORE_LP_OFF : Word = 72;
+
+    // stack frames
+    // enter: push fp, fp = sp, push lp, sp -= op, lp = sp
+    //   [fp] - saved fp, [fp+1] - return address, [fp+2] - first argument
+    //   [fp-1] - saved lp, [lp] .. [lp+op-1] - local variables
+    // leave: sp = fp - 1, pop lp, pop fp
+    pub const ENTER        : Word = 69;
+    pub const LEAVE        : Word = 70;
+
+    // moves between ax and stack registers
+    pub const MOVE_SP_TO_AX: Word = 73;
+    pub const MOVE_FP_TO_AX: Word = 74;
+    pub const MOVE_LP_TO_AX: Word = 75;
+    pub const MOVE_AX_TO_SP: Word = 76;
+    pub const MOVE_AX_TO_FP: Word = 77;
+    pub const MOVE_AX_TO_LP: Word = 78;
+
+    // textual form of every instruction as printed by disassembler
+    // and accepted by assembler, `#` marks the inline operand,
+    // `~` marks the inline signed offset
+    pub const MNEMONICS: &'static [(Word, &'static str)] = &[
+        (Self::PUSH         , "push"),
+        (Self::POP          , "pop"),
+        (Self::INC          , "inc"),
+        (Self::DEC          , "dec"),
+        (Self::NEG          , "neg"),
+        (Self::ADD          , "add"),
+        (Self::SUB          , "sub"),
+        (Self::MUL          , "mul"),
+        (Self::DIV          , "div"),
+        (Self::FNEG         , "fneg"),
+        (Self::FADD         , "fadd"),
+        (Self::FSUB         , "fsub"),
+        (Self::FMUL         , "fmul"),
+        (Self::FDIV         , "fdiv"),
+        (Self::AND          , "and"),
+        (Self::OR           , "or"),
+        (Self::XOR          , "xor"),
+        (Self::NOT          , "not"),
+        (Self::SHL          , "shl"),
+        (Self::SHR          , "shr"),
+        (Self::JMP          , "jmp"),
+        (Self::JE           , "je"),
+        (Self::JNE          , "jne"),
+        (Self::JG           , "jg"),
+        (Self::JGE          , "jge"),
+        (Self::JL           , "jl"),
+        (Self::JLE          , "jle"),
+        (Self::JA           , "ja"),
+        (Self::JAE          , "jae"),
+        (Self::JB           , "jb"),
+        (Self::JBE          , "jbe"),
+        (Self::FJG          , "fjg"),
+        (Self::FJGE         , "fjge"),
+        (Self::FJL          , "fjl"),
+        (Self::FJLE         , "fjle"),
+        (Self::CALL         , "call"),
+        (Self::RET          , "ret"),
+        (Self::SYSCALL      , "syscall"),
+        (Self::MOVE_OP_TO_AX, "mov ax, #"),
+        (Self::MOVE_OP_TO_BX, "mov bx, #"),
+        (Self::MOVE_OP_TO_CX, "mov cx, #"),
+        (Self::MOVE_OP_TO_DX, "mov dx, #"),
+        (Self::MOVE_BX_TO_AX, "mov ax, bx"),
+        (Self::MOVE_CX_TO_AX, "mov ax, cx"),
+        (Self::MOVE_DX_TO_AX, "mov ax, dx"),
+        (Self::MOVE_AX_TO_BX, "mov bx, ax"),
+        (Self::MOVE_CX_TO_BX, "mov bx, cx"),
+        (Self::MOVE_DX_TO_BX, "mov bx, dx"),
+        (Self::MOVE_AX_TO_CX, "mov cx, ax"),
+        (Self::MOVE_BX_TO_CX, "mov cx, bx"),
+        (Self::MOVE_DX_TO_CX, "mov cx, dx"),
+        (Self::MOVE_AX_TO_DX, "mov dx, ax"),
+        (Self::MOVE_BX_TO_DX, "mov dx, bx"),
+        (Self::MOVE_CX_TO_DX, "mov dx, cx"),
+        (Self::CWTOR        , "cwtor"),
+        (Self::CSWTOR       , "cswtor"),
+        (Self::CRTOW        , "crtow"),
+        (Self::CRTOSW       , "crtosw"),
+        (Self::DEREF        , "deref"),
+        (Self::MALLOC       , "malloc"),
+        (Self::FREE         , "free"),
+        (Self::STORE        , "store [dx]"),
+        (Self::LOAD_DX_OFF  , "load [dx~]"),
+        (Self::STORE_DX_OFF , "store [dx~]"),
+        (Self::LOAD_FP_OFF  , "load [fp~]"),
+        (Self::STORE_FP_OFF , "store [fp~]"),
+        (Self::LOAD_DX_BX   , "load [dx+bx]"),
+        (Self::STORE_DX_BX  , "store [dx+bx]"),
+        (Self::LOAD_LP_OFF  , "load [lp~]"),
+        (Self::STORE_LP_OFF , "store [lp~]"),
+        (Self::ENTER        , "enter #"),
+        (Self::LEAVE        , "leave"),
+        (Self::MOVE_SP_TO_AX, "mov ax, sp"),
+        (Self::MOVE_FP_TO_AX, "mov ax, fp"),
+        (Self::MOVE_LP_TO_AX, "mov ax, lp"),
+        (Self::MOVE_AX_TO_SP, "mov sp, ax"),
+        (Self::MOVE_AX_TO_FP, "mov fp, ax"),
+        (Self::MOVE_AX_TO_LP, "mov lp, ax"),
+    ];
+
+    pub fn mnemonic(opcode: Word) -> Option<&'static str> {
+        Self::MNEMONICS
+            .iter()
+            .find(|(code, _)| *code == opcode)
+            .map(|(_, text)| *text)
+    }
+
+    /// number of words taken by instruction with its operands
+    pub fn length(opcode: Word) -> Option<Word> {
+        Self::mnemonic(opcode)
+            .map(|text| 1 + text.matches(['#', '~']).count() as Word)
+    }
+
+    /// whether instruction is followed by operand word,
+    /// looked up without search in mnemonics
+    pub fn has_operand(opcode: Word) -> bool {
+        (opcode as usize) < OPERANDS.len() && OPERANDS[opcode as usize]
+    }
+}
+
+// opcode -> instruction has operand
+const OPERANDS: [bool; 128] = operands();
 
-}
\ No newline at end of file
+const fn operands() -> [bool; 128] {
+    let mut table = [false; 128];
+    let mut idx = 0;
+    while idx < OpCode::MNEMONICS.len() {
+        let (opcode, text) = OpCode::MNEMONICS[idx];
+        let text = text.as_bytes();
+        let mut pos = 0;
+        while pos < text.len() {
+            if text[pos] == b'#' || text[pos] == b'~' {
+                table[opcode as usize] = true;
+            }
+            pos += 1;
+        }
+        idx += 1;
+    }
+    table
+}
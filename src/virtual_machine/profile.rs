@@ -0,0 +1,288 @@
+use std::{
+    collections::{BTreeMap, HashMap},
+    fmt::Write as _,
+};
+
+use super::{
+    error::{Registers, VmError},
+    image::{format_instruction, Symbol, SymbolKind},
+    observer::Observer,
+    OpCode,
+    Word,
+};
+
+// addresses shown in report
+const HOT_ADDRESSES: usize = 20;
+
+/// observer, which counts executed instructions per address, opcode
+/// and procedure. Procedures are entered by CALL and left by RET,
+/// the first one is where execution started
+#[derive(Debug, Default)]
+pub struct Profiler {
+    total    : u64,
+    addresses: HashMap<Word, u64>,
+    opcodes  : HashMap<Word, u64>,
+    // tree of calls, first node is root
+    nodes    : Vec<Node>,
+    // node of executed procedure
+    current  : usize,
+    // opcode of executed instruction
+    opcode   : Word,
+}
+
+#[derive(Debug)]
+struct Node {
+    parent  : usize,
+    // address of procedure
+    address : Word,
+    // instructions executed in procedure itself
+    count   : u64,
+    children: HashMap<Word, usize>,
+}
+
+impl Profiler {
+    pub fn new() -> Self {
+        Self::default()
+    }
+
+    /// executed instructions
+    pub fn total(&self) -> u64 {
+        self.total
+    }
+
+    /// executions of instruction at address
+    pub fn count(&self, address: Word) -> u64 {
+        self.addresses.get(&address).copied().unwrap_or_default()
+    }
+
+    /// executions of instructions with opcode
+    pub fn opcode_count(&self, opcode: Word) -> u64 {
+        self.opcodes.get(&opcode).copied().unwrap_or_default()
+    }
+
+    /// report sorted by counts of procedures, opcodes and addresses,
+    /// memory is used to show instructions
+    pub fn report(
+        &self,
+        symbols: &BTreeMap<String, Symbol>,
+        memory: &[Word],
+    ) -> String {
+        let names = Names::new(symbols);
+        let total = self.total.max(1) as f64;
+        let percent = |count: u64| count as f64 * 100.0 / total;
+        let mut result = format!("instructions: {}\n", self.total);
+
+        let mut procedures: Vec<_> = self.procedures().into_iter().collect();
+        procedures.sort_by_key(|(address, (own, _))| (!own, *address));
+
+        result.push_str("\nprocedures:\n");
+        writeln!(
+            result,
+            "{:>12} {:>6} {:>12} {:>6}  name",
+            "self", "%", "total", "%"
+        ).unwrap();
+        for (address, (own, inclusive)) in procedures {
+            writeln!(
+                result,
+                "{own:>12} {:>6.2} {inclusive:>12} {:>6.2}  {}",
+                percent(own),
+                percent(inclusive),
+                names.procedure(address),
+            ).unwrap();
+        }
+
+        let mut opcodes: Vec<_> = self.opcodes.iter().collect();
+        opcodes.sort_by_key(|(opcode, count)| (!**count, **opcode));
+
+        result.push_str("\nopcodes:\n");
+        writeln!(result, "{:>12} {:>6}  mnemonic", "count", "%").unwrap();
+        for (opcode, count) in opcodes {
+            let mnemonic = OpCode::mnemonic(*opcode)
+                .map(str::to_string)
+                .unwrap_or_else(|| format!("0x{opcode:x}"));
+            writeln!(
+                result,
+                "{count:>12} {:>6.2}  {mnemonic}",
+                percent(*count)
+            ).unwrap();
+        }
+
+        let mut addresses: Vec<_> = self.addresses.iter().collect();
+        addresses.sort_by_key(|(address, count)| (!**count, **address));
+
+        result.push_str("\naddresses:\n");
+        writeln!(
+            result,
+            "{:>12} {:>6}  {:<18}  instruction",
+            "count", "%", "address"
+        ).unwrap();
+        for (address, count) in addresses.into_iter().take(HOT_ADDRESSES) {
+            let text = format_instruction(memory, *address as usize)
+                .map(|(text, _)| text)
+                .unwrap_or_default();
+            writeln!(
+                result,
+                "{count:>12} {:>6.2}  0x{address:0>16x}  {text:<24}  {}",
+                percent(*count),
+                names.location(*address),
+            ).unwrap();
+        }
+
+        result
+    }
+
+    /// stacks of procedures with their own counts, one per line
+    /// as `main;f;g 42`, which is read by flame graph tools
+    pub fn folded(&self, symbols: &BTreeMap<String, Symbol>) -> String {
+        let names = Names::new(symbols);
+        let mut lines = Vec::new();
+        for (idx, node) in self.nodes.iter().enumerate() {
+            if node.count == 0 {
+                continue;
+            }
+            let mut stack = Vec::new();
+            let mut frame = idx;
+            loop {
+                stack.push(names.procedure(self.nodes[frame].address));
+                if frame == 0 {
+                    break;
+                }
+                frame = self.nodes[frame].parent;
+            }
+            stack.reverse();
+            lines.push(format!("{} {}", stack.join(";"), node.count));
+        }
+        lines.sort();
+
+        let mut result = lines.join("\n");
+        if !result.is_empty() {
+            result.push('\n');
+        }
+        result
+    }
+
+    // self and total counts of procedures,
+    // total of recursive calls is counted in the outermost one
+    fn procedures(&self) -> HashMap<Word, (u64, u64)> {
+        // children are always after parents
+        let mut inclusive: Vec<u64> =
+            self.nodes.iter().map(|node| node.count).collect();
+        for (idx, node) in self.nodes.iter().enumerate().skip(1).rev() {
+            inclusive[node.parent] += inclusive[idx];
+        }
+
+        let mut procedures: HashMap<Word, (u64, u64)> = HashMap::new();
+        let mut active: HashMap<Word, usize> = HashMap::new();
+        // node and whether it is left
+        let mut stack = vec![(0, false)];
+        while let Some((idx, left)) = stack.pop() {
+            let Some(node) = self.nodes.get(idx) else {
+                break;
+            };
+            let frames = active.entry(node.address).or_default();
+            if left {
+                *frames -= 1;
+                continue;
+            }
+
+            let counts = procedures.entry(node.address).or_default();
+            counts.0 += node.count;
+            if *frames == 0 {
+                counts.1 += inclusive[idx];
+            }
+            *frames += 1;
+            stack.push((idx, true));
+            let children = node.children.values();
+            stack.extend(children.map(|child| (*child, false)));
+        }
+        procedures
+    }
+
+    fn enter(&mut self, address: Word) {
+        let next = self.nodes.len();
+        let current = self.current;
+        let child = *self.nodes[current]
+            .children
+            .entry(address)
+            .or_insert(next);
+        if child == next {
+            self.nodes.push(Node::new(current, address));
+        }
+        self.current = child;
+    }
+}
+
+impl Node {
+    fn new(parent: usize, address: Word) -> Self {
+        Self {
+            parent,
+            address,
+            count   : 0,
+            children: HashMap::new(),
+        }
+    }
+}
+
+impl Observer for Profiler {
+    fn before(&mut self, registers: &Registers, memory: &[Word]) {
+        if self.nodes.is_empty() {
+            self.nodes.push(Node::new(0, registers.ip));
+        }
+        self.opcode = memory
+            .get(registers.ip as usize)
+            .copied()
+            .unwrap_or_default();
+
+        self.total += 1;
+        *self.addresses.entry(registers.ip).or_default() += 1;
+        *self.opcodes.entry(self.opcode).or_default() += 1;
+        self.nodes[self.current].count += 1;
+    }
+
+    fn after(&mut self, registers: &Registers, error: Option<&VmError>) {
+        if error.is_some() {
+            return;
+        }
+        match self.opcode {
+            OpCode::CALL => self.enter(registers.ip),
+            // return from root is kept in root
+            OpCode::RET => self.current = self.nodes[self.current].parent,
+            _ => (),
+        }
+    }
+}
+
+// names of addresses by symbols, functions are preferred
+struct Names {
+    names: BTreeMap<Word, String>,
+}
+
+impl Names {
+    fn new(symbols: &BTreeMap<String, Symbol>) -> Self {
+        let mut sorted: Vec<_> = symbols.iter().collect();
+        sorted.sort_by_key(|(_, symbol)| symbol.kind != SymbolKind::Function);
+
+        let mut names = BTreeMap::new();
+        for (name, symbol) in sorted {
+            names.entry(symbol.address).or_insert_with(|| name.clone());
+        }
+        Self { names }
+    }
+
+    // name of procedure at address
+    fn procedure(&self, address: Word) -> String {
+        match self.names.get(&address) {
+            Some(name) => name.clone(),
+            None => format!("0x{address:x}"),
+        }
+    }
+
+    // nearest symbol before address with offset
+    fn location(&self, address: Word) -> String {
+        match self.names.range(..=address).next_back() {
+            Some((start, name)) if *start == address => name.clone(),
+            Some((start, name)) => format!("{name}+0x{:x}", address - start),
+            None => String::new(),
+        }
+    }
+}
@@ -0,0 +1,267 @@
+use std::{collections::BTreeMap, fs};
+
+use super::{
+    error::Registers,
+    heap::Heap,
+    image::{symbols_payload, ByteReader, Image, Symbol},
+    op_codes::ISA_VERSION,
+    Memory,
+    Word,
+};
+
+// file layout, all numbers are little-endian:
+//
+//   header:
+//     magic              [u8; 4]
+//     format version     u16
+//     isa version        u16
+//     flags              u32, reserved
+//   registers            u64 each: ip, sp, fp, lp, ax, bx, cx, dx
+//   call depth           u64
+//   executed steps       u64
+//   output bytes         u64, written by syscalls
+//   input bytes          u64, read by syscalls
+//   memory size          u64, words, at most MAX_MEMORY_WORDS
+//   runs count           u64
+//   for every run of memory, zero words between runs are omitted:
+//     address            u64
+//     length             u64, words
+//     words              u64 each
+//   heap length          u64, words
+//   heap                 u64 each, see `Heap::to_words`
+//   symbols length       u64, bytes
+//   symbols              same as symbols section of image
+
+pub const MAGIC: [u8; 4] = *b"KNDS";
+pub const FORMAT_VERSION: u16 = 1;
+
+/// largest memory of restored vm in words, 2 GiB, size in file
+/// is checked against it before memory is allocated
+pub const MAX_MEMORY_WORDS: Word = 1 << 28;
+
+const WORD_SIZE: usize = size_of::<Word>();
+// zero words, which are cheaper to omit than to keep in run
+const MIN_ZERO_GAP: usize = 3;
+
+/// full state of program in vm, which may be saved to file and
+/// restored later, see `VirtualMachine::snapshot`.
+/// Streams, syscalls and limits belong to host and are not saved
+#[derive(Debug, Clone)]
+pub struct Snapshot {
+    pub(super) registers   : Registers,
+    pub(super) depth       : Word,
+    pub(super) steps       : u64,
+    pub(super) output_bytes: u64,
+    pub(super) input_bytes : u64,
+    pub(super) memory      : Memory,
+    pub(super) heap        : Heap,
+    pub(super) symbols     : BTreeMap<String, Symbol>,
+}
+
+impl Snapshot {
+    pub fn registers(&self) -> &Registers {
+        &self.registers
+    }
+
+    pub fn memory(&self) -> &[Word] {
+        &self.memory
+    }
+
+    pub fn steps(&self) -> u64 {
+        self.steps
+    }
+
+    /// bytes written to output before snapshot
+    pub fn output_bytes(&self) -> u64 {
+        self.output_bytes
+    }
+
+    /// bytes read from input before snapshot, host has to skip them
+    /// in input of restored vm
+    pub fn input_bytes(&self) -> u64 {
+        self.input_bytes
+    }
+
+    pub fn to_bytes(&self) -> Vec<u8> {
+        let mut words = vec![
+            self.registers.ip,
+            self.registers.sp,
+            self.registers.fp,
+            self.registers.lp,
+            self.registers.ax,
+            self.registers.bx,
+            self.registers.cx,
+            self.registers.dx,
+            self.depth,
+            self.steps,
+            self.output_bytes,
+            self.input_bytes,
+            self.memory.len() as Word,
+        ];
+
+        let runs = memory_runs(&self.memory);
+        words.push(runs.len() as Word);
+        for (start, end) in runs {
+            words.extend([start as Word, (end - start) as Word]);
+            words.extend_from_slice(&self.memory[start..end]);
+        }
+
+        let heap = self.heap.to_words();
+        words.push(heap.len() as Word);
+        words.extend(heap);
+
+        let symbols = symbols_payload(&self.symbols);
+        words.push(symbols.len() as Word);
+
+        let mut bytes = Vec::with_capacity(
+            12 + words.len() * WORD_SIZE + symbols.len()
+        );
+        bytes.extend_from_slice(&MAGIC);
+        bytes.extend_from_slice(&FORMAT_VERSION.to_le_bytes());
+        bytes.extend_from_slice(&ISA_VERSION.to_le_bytes());
+        bytes.extend_from_slice(&0u32.to_le_bytes());
+        for word in words {
+            bytes.extend_from_slice(&word.to_le_bytes());
+        }
+        bytes.extend_from_slice(&symbols);
+        bytes
+    }
+
+    pub fn from_bytes(bytes: &[u8]) -> Result<Self, String> {
+        let mut reader = ByteReader { bytes, position: 0 };
+
+        if reader.take(MAGIC.len()).ok() != Some(MAGIC.as_slice()) {
+            return Err("not a kondra snapshot: bad magic bytes".to_string());
+        }
+
+        let format_version = reader.u16()?;
+        if format_version != FORMAT_VERSION {
+            return Err(format!(
+                "unsupported snapshot format version {format_version}, \
+                 expected {FORMAT_VERSION}"
+            ));
+        }
+
+        let isa_version = reader.u16()?;
+        if isa_version != ISA_VERSION {
+            return Err(format!(
+                "snapshot was made by ISA version {isa_version}, \
+                 but vm has {ISA_VERSION}"
+            ));
+        }
+        let _flags = reader.u32()?;
+
+        let registers = Registers {
+            ip: reader.u64()?,
+            sp: reader.u64()?,
+            fp: reader.u64()?,
+            lp: reader.u64()?,
+            ax: reader.u64()?,
+            bx: reader.u64()?,
+            cx: reader.u64()?,
+            dx: reader.u64()?,
+        };
+        let depth = reader.u64()?;
+        let steps = reader.u64()?;
+        let output_bytes = reader.u64()?;
+        let input_bytes = reader.u64()?;
+
+        // memory is allocated after the whole file is checked
+        let size = reader.u64()?;
+        if size > MAX_MEMORY_WORDS {
+            return Err(format!(
+                "memory size 0x{size:x} is larger than \
+                 0x{MAX_MEMORY_WORDS:x} words"
+            ));
+        }
+        let mut runs = Vec::new();
+        for idx in 0..reader.u64()? {
+            let address = reader.u64()?;
+            let length = reader.u64()?;
+            if address.checked_add(length).is_none_or(|end| end > size) {
+                return Err(format!("memory run {idx} does not fit in memory"));
+            }
+            let words = read_words(&mut reader, length as usize)?;
+            runs.push((address as usize, words));
+        }
+
+        let heap_length = reader.u64()? as usize;
+        let heap = read_words(&mut reader, heap_length)?;
+        let heap = Heap::from_words(&heap, size)?;
+
+        let symbols_length = reader.u64()? as usize;
+        let mut image = Image::new();
+        image.parse_symbols(reader.take(symbols_length)?)?;
+
+        let remaining = bytes.len() - reader.position;
+        if remaining > 0 {
+            return Err(format!("unexpected {remaining} trailing bytes"));
+        }
+
+        let mut memory = Memory::new();
+        memory
+            .try_reserve_exact(size as usize)
+            .map_err(|_| format!("memory size 0x{size:x} is too large"))?;
+        memory.resize(size as usize, 0);
+        for (address, words) in runs {
+            memory[address..address + words.len()].copy_from_slice(&words);
+        }
+
+        Ok(Self {
+            registers,
+            depth,
+            steps,
+            output_bytes,
+            input_bytes,
+            memory,
+            heap,
+            symbols: image.get_symbols().clone(),
+        })
+    }
+
+    /// file is replaced only when whole snapshot is written,
+    /// so previous snapshot survives crash of host
+    pub fn save_to_file(&self, path: &str) -> Result<(), String> {
+        let temp = format!("{path}.tmp");
+        fs::write(&temp, self.to_bytes())
+            .and_then(|()| fs::rename(&temp, path))
+            .map_err(|err| format!("Writing snapshot was failed: {err}"))
+    }
+
+    pub fn load_from_file(path: &str) -> Result<Self, String> {
+        let bytes = fs::read(path)
+            .map_err(|err| format!("Opening file was failed: {err}"))?;
+        Self::from_bytes(&bytes)
+    }
+}
+
+// ranges of memory, which contain all non-zero words
+fn memory_runs(memory: &[Word]) -> Vec<(usize, usize)> {
+    let mut runs: Vec<(usize, usize)> = Vec::new();
+    for (address, word) in memory.iter().enumerate() {
+        if *word == 0 {
+            continue;
+        }
+        match runs.last_mut() {
+            Some((_, end)) if address - *end < MIN_ZERO_GAP => {
+                *end = address + 1;
+            }
+            _ => runs.push((address, address + 1)),
+        }
+    }
+    runs
+}
+
+fn read_words(
+    reader: &mut ByteReader,
+    count: usize,
+) -> Result<Vec<Word>, String> {
+    let bytes = count
+        .checked_mul(WORD_SIZE)
+        .ok_or_else(|| "length does not fit in file".to_string())
+        .and_then(|size| reader.take(size))?;
+    Ok(bytes
+        .chunks_exact(WORD_SIZE)
+        .map(|chunk| Word::from_le_bytes(chunk.try_into().unwrap()))
+        .collect())
+}
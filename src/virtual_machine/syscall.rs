@@ -0,0 +1,252 @@
+use std::{
+    collections::HashMap,
+    fmt,
+    io::{self, ErrorKind, Read, Write},
+};
+
+use super::{
+    error::{Registers, VmError},
+    into_char::IntoChar,
+    Word,
+};
+
+// codes of default syscalls, passed in ax
+pub const PRINT    : Word = 0;
+pub const READ_LINE: Word = 1;
+pub const EXIT     : Word = 2;
+
+// returned in ax by read line at the end of input
+pub const END_OF_INPUT: Word = Word::MAX;
+
+/// state of vm available to syscall handler,
+/// changed registers are written back after the call.
+/// Memory is written only by `write`, so vm knows which
+/// decoded instructions and compiled code it changed
+pub struct SyscallContext<'a> {
+    pub registers       : &'a mut Registers,
+    pub input           : &'a mut dyn Read,
+    pub output          : &'a mut dyn Write,
+    pub(super) memory   : &'a mut [Word],
+    // range of written words
+    pub(super) written  : Option<(Word, Word)>,
+    // written words for observer, None when it is not active
+    pub(super) writes   : Option<Vec<(Word, Word)>>,
+}
+
+impl SyscallContext<'_> {
+    pub fn memory(&self) -> &[Word] {
+        self.memory
+    }
+
+    pub fn read(&self, address: Word) -> Result<Word, VmError> {
+        match self.memory.get(address as usize) {
+            Some(&value) => Ok(value),
+            None => Err(VmError::BadAddress {
+                address,
+                registers: *self.registers,
+            }),
+        }
+    }
+
+    pub fn write(
+        &mut self,
+        address: Word,
+        value: Word,
+    ) -> Result<(), VmError> {
+        match self.memory.get_mut(address as usize) {
+            Some(word) => {
+                *word = value;
+                self.written = Some(match self.written {
+                    Some((start, end)) =>
+                        (start.min(address), end.max(address + 1)),
+                    None => (address, address + 1),
+                });
+                if let Some(writes) = &mut self.writes {
+                    writes.push((address, value));
+                }
+                Ok(())
+            }
+            None => Err(VmError::BadAddress {
+                address,
+                registers: *self.registers,
+            }),
+        }
+    }
+
+    /// ends program, ax is its result
+    pub fn halt(&mut self) {
+        self.registers.ip = self.memory.len() as Word;
+    }
+
+    /// reads bytes till new line including it,
+    /// None at the end of input
+    pub fn read_line(&mut self) -> Result<Option<String>, VmError> {
+        let mut bytes = Vec::new();
+        let mut byte = [0];
+        loop {
+            match self.input.read(&mut byte) {
+                Ok(0) => break,
+                Ok(_) => {
+                    bytes.push(byte[0]);
+                    if byte[0] == b'\n' {
+                        break;
+                    }
+                }
+                Err(error) if error.kind() == ErrorKind::Interrupted => (),
+                Err(error) => return Err(self.io_error(error)),
+            }
+        }
+
+        if bytes.is_empty() {
+            return Ok(None);
+        }
+
+        String::from_utf8(bytes).map(Some).map_err(|_| {
+            self.io_error(io::Error::new(
+                ErrorKind::InvalidData,
+                "input is not valid utf-8",
+            ))
+        })
+    }
+
+    pub fn io_error(&self, error: io::Error) -> VmError {
+        VmError::Io {
+            error,
+            registers: *self.registers,
+        }
+    }
+}
+
+/// host function called by SYSCALL instruction,
+/// registers are passed as arguments and results
+pub trait SyscallHandler {
+    fn call(&mut self, context: &mut SyscallContext) -> Result<(), VmError>;
+}
+
+impl<F> SyscallHandler for F
+where
+    F: FnMut(&mut SyscallContext) -> Result<(), VmError>
+{
+    fn call(&mut self, context: &mut SyscallContext) -> Result<(), VmError> {
+        self(context)
+    }
+}
+
+/// handlers keyed by syscall code
+#[derive(Default)]
+pub struct Syscalls {
+    handlers: HashMap<Word, Box<dyn SyscallHandler>>,
+}
+
+impl Syscalls {
+    pub fn new() -> Self {
+        Self::default()
+    }
+
+    /// print, read line and exit
+    pub fn with_defaults() -> Self {
+        let mut syscalls = Self::new();
+        syscalls.register(PRINT, Print);
+        syscalls.register(READ_LINE, ReadLine);
+        syscalls.register(EXIT, Exit);
+        syscalls
+    }
+
+    /// returns previous handler of code
+    pub fn register(
+        &mut self,
+        code: Word,
+        handler: impl SyscallHandler + 'static,
+    ) -> Option<Box<dyn SyscallHandler>> {
+        self.handlers.insert(code, Box::new(handler))
+    }
+
+    pub fn unregister(
+        &mut self,
+        code: Word,
+    ) -> Option<Box<dyn SyscallHandler>> {
+        self.handlers.remove(&code)
+    }
+
+    pub fn get_mut(
+        &mut self,
+        code: Word,
+    ) -> Option<&mut (dyn SyscallHandler + 'static)> {
+        self.handlers.get_mut(&code).map(|handler| handler.as_mut())
+    }
+}
+
+impl fmt::Debug for Syscalls {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        let mut codes: Vec<&Word> = self.handlers.keys().collect();
+        codes.sort();
+        f.debug_struct("Syscalls").field("codes", &codes).finish()
+    }
+}
+
+// print string in console
+// every char in unicode and stores in Word
+// dx - address of first char
+// cx - length of string
+// ax - num of printed bytes
+pub struct Print;
+
+impl SyscallHandler for Print {
+    fn call(&mut self, context: &mut SyscallContext) -> Result<(), VmError> {
+        let mut count = 0;
+        while context.registers.cx > 0 {
+            let value = context.read(context.registers.dx)?;
+            let Some(c) = value.into_char() else {
+                return Err(VmError::InvalidChar {
+                    value,
+                    registers: *context.registers,
+                });
+            };
+            if let Err(error) = write!(context.output, "{c}") {
+                return Err(context.io_error(error));
+            }
+            context.registers.dx += 1;
+            context.registers.cx -= 1;
+            count += 1;
+        }
+        if let Err(error) = context.output.flush() {
+            return Err(context.io_error(error));
+        }
+        context.registers.ax = count;
+        Ok(())
+    }
+}
+
+// get line from console
+// dx - address of buffer
+// ax - num of gotten chars, END_OF_INPUT when input is over
+pub struct ReadLine;
+
+impl SyscallHandler for ReadLine {
+    fn call(&mut self, context: &mut SyscallContext) -> Result<(), VmError> {
+        let Some(buf) = context.read_line()? else {
+            context.registers.ax = END_OF_INPUT;
+            return Ok(());
+        };
+        let mut count = 0;
+        for c in buf.chars() {
+            context.write(context.registers.dx, c as Word)?;
+            context.registers.dx += 1;
+            count += 1;
+        }
+        context.registers.ax = count;
+        Ok(())
+    }
+}
+
+// end of program
+// dx - return value
+pub struct Exit;
+
+impl SyscallHandler for Exit {
+    fn call(&mut self, context: &mut SyscallContext) -> Result<(), VmError> {
+        context.registers.ax = context.registers.dx;
+        context.halt();
+        Ok(())
+    }
+}
@@ -0,0 +1,234 @@
+use std::{
+    collections::BTreeSet,
+    fmt::Write as _,
+    io::{self, Write},
+    ops::Range,
+};
+
+use super::{
+    error::{Registers, VmError},
+    image::format_instruction,
+    observer::Observer,
+    Word,
+};
+
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq)]
+pub enum TraceFormat {
+    /// one aligned line per instruction with changed registers
+    #[default]
+    Text,
+    /// one json object per line with all registers
+    Json,
+}
+
+/// observer, which writes every executed instruction with
+/// registers before and after it and written memory words
+pub struct Tracer<T: Write> {
+    output   : T,
+    format   : TraceFormat,
+    // only instructions in range are written
+    addresses: Option<Range<Word>>,
+    // only instructions with these opcodes are written
+    opcodes  : Option<BTreeSet<Word>>,
+    // instructions executed since tracer was set
+    step     : u64,
+    // executed instruction, None when it is filtered out
+    current  : Option<Entry>,
+    // first failed write, reported by `flush`
+    error    : Option<io::Error>,
+}
+
+struct Entry {
+    step  : u64,
+    text  : String,
+    before: Registers,
+    writes: Vec<(Word, Word)>,
+}
+
+impl<T: Write> Tracer<T> {
+    pub fn new(output: T, format: TraceFormat) -> Self {
+        Self {
+            output,
+            format,
+            addresses: None,
+            opcodes  : None,
+            step     : 0,
+            current  : None,
+            error    : None,
+        }
+    }
+
+    /// traces only instructions at addresses in range
+    pub fn with_addresses(mut self, addresses: Range<Word>) -> Self {
+        self.addresses = Some(addresses);
+        self
+    }
+
+    /// traces only instructions with given opcodes
+    pub fn with_opcodes(
+        mut self,
+        opcodes: impl IntoIterator<Item = Word>,
+    ) -> Self {
+        self.opcodes = Some(opcodes.into_iter().collect());
+        self
+    }
+
+    pub fn output(&self) -> &T {
+        &self.output
+    }
+
+    /// flushes output, returns first error of writing trace
+    pub fn flush(&mut self) -> io::Result<()> {
+        match self.error.take() {
+            Some(error) => Err(error),
+            None => self.output.flush(),
+        }
+    }
+
+    fn is_traced(&self, ip: Word, opcode: Option<Word>) -> bool {
+        let address_matches = self
+            .addresses
+            .as_ref()
+            .is_none_or(|addresses| addresses.contains(&ip));
+        let opcode_matches = self
+            .opcodes
+            .as_ref()
+            .is_none_or(|opcodes| {
+                opcode.is_some_and(|opcode| opcodes.contains(&opcode))
+            });
+        address_matches && opcode_matches
+    }
+
+    fn text_line(entry: &Entry, after: &Registers, error: Option<&VmError>)
+        -> String
+    {
+        let mut line = format!(
+            "{:>8}  0x{:0>8x}  {:<24}",
+            entry.step, entry.before.ip, entry.text
+        );
+        let before = registers(&entry.before);
+        for ((name, old), (_, new)) in before.iter().zip(registers(after)) {
+            if *name != "ip" && *old != new {
+                write!(line, "  {name}: 0x{old:x} -> 0x{new:x}").unwrap();
+            }
+        }
+        for (address, value) in &entry.writes {
+            write!(line, "  [0x{address:x}] <- 0x{value:x}").unwrap();
+        }
+        if let Some(error) = error {
+            write!(line, "  ! {error}").unwrap();
+        }
+        line.truncate(line.trim_end().len());
+        line
+    }
+
+    fn json_line(entry: &Entry, after: &Registers, error: Option<&VmError>)
+        -> String
+    {
+        let mut line = format!(
+            "{{\"step\":{},\"ip\":{},\"instruction\":{},\
+             \"before\":{},\"after\":{},\"writes\":[",
+            entry.step,
+            entry.before.ip,
+            json_string(&entry.text),
+            json_registers(&entry.before),
+            json_registers(after),
+        );
+        for (idx, (address, value)) in entry.writes.iter().enumerate() {
+            if idx > 0 {
+                line.push(',');
+            }
+            write!(line, "{{\"address\":{address},\"value\":{value}}}")
+                .unwrap();
+        }
+        line.push_str("],\"error\":");
+        match error {
+            Some(error) => line.push_str(&json_string(&error.to_string())),
+            None => line.push_str("null"),
+        }
+        line.push('}');
+        line
+    }
+}
+
+impl<T: Write> Observer for Tracer<T> {
+    fn before(&mut self, registers: &Registers, memory: &[Word]) {
+        let step = self.step;
+        self.step += 1;
+
+        let opcode = memory.get(registers.ip as usize).copied();
+        if !self.is_traced(registers.ip, opcode) {
+            self.current = None;
+            return;
+        }
+
+        let text = match format_instruction(memory, registers.ip as usize) {
+            Some((text, _)) => text,
+            None => format!("0x{:x}", opcode.unwrap_or_default()),
+        };
+        self.current = Some(Entry {
+            step,
+            text,
+            before: *registers,
+            writes: Vec::new(),
+        });
+    }
+
+    fn write(&mut self, address: Word, value: Word) {
+        if let Some(entry) = &mut self.current {
+            entry.writes.push((address, value));
+        }
+    }
+
+    fn after(&mut self, registers: &Registers, error: Option<&VmError>) {
+        let Some(entry) = self.current.take() else {
+            return;
+        };
+        let line = match self.format {
+            TraceFormat::Text => Self::text_line(&entry, registers, error),
+            TraceFormat::Json => Self::json_line(&entry, registers, error),
+        };
+        if self.error.is_none() {
+            if let Err(error) = writeln!(self.output, "{line}") {
+                self.error = Some(error);
+            }
+        }
+    }
+}
+
+fn registers(registers: &Registers) -> [(&'static str, Word); 8] {
+    [
+        ("ip", registers.ip),
+        ("sp", registers.sp),
+        ("fp", registers.fp),
+        ("lp", registers.lp),
+        ("ax", registers.ax),
+        ("bx", registers.bx),
+        ("cx", registers.cx),
+        ("dx", registers.dx),
+    ]
+}
+
+fn json_registers(values: &Registers) -> String {
+    let fields: Vec<String> = registers(values)
+        .iter()
+        .map(|(name, value)| format!("\"{name}\":{value}"))
+        .collect();
+    format!("{{{}}}", fields.join(","))
+}
+
+fn json_string(text: &str) -> String {
+    let mut result = String::from('"');
+    for c in text.chars() {
+        match c {
+            '"' => result.push_str("\\\""),
+            '\\' => result.push_str("\\\\"),
+            c if c.is_control() => {
+                write!(result, "\\u{:04x}", c as u32).unwrap();
+            }
+            c => result.push(c),
+        }
+    }
+    result.push('"');
+    result
+}
@@ -0,0 +1,153 @@
+// text assembler: directives, labels, literals and errors
+
+use virtual_machine::{
+    image::{Symbol, SymbolKind},
+    Assembler,
+    AsmError,
+    Image,
+    OpCode,
+    Real,
+    Word,
+};
+
+fn assemble(source: &str) -> Image {
+    Assembler::new().assemble(source).unwrap()
+}
+
+fn error(source: &str) -> AsmError {
+    Assembler::new().assemble(source).unwrap_err()
+}
+
+#[test]
+fn directives() {
+    let image = assemble("
+        .memory 0x1000
+        .bss 16
+        .data
+        value: 7
+        .code
+        .func main
+        main:
+                mov ax, 1
+                ret
+        .entry main
+    ");
+    assert_eq!(image.get_image(), &[
+        7,
+        OpCode::MOVE_OP_TO_AX, 1,
+        OpCode::RET,
+    ]);
+    assert_eq!(image.get_entry_point(), 1);
+    assert_eq!(image.get_bss_size(), 16);
+    assert_eq!(image.get_memory_requirement(), 0x1000);
+    assert_eq!(
+        image.get_symbol("value"),
+        Some(Symbol { kind: SymbolKind::Data, address: 0 })
+    );
+    assert_eq!(
+        image.get_symbol("main"),
+        Some(Symbol { kind: SymbolKind::Function, address: 1 })
+    );
+
+    // entry point without label is the next instruction
+    let image = assemble("
+        0, 0
+        .entry
+        ret
+    ");
+    assert_eq!(image.get_entry_point(), 2);
+}
+
+#[test]
+fn labels_are_referred_before_definition() {
+    let image = assemble("
+        start:  mov dx, done
+                jmp
+                load [dx+done]
+        done:   ret
+        .data
+                start, done
+    ");
+    assert_eq!(image.get_image(), &[
+        OpCode::MOVE_OP_TO_DX, 5,
+        OpCode::JMP,
+        OpCode::LOAD_DX_OFF, 5,
+        OpCode::RET,
+        0, 5,
+    ]);
+    assert_eq!(
+        image.get_symbol("done"),
+        Some(Symbol { kind: SymbolKind::Label, address: 5 })
+    );
+}
+
+#[test]
+fn literals() {
+    let image = assemble(r#"
+        .data
+        0x1f, 0b101, 0o17, 1_000, -1, +2
+        'c', '\n', '\'', '\u{263a}'
+        1.5, -0.25
+        "a\tb\"\\\0"
+        "; is not a comment, neither is ',' a separator"
+        .code
+                mov ax, -0x10   ; comment
+                load [fp-2]
+                store [lp+0x3]
+    "#);
+    let mut expected: Vec<Word> = vec![
+        0x1f, 0b101, 0o17, 1000, Word::MAX, 2,
+        'c' as Word, '\n' as Word, '\'' as Word, '\u{263a}' as Word,
+        (1.5 as Real).to_bits(), (-0.25 as Real).to_bits(),
+    ];
+    expected.extend("a\tb\"\\\0".chars().map(|c| c as Word));
+    expected.extend(
+        "; is not a comment, neither is ',' a separator"
+            .chars()
+            .map(|c| c as Word)
+    );
+    expected.extend([
+        OpCode::MOVE_OP_TO_AX, 0x10u64.wrapping_neg(),
+        OpCode::LOAD_FP_OFF, 2u64.wrapping_neg(),
+        OpCode::STORE_LP_OFF, 3,
+    ]);
+    assert_eq!(image.get_image(), expected);
+}
+
+#[test]
+fn errors_have_line_numbers() {
+    let cases = [
+        ("ret\nfrob ax\n", 2, "unknown instruction `frob ax`"),
+        ("ret\nret\nmov ax,\n", 3, "missing operand in `mov ax,`"),
+        ("enter\n", 1, "missing operand in `enter`"),
+        ("load [dx]\n", 1, "missing operand in `load [dx]`"),
+        ("a: ret\n\na: ret\n", 3, "label `a` is already defined"),
+        ("mov dx, nowhere\nret\n", 1, "undefined label `nowhere`"),
+        ("ret\n.entry nowhere\n", 2, "undefined label `nowhere`"),
+        ("ret\n.func nowhere\n", 2, "undefined label `nowhere`"),
+        ("ret\n.object nowhere\n", 2, "undefined label `nowhere`"),
+        ("a = 0x2\nret\na: ret\n", 3, "label `a` is already defined"),
+        ("a = b\n", 1, "invalid literal `b`"),
+        (".entry\n.entry\nret\n", 2, "entry point is already defined"),
+        ("ret\n.entry\n\n; end\n", 2, "entry point is outside of image"),
+        (".bss\n", 1, "invalid arguments for `.bss`"),
+        (".bss -1\n", 1, "invalid arguments for `.bss`"),
+        (".memory -0x10\n", 1, "invalid arguments for `.memory`"),
+        (".text\n", 1, "unknown directive `.text`"),
+        ("ax: ret\n", 1, "register name `ax` used as label"),
+        ("mov ax, bx1x\nret\n", 1, "undefined label `bx1x`"),
+        ("mov ax, 0xg\n", 1, "invalid literal `0xg`"),
+        (".data\n\"open\n", 2, "invalid literal \"open"),
+        (".data\n'ab'\n", 2, "invalid literal `'ab'`"),
+        (".data\n\"\\q\"\n", 2, "invalid literal \"\\q\""),
+        ("load [dx2]\n", 1, "offset `2` must start with sign"),
+    ];
+    for (source, line, message) in cases {
+        let err = error(source);
+        assert_eq!((err.line, err.message.as_str()), (line, message));
+    }
+    assert_eq!(
+        error("ret\nfrob\n").to_string(),
+        "line 2: unknown instruction `frob`"
+    );
+}
@@ -0,0 +1,29 @@
+// vm with in-memory console shared by integration tests,
+// every test uses only part of it
+#![allow(dead_code)]
+
+use std::io::Cursor;
+
+use virtual_machine::{NoObserver, Observer, VirtualMachine, Word};
+
+pub type Vm<O = NoObserver> = VirtualMachine<Cursor<Vec<u8>>, Vec<u8>, O>;
+
+/// vm, which reads `input` and collects its output in vector
+pub fn vm(input: &str) -> Vm {
+    console(VirtualMachine::new(), input)
+}
+
+/// the same with memory of `size` bytes
+pub fn vm_with_memory(size: Word, input: &str) -> Vm {
+    console(VirtualMachine::with_memory(size), input)
+}
+
+pub fn observed<O: Observer>(observer: O) -> Vm<O> {
+    vm("").with_observer(observer)
+}
+
+fn console(vm: VirtualMachine, input: &str) -> Vm {
+    vm
+        .with_input(Cursor::new(input.as_bytes().to_vec()))
+        .with_output(Vec::new())
+}
@@ -0,0 +1,716 @@
+// behaviour of every instruction, programs run with in-memory console
+
+mod common;
+
+use virtual_machine::{
+    image::Image,
+    syscall,
+    OpCode,
+    Real,
+    Registers,
+    SWord,
+    StepOutcome,
+    VmError,
+    Word,
+};
+
+use common::Vm;
+
+fn vm(image: &Image, input: &str) -> Vm {
+    let mut vm = common::vm(input);
+    vm.load_image(image).unwrap();
+    vm
+}
+
+/// sets ax, bx, cx and dx, executes code and returns by RET
+fn program(regs: [Word; 4], code: &[Word]) -> Image {
+    let mut image = Image::new();
+    image.emit_opcode_with_operand(OpCode::MOVE_OP_TO_AX, regs[0]);
+    image.emit_opcode_with_operand(OpCode::MOVE_OP_TO_BX, regs[1]);
+    image.emit_opcode_with_operand(OpCode::MOVE_OP_TO_CX, regs[2]);
+    image.emit_opcode_with_operand(OpCode::MOVE_OP_TO_DX, regs[3]);
+    for word in code {
+        image.emit_opcode(*word);
+    }
+    image.emit_opcode(OpCode::RET);
+    image
+}
+
+fn run(regs: [Word; 4], code: &[Word]) -> Result<Registers, VmError> {
+    let image = program(regs, code);
+    let mut vm = vm(&image, "");
+    vm.call(image.get_entry_point(), &[])?;
+    Ok(vm.registers())
+}
+
+/// ax after binary operation
+fn binary(opcode: Word, ax: Word, bx: Word) -> Word {
+    run([ax, bx, 0, 0], &[opcode]).unwrap().ax
+}
+
+fn unary(opcode: Word, ax: Word) -> Word {
+    binary(opcode, ax, 0)
+}
+
+fn real_binary(opcode: Word, ax: Real, bx: Real) -> Real {
+    real(binary(opcode, bits(ax), bits(bx)))
+}
+
+fn bits(value: Real) -> Word {
+    value.to_bits()
+}
+
+fn real(word: Word) -> Real {
+    Real::from_bits(word)
+}
+
+fn signed(value: SWord) -> Word {
+    value as Word
+}
+
+/// whether conditional jump is taken
+fn jumps(opcode: Word, ax: Word, bx: Word) -> bool {
+    let mut image = Image::new();
+    let taken = image.new_label();
+    image.emit_opcode_with_operand(OpCode::MOVE_OP_TO_AX, ax);
+    image.emit_opcode_with_operand(OpCode::MOVE_OP_TO_BX, bx);
+    image.emit_opcode_with_label(OpCode::MOVE_OP_TO_DX, taken);
+    image.emit_opcode(opcode);
+    image.emit_opcode_with_operand(OpCode::MOVE_OP_TO_AX, 0);
+    image.emit_opcode(OpCode::RET);
+    image.place_label(taken).unwrap();
+    image.emit_opcode_with_operand(OpCode::MOVE_OP_TO_AX, 1);
+    image.emit_opcode(OpCode::RET);
+    image.finalize().unwrap();
+
+    let mut vm = vm(&image, "");
+    match vm.call(image.get_entry_point(), &[]).unwrap() {
+        0 => false,
+        1 => true,
+        value => panic!("unexpected result {value}"),
+    }
+}
+
+fn real_jumps(opcode: Word, ax: Real, bx: Real) -> bool {
+    jumps(opcode, bits(ax), bits(bx))
+}
+
+#[test]
+fn integer_arithmetic_wraps() {
+    assert_eq!(unary(OpCode::INC, 41), 42);
+    assert_eq!(unary(OpCode::INC, Word::MAX), 0);
+    assert_eq!(unary(OpCode::DEC, 0), Word::MAX);
+    assert_eq!(unary(OpCode::NEG, 5), signed(-5));
+    assert_eq!(unary(OpCode::NEG, signed(SWord::MIN)), signed(SWord::MIN));
+    assert_eq!(binary(OpCode::ADD, Word::MAX, 2), 1);
+    assert_eq!(binary(OpCode::SUB, 1, 2), Word::MAX);
+    assert_eq!(binary(OpCode::MUL, 1 << 63, 2), 0);
+    assert_eq!(binary(OpCode::MUL, 6, 7), 42);
+}
+
+#[test]
+fn division_sets_quotient_and_remainder() {
+    let regs = run([43, 5, 0, 0], &[OpCode::DIV]).unwrap();
+    assert_eq!((regs.ax, regs.dx), (8, 3));
+
+    // division is unsigned
+    let regs = run([signed(-1), 2, 0, 0], &[OpCode::DIV]).unwrap();
+    assert_eq!((regs.ax, regs.dx), (Word::MAX / 2, 1));
+}
+
+#[test]
+fn division_by_zero_traps() {
+    let err = run([1, 0, 0, 0], &[OpCode::DIV]).unwrap_err();
+    assert!(matches!(err, VmError::DivisionByZero { .. }));
+}
+
+#[test]
+fn bitwise_operations() {
+    assert_eq!(binary(OpCode::AND, 0b1100, 0b1010), 0b1000);
+    assert_eq!(binary(OpCode::OR, 0b1100, 0b1010), 0b1110);
+    assert_eq!(binary(OpCode::XOR, 0b1100, 0b1010), 0b0110);
+    assert_eq!(unary(OpCode::NOT, 0), Word::MAX);
+}
+
+#[test]
+fn shifts_wrap_amount() {
+    assert_eq!(binary(OpCode::SHL, 1, 63), 1 << 63);
+    assert_eq!(binary(OpCode::SHL, 1 << 63, 1), 0);
+    assert_eq!(binary(OpCode::SHR, 1 << 63, 63), 1);
+    // shift is logical
+    assert_eq!(binary(OpCode::SHR, Word::MAX, 60), 0xf);
+    // amount is taken modulo word size
+    assert_eq!(binary(OpCode::SHL, 3, 64), 3);
+    assert_eq!(binary(OpCode::SHR, 3, 65), 1);
+}
+
+#[test]
+fn real_arithmetic() {
+    let op = |opcode, ax, bx| real_binary(opcode, ax, bx);
+
+    assert_eq!(real(unary(OpCode::FNEG, bits(1.5))), -1.5);
+    assert_eq!(op(OpCode::FADD, 1.5, 2.25), 3.75);
+    assert_eq!(op(OpCode::FSUB, 1.5, 2.25), -0.75);
+    assert_eq!(op(OpCode::FMUL, 1.5, -2.0), -3.0);
+    assert_eq!(op(OpCode::FDIV, 1.0, 4.0), 0.25);
+}
+
+#[test]
+fn real_arithmetic_edge_values() {
+    let op = |opcode, ax, bx| real_binary(opcode, ax, bx);
+
+    assert_eq!(op(OpCode::FDIV, 1.0, 0.0), Real::INFINITY);
+    assert_eq!(op(OpCode::FDIV, -1.0, 0.0), Real::NEG_INFINITY);
+    assert!(op(OpCode::FDIV, 0.0, 0.0).is_nan());
+    assert!(op(OpCode::FSUB, Real::INFINITY, Real::INFINITY).is_nan());
+    assert!(op(OpCode::FADD, Real::NAN, 1.0).is_nan());
+    assert_eq!(op(OpCode::FMUL, Real::MAX, 2.0), Real::INFINITY);
+    assert_eq!(real(unary(OpCode::FNEG, bits(0.0))).to_bits(), bits(-0.0));
+}
+
+#[test]
+fn casts() {
+    assert_eq!(real(unary(OpCode::CWTOR, 3)), 3.0);
+    assert_eq!(real(unary(OpCode::CWTOR, Word::MAX)), Word::MAX as Real);
+    assert_eq!(real(unary(OpCode::CSWTOR, signed(-3))), -3.0);
+    assert_eq!(
+        real(unary(OpCode::CSWTOR, signed(SWord::MIN))),
+        SWord::MIN as Real
+    );
+
+    assert_eq!(unary(OpCode::CRTOW, bits(3.9)), 3);
+    assert_eq!(unary(OpCode::CRTOW, bits(-1.0)), 0);
+    assert_eq!(unary(OpCode::CRTOW, bits(Real::INFINITY)), Word::MAX);
+    assert_eq!(unary(OpCode::CRTOW, bits(Real::NAN)), 0);
+
+    assert_eq!(unary(OpCode::CRTOSW, bits(-3.9)), signed(-3));
+    assert_eq!(unary(OpCode::CRTOSW, bits(Real::INFINITY)), signed(SWord::MAX));
+    assert_eq!(
+        unary(OpCode::CRTOSW, bits(Real::NEG_INFINITY)),
+        signed(SWord::MIN)
+    );
+    assert_eq!(unary(OpCode::CRTOSW, bits(Real::NAN)), 0);
+}
+
+#[test]
+fn equality_jumps() {
+    assert!(jumps(OpCode::JE, 7, 7));
+    assert!(!jumps(OpCode::JE, 7, 8));
+    assert!(jumps(OpCode::JNE, 7, 8));
+    assert!(!jumps(OpCode::JNE, 7, 7));
+}
+
+#[test]
+fn signed_jumps() {
+    let min = signed(SWord::MIN);
+    let max = signed(SWord::MAX);
+    let minus_one = signed(-1);
+
+    assert!(jumps(OpCode::JG, 1, minus_one));
+    assert!(!jumps(OpCode::JG, minus_one, 1));
+    assert!(!jumps(OpCode::JG, 5, 5));
+    assert!(jumps(OpCode::JG, max, min));
+
+    assert!(jumps(OpCode::JGE, 5, 5));
+    assert!(jumps(OpCode::JGE, 0, min));
+    assert!(!jumps(OpCode::JGE, min, 0));
+
+    assert!(jumps(OpCode::JL, min, max));
+    assert!(jumps(OpCode::JL, minus_one, 0));
+    assert!(!jumps(OpCode::JL, 5, 5));
+    assert!(!jumps(OpCode::JL, 0, minus_one));
+
+    assert!(jumps(OpCode::JLE, 5, 5));
+    assert!(jumps(OpCode::JLE, min, minus_one));
+    assert!(!jumps(OpCode::JLE, max, min));
+}
+
+#[test]
+fn unsigned_jumps() {
+    let minus_one = signed(-1);
+
+    assert!(jumps(OpCode::JA, minus_one, 1));
+    assert!(!jumps(OpCode::JA, 5, 5));
+    assert!(!jumps(OpCode::JA, 0, Word::MAX));
+
+    assert!(jumps(OpCode::JAE, 5, 5));
+    assert!(jumps(OpCode::JAE, Word::MAX, 0));
+    assert!(!jumps(OpCode::JAE, 0, 1));
+
+    assert!(jumps(OpCode::JB, 1, minus_one));
+    assert!(!jumps(OpCode::JB, 5, 5));
+
+    assert!(jumps(OpCode::JBE, 5, 5));
+    assert!(jumps(OpCode::JBE, 0, Word::MAX));
+    assert!(!jumps(OpCode::JBE, Word::MAX, 0));
+}
+
+#[test]
+fn real_jumps_compare_values() {
+    assert!(real_jumps(OpCode::FJG, 2.0, 1.0));
+    assert!(!real_jumps(OpCode::FJG, 1.0, 2.0));
+    assert!(!real_jumps(OpCode::FJG, 1.0, 1.0));
+    // as words negative reals are greater than positive ones
+    assert!(real_jumps(OpCode::FJG, 1.0, -2.0));
+
+    assert!(real_jumps(OpCode::FJGE, 1.0, 1.0));
+    assert!(real_jumps(OpCode::FJGE, 0.0, -0.0));
+    assert!(!real_jumps(OpCode::FJGE, -3.0, -2.0));
+
+    assert!(real_jumps(OpCode::FJL, -3.0, -2.0));
+    assert!(!real_jumps(OpCode::FJL, 1.0, 1.0));
+    assert!(!real_jumps(OpCode::FJL, 1.0, -2.0));
+
+    assert!(real_jumps(OpCode::FJLE, 1.0, 1.0));
+    assert!(real_jumps(OpCode::FJLE, -0.0, 0.0));
+    assert!(!real_jumps(OpCode::FJLE, 2.0, 1.0));
+}
+
+#[test]
+fn real_jumps_with_infinities() {
+    let inf = Real::INFINITY;
+
+    assert!(real_jumps(OpCode::FJG, inf, Real::MAX));
+    assert!(real_jumps(OpCode::FJL, -inf, Real::MIN));
+    assert!(real_jumps(OpCode::FJGE, inf, inf));
+    assert!(real_jumps(OpCode::FJLE, -inf, -inf));
+    assert!(!real_jumps(OpCode::FJL, inf, inf));
+}
+
+#[test]
+fn real_jumps_with_nan_are_not_taken() {
+    let nan = Real::NAN;
+    for opcode in [OpCode::FJG, OpCode::FJGE, OpCode::FJL, OpCode::FJLE] {
+        assert!(!real_jumps(opcode, nan, 1.0));
+        assert!(!real_jumps(opcode, 1.0, nan));
+        assert!(!real_jumps(opcode, nan, nan));
+    }
+}
+
+#[test]
+fn jmp_is_unconditional() {
+    let mut image = Image::new();
+    let target = image.new_label();
+    image.emit_opcode_with_label(OpCode::MOVE_OP_TO_DX, target);
+    image.emit_opcode(OpCode::JMP);
+    image.emit_opcode_with_operand(OpCode::MOVE_OP_TO_AX, 0);
+    image.emit_opcode(OpCode::RET);
+    image.place_label(target).unwrap();
+    image.emit_opcode_with_operand(OpCode::MOVE_OP_TO_AX, 1);
+    image.emit_opcode(OpCode::RET);
+
+    let mut vm = vm(&image, "");
+    assert_eq!(vm.call(0, &[]).unwrap(), 1);
+}
+
+#[test]
+fn register_moves() {
+    let moves = [
+        (OpCode::MOVE_BX_TO_AX, 0, 1),
+        (OpCode::MOVE_CX_TO_AX, 0, 2),
+        (OpCode::MOVE_DX_TO_AX, 0, 3),
+        (OpCode::MOVE_AX_TO_BX, 1, 0),
+        (OpCode::MOVE_CX_TO_BX, 1, 2),
+        (OpCode::MOVE_DX_TO_BX, 1, 3),
+        (OpCode::MOVE_AX_TO_CX, 2, 0),
+        (OpCode::MOVE_BX_TO_CX, 2, 1),
+        (OpCode::MOVE_DX_TO_CX, 2, 3),
+        (OpCode::MOVE_AX_TO_DX, 3, 0),
+        (OpCode::MOVE_BX_TO_DX, 3, 1),
+        (OpCode::MOVE_CX_TO_DX, 3, 2),
+    ];
+
+    let initial = [10, 11, 12, 13];
+    for (opcode, dst, src) in moves {
+        let regs = run(initial, &[opcode]).unwrap();
+        let mut expected = initial;
+        expected[dst] = initial[src];
+        assert_eq!(
+            [regs.ax, regs.bx, regs.cx, regs.dx],
+            expected,
+            "{}",
+            OpCode::mnemonic(opcode).unwrap()
+        );
+    }
+}
+
+#[test]
+fn operand_moves() {
+    let regs = run([Word::MAX, 1, signed(-1), 1 << 63], &[]).unwrap();
+    assert_eq!(
+        [regs.ax, regs.bx, regs.cx, regs.dx],
+        [Word::MAX, 1, signed(-1), 1 << 63]
+    );
+}
+
+#[test]
+fn stack_register_moves() {
+    let code = [
+        OpCode::MOVE_SP_TO_AX, OpCode::MOVE_AX_TO_BX,
+        OpCode::MOVE_FP_TO_AX, OpCode::MOVE_AX_TO_CX,
+        OpCode::MOVE_LP_TO_AX, OpCode::MOVE_AX_TO_DX,
+    ];
+    let image = program([0; 4], &code);
+    let mut vm = vm(&image, "");
+    let top = vm.address();
+    vm.call(0, &[]).unwrap();
+    let regs = vm.registers();
+    // return address is on the stack
+    assert_eq!(regs.bx, top - 1);
+    assert_eq!(regs.cx, top);
+    assert_eq!(regs.dx, top - 1);
+
+    // writes of stack registers are seen by following instructions
+    let code = [
+        OpCode::MOVE_SP_TO_AX,
+        OpCode::MOVE_AX_TO_FP,
+        OpCode::MOVE_AX_TO_LP,
+        OpCode::MOVE_OP_TO_AX, 0x55,
+        OpCode::STORE_LP_OFF, signed(-1),
+        OpCode::MOVE_SP_TO_AX,
+        OpCode::DEC,
+        OpCode::MOVE_AX_TO_SP,
+        OpCode::POP,
+        OpCode::MOVE_FP_TO_AX,
+        OpCode::MOVE_AX_TO_SP,
+    ];
+    let regs = run([0; 4], &code).unwrap();
+    assert_eq!(regs.cx, 0x55);
+}
+
+#[test]
+fn push_and_pop_use_cx() {
+    let code = [
+        OpCode::PUSH,
+        OpCode::MOVE_OP_TO_CX, 2,
+        OpCode::PUSH,
+        OpCode::POP,
+        OpCode::MOVE_CX_TO_AX,
+        OpCode::POP,
+    ];
+    let regs = run([0, 0, 1, 0], &code).unwrap();
+    assert_eq!((regs.ax, regs.cx), (2, 1));
+}
+
+#[test]
+fn pop_of_empty_stack_traps() {
+    let mut image = Image::new();
+    image.emit_opcode(OpCode::POP);
+    let err = vm(&image, "").execute().unwrap_err();
+    assert!(matches!(err, VmError::StackUnderflow { depth: 0, .. }));
+
+    let mut image = Image::new();
+    image.emit_opcode(OpCode::RET);
+    let err = vm(&image, "").execute().unwrap_err();
+    assert!(matches!(err, VmError::StackUnderflow { .. }));
+}
+
+#[test]
+fn endless_recursion_overflows_stack() {
+    let mut image = Image::new();
+    image.emit_opcode_with_operand(OpCode::MOVE_OP_TO_DX, 0);
+    image.emit_opcode(OpCode::CALL);
+
+    let mut vm = vm(&image, "");
+    vm.set_stack_limit(Some(100));
+    let err = vm.execute().unwrap_err();
+    assert!(matches!(err, VmError::StackOverflow { depth: 100, .. }));
+}
+
+#[test]
+fn call_and_ret() {
+    let mut image = Image::new();
+    let procedure = image.new_label();
+    image.emit_opcode_with_label(OpCode::MOVE_OP_TO_DX, procedure);
+    image.emit_opcode(OpCode::CALL);
+    image.emit_opcode(OpCode::INC);
+    image.emit_opcode(OpCode::RET);
+    image.place_label(procedure).unwrap();
+    image.emit_opcode_with_operand(OpCode::MOVE_OP_TO_AX, 41);
+    image.emit_opcode(OpCode::RET);
+
+    let mut vm = vm(&image, "");
+    assert_eq!(vm.call(0, &[]).unwrap(), 42);
+    assert_eq!(vm.call_depth(), 0);
+}
+
+#[test]
+fn frames() {
+    // (a - b) * 2 with local variable
+    let code = [
+        OpCode::ENTER, 1,
+        OpCode::LOAD_FP_OFF, 3,
+        OpCode::MOVE_AX_TO_BX,
+        OpCode::LOAD_FP_OFF, 2,
+        OpCode::SUB,
+        OpCode::STORE_LP_OFF, 0,
+        OpCode::MOVE_AX_TO_BX,
+        OpCode::LOAD_LP_OFF, 0,
+        OpCode::ADD,
+        OpCode::LEAVE,
+        OpCode::RET,
+    ];
+    let mut image = Image::new();
+    for word in code {
+        image.emit_opcode(word);
+    }
+
+    let mut vm = vm(&image, "");
+    let before = vm.registers();
+    assert_eq!(vm.call(0, &[10, 3]).unwrap(), 14);
+    assert_eq!(vm.call(0, &[3, 10]).unwrap(), signed(-14));
+    let after = vm.registers();
+    assert_eq!(
+        (after.sp, after.fp, after.lp),
+        (before.sp, before.fp, before.lp)
+    );
+}
+
+#[test]
+fn stores_relative_to_frame_pointer() {
+    // local below saved lp and argument above return address
+    let code = [
+        OpCode::ENTER, 1,
+        OpCode::MOVE_OP_TO_AX, 5,
+        OpCode::STORE_FP_OFF, signed(-2),
+        OpCode::LOAD_LP_OFF, 0,
+        OpCode::MOVE_AX_TO_BX,
+        OpCode::LOAD_FP_OFF, 2,
+        OpCode::INC,
+        OpCode::STORE_FP_OFF, 3,
+        OpCode::LOAD_FP_OFF, 3,
+        OpCode::ADD,
+        OpCode::LEAVE,
+        OpCode::RET,
+    ];
+    let mut image = Image::new();
+    for word in code {
+        image.emit_opcode(word);
+    }
+
+    let mut vm = vm(&image, "");
+    assert_eq!(vm.call(0, &[10, 3]).unwrap(), 16);
+}
+
+#[test]
+fn leave_restores_caller_frame() {
+    let code = [
+        OpCode::ENTER, 2,
+        OpCode::MOVE_FP_TO_AX, OpCode::MOVE_AX_TO_BX,
+        OpCode::ENTER, 3,
+        OpCode::LEAVE,
+        OpCode::MOVE_FP_TO_AX, OpCode::MOVE_AX_TO_CX,
+        OpCode::MOVE_LP_TO_AX, OpCode::MOVE_AX_TO_DX,
+        OpCode::MOVE_SP_TO_AX,
+        OpCode::LEAVE,
+    ];
+    let regs = run([0; 4], &code).unwrap();
+    assert_eq!(regs.cx, regs.bx);
+    // saved fp, saved lp and two locals
+    assert_eq!(regs.dx, regs.bx - 3);
+    assert_eq!(regs.ax, regs.dx);
+}
+
+#[test]
+fn loads_and_stores() {
+    let mut image = Image::new();
+    let data = image.emit_data(0);
+    image.emit_data(0);
+    image.emit_data(0);
+    image.set_entry_point_here();
+    let code = [
+        OpCode::MOVE_OP_TO_DX, data,
+        OpCode::MOVE_OP_TO_AX, 7,
+        OpCode::STORE,
+        OpCode::MOVE_OP_TO_AX, 8,
+        OpCode::STORE_DX_OFF, 1,
+        OpCode::MOVE_OP_TO_BX, 2,
+        OpCode::MOVE_OP_TO_AX, 9,
+        OpCode::STORE_DX_BX,
+        OpCode::MOVE_OP_TO_DX, data + 2,
+        OpCode::LOAD_DX_OFF, signed(-1),
+        OpCode::MOVE_AX_TO_CX,
+        OpCode::MOVE_OP_TO_BX, signed(-2),
+        OpCode::LOAD_DX_BX,
+        OpCode::RET,
+    ];
+    for word in code {
+        image.emit_opcode(word);
+    }
+
+    let mut vm = vm(&image, "");
+    assert_eq!(vm.call(image.get_entry_point(), &[]).unwrap(), 7);
+    assert_eq!(vm.registers().cx, 8);
+    assert_eq!(&vm.memory()[..3], &[7, 8, 9]);
+}
+
+#[test]
+fn deref() {
+    let mut image = Image::new();
+    image.emit_data(0x1234);
+    image.set_entry_point_here();
+    image.emit_opcode_with_operand(OpCode::MOVE_OP_TO_AX, 0);
+    image.emit_opcode(OpCode::INC);
+    image.emit_opcode(OpCode::DEC);
+    image.emit_opcode(OpCode::DEREF);
+    image.emit_opcode(OpCode::RET);
+
+    // null pointer is not dereferenced even when it is mapped
+    let mut vm = vm(&image, "");
+    let err = vm.call(image.get_entry_point(), &[]).unwrap_err();
+    assert!(matches!(err, VmError::BadAddress { address: 0, .. }));
+
+    // program starts with `mov ax, #`, `mov bx, #`
+    let regs = run([2, 0, 0, 0], &[OpCode::DEREF]).unwrap();
+    assert_eq!(regs.ax, OpCode::MOVE_OP_TO_BX);
+}
+
+#[test]
+fn out_of_memory_access_traps() {
+    let err = run([0, 0, 0, Word::MAX], &[OpCode::STORE]).unwrap_err();
+    assert!(matches!(
+        err,
+        VmError::BadAddress { address: Word::MAX, .. }
+    ));
+
+    let err = run([Word::MAX, 0, 0, 0], &[OpCode::DEREF]).unwrap_err();
+    assert!(matches!(err, VmError::BadAddress { .. }));
+}
+
+#[test]
+fn malloc_and_free() {
+    let code = [
+        OpCode::MALLOC,
+        OpCode::MOVE_AX_TO_DX,
+        OpCode::MOVE_OP_TO_AX, 5,
+        OpCode::STORE_DX_OFF, 2,
+        OpCode::MOVE_DX_TO_AX,
+        OpCode::FREE,
+        OpCode::MALLOC,
+    ];
+    let regs = run([0, 0, 24, 0], &code).unwrap();
+    // freed block is reused and zeroed
+    assert_eq!(regs.ax, regs.dx);
+    assert_ne!(regs.ax, 0);
+
+    let image = program([0, 0, 24, 0], &[OpCode::MALLOC]);
+    let mut vm = vm(&image, "");
+    let address = vm.call(0, &[]).unwrap();
+    assert!(vm.memory()[address as usize..address as usize + 3]
+        .iter()
+        .all(|word| *word == 0));
+}
+
+#[test]
+fn malloc_returns_zero_when_memory_is_exhausted() {
+    let regs = run([0, 0, Word::MAX - 7, 0], &[OpCode::MALLOC]).unwrap();
+    assert_eq!(regs.ax, 0);
+}
+
+#[test]
+fn invalid_frees_trap() {
+    let err = run([100, 0, 0, 0], &[OpCode::FREE]).unwrap_err();
+    assert!(matches!(err, VmError::InvalidFree { address: 100, .. }));
+
+    let code = [
+        OpCode::MALLOC,
+        OpCode::MOVE_AX_TO_DX,
+        OpCode::FREE,
+        OpCode::MOVE_DX_TO_AX,
+        OpCode::FREE,
+    ];
+    let err = run([0, 0, 8, 0], &code).unwrap_err();
+    assert!(matches!(err, VmError::DoubleFree { .. }));
+}
+
+#[test]
+fn print_syscall() {
+    let mut image = Image::new();
+    let text = image.emit_data("héllo\n");
+    image.set_entry_point_here();
+    image.emit_opcode_with_operand(OpCode::MOVE_OP_TO_DX, text);
+    image.emit_opcode_with_operand(OpCode::MOVE_OP_TO_CX, 6);
+    image.emit_opcode_with_operand(OpCode::MOVE_OP_TO_AX, syscall::PRINT);
+    image.emit_opcode(OpCode::SYSCALL);
+    image.emit_opcode(OpCode::RET);
+
+    let mut vm = vm(&image, "");
+    assert_eq!(vm.call(image.get_entry_point(), &[]).unwrap(), 6);
+    assert_eq!(vm.output().as_slice(), "héllo\n".as_bytes());
+}
+
+#[test]
+fn print_of_invalid_char_traps() {
+    let mut image = Image::new();
+    image.emit_data(0xd800);
+    image.set_entry_point_here();
+    image.emit_opcode_with_operand(OpCode::MOVE_OP_TO_CX, 1);
+    image.emit_opcode_with_operand(OpCode::MOVE_OP_TO_DX, 0);
+    image.emit_opcode_with_operand(OpCode::MOVE_OP_TO_AX, syscall::PRINT);
+    image.emit_opcode(OpCode::SYSCALL);
+    let err = vm(&image, "").execute().unwrap_err();
+    assert!(matches!(err, VmError::InvalidChar { value: 0xd800, .. }));
+}
+
+#[test]
+fn read_line_syscall() {
+    let mut image = Image::new();
+    image.emit_opcode_with_operand(OpCode::MOVE_OP_TO_DX, 0x100);
+    image.emit_opcode_with_operand(OpCode::MOVE_OP_TO_AX, syscall::READ_LINE);
+    image.emit_opcode(OpCode::SYSCALL);
+    image.emit_opcode(OpCode::RET);
+
+    let mut vm = vm(&image, "añ\nrest");
+    assert_eq!(vm.call(0, &[]).unwrap(), 3);
+    assert_eq!(&vm.memory()[0x100..0x103], &['a' as Word, 'ñ' as Word, 10]);
+    assert_eq!(vm.call(0, &[]).unwrap(), 4);
+    assert_eq!(vm.call(0, &[]).unwrap(), syscall::END_OF_INPUT);
+}
+
+#[test]
+fn exit_syscall_halts() {
+    let code = [
+        OpCode::MOVE_OP_TO_AX, syscall::EXIT,
+        OpCode::SYSCALL,
+        OpCode::INC,
+    ];
+    let image = program([0, 0, 0, 42], &code);
+    let mut vm = vm(&image, "");
+    assert_eq!(vm.execute().unwrap(), 42);
+    assert!(vm.is_halted());
+}
+
+#[test]
+fn unknown_syscall_traps() {
+    let err = run([1000, 0, 0, 0], &[OpCode::SYSCALL]).unwrap_err();
+    assert!(matches!(err, VmError::BadSyscall { code: 1000, .. }));
+}
+
+#[test]
+fn invalid_opcode_traps() {
+    for opcode in [0, 1000, Word::MAX] {
+        let err = run([0; 4], &[opcode]).unwrap_err();
+        assert!(matches!(
+            err,
+            VmError::InvalidOpcode { opcode: found, .. } if found == opcode
+        ));
+        assert_eq!(err.ip(), Some(8));
+    }
+}
+
+#[test]
+fn every_opcode_is_implemented() {
+    for (opcode, template) in OpCode::MNEMONICS {
+        let mut image = Image::new();
+        image.emit_opcode(*opcode);
+        for _ in 1..OpCode::length(*opcode).unwrap() {
+            image.emit_opcode(0);
+        }
+
+        let mut vm = vm(&image, "");
+        vm.set_yield_on_syscall(true);
+        if let StepOutcome::Trapped(VmError::InvalidOpcode { .. }) = vm.step() {
+            panic!("`{template}` is not implemented");
+        }
+    }
+}
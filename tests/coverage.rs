@@ -0,0 +1,144 @@
+// coverage of executed instructions and conditional jumps
+
+mod common;
+
+use std::process::Command;
+
+use virtual_machine::{Assembler, Coverage};
+
+const SOURCE: &str = "\
+; returns 1 for positive ax
+.entry
+main:
+        mov bx, 0
+        mov dx, positive
+        jg
+        mov ax, 0
+        ret
+positive:
+        mov ax, 1
+        ret
+";
+
+#[test]
+fn lcov_maps_hits_and_branches_to_source() {
+    let (image, lines) = Assembler::new()
+        .assemble_with_source_map(SOURCE)
+        .unwrap();
+    let mut vm = common::observed(Coverage::new());
+    vm.load_image(&image).unwrap();
+    assert_eq!(vm.call(0, &[]).unwrap(), 0);
+    assert_eq!(vm.call(0, &[]).unwrap(), 0);
+
+    let coverage = vm.observer();
+    let jump = 4;
+    assert_eq!(coverage.branch(jump).unwrap().not_taken, 2);
+    assert_eq!(coverage.branch(jump).unwrap().taken, 0);
+
+    assert_eq!(
+        coverage.lcov("test.kasm", &image, &lines),
+        "TN:\nSF:test.kasm\n\
+         BRDA:6,4,0,0\nBRDA:6,4,1,2\nBRF:2\nBRH:1\n\
+         DA:4,2\nDA:5,2\nDA:6,2\nDA:7,2\nDA:8,2\nDA:10,0\nDA:11,0\n\
+         LF:7\nLH:5\nend_of_record\n"
+    );
+
+    let listing = coverage.listing(&image);
+    let listing: Vec<&str> = listing.lines().collect();
+    assert_eq!(
+        listing[2],
+        "       2  0x0000000000000004: jg  ; taken 0, not taken 2"
+    );
+    assert_eq!(listing[5], "   #####  0x0000000000000008: mov ax, 0x1");
+}
+
+#[test]
+fn taken_and_not_taken_branches_are_counted() {
+    // returns 1 for positive argument
+    let source = "\
+.entry
+main:
+        enter 0
+        load [fp+0x2]
+        mov bx, 0
+        mov dx, positive
+        jg
+        mov ax, 0
+        leave
+        ret
+positive:
+        mov ax, 1
+        leave
+        ret
+";
+    let (image, lines) = Assembler::new()
+        .assemble_with_source_map(source)
+        .unwrap();
+    let mut vm = common::observed(Coverage::new());
+    vm.load_image(&image).unwrap();
+    for (arg, result) in [(5, 1), (0, 0), (3, 1), ((-3i64) as u64, 0), (7, 1)] {
+        assert_eq!(vm.call(0, &[arg]).unwrap(), result);
+    }
+
+    let coverage = vm.observer();
+    let jump = 8;
+    assert_eq!(coverage.branch(jump).unwrap().taken, 3);
+    assert_eq!(coverage.branch(jump).unwrap().not_taken, 2);
+
+    assert_eq!(
+        coverage.lcov("positive.kasm", &image, &lines),
+        "TN:\nSF:positive.kasm\n\
+         BRDA:7,8,0,3\nBRDA:7,8,1,2\nBRF:2\nBRH:2\n\
+         DA:3,5\nDA:4,5\nDA:5,5\nDA:6,5\nDA:7,5\nDA:8,2\nDA:9,2\n\
+         DA:10,2\nDA:12,3\nDA:13,3\nDA:14,3\n\
+         LF:11\nLH:11\nend_of_record\n"
+    );
+
+    let listing = coverage.listing(&image);
+    assert!(
+        listing.contains(
+            "       5  0x0000000000000008: jg  ; taken 3, not taken 2"
+        ),
+        "{listing}"
+    );
+}
+
+#[test]
+fn jump_to_next_instruction_is_counted_by_condition() {
+    let image = Assembler::new()
+        .assemble("\
+.entry
+main:
+        enter 0
+        load [fp+0x2]
+        mov bx, 0
+        mov dx, next
+        jg
+next:
+        leave
+        ret
+")
+        .unwrap();
+    let mut vm = common::observed(Coverage::new());
+    vm.load_image(&image).unwrap();
+    vm.call(0, &[1]).unwrap();
+    vm.call(0, &[1]).unwrap();
+    vm.call(0, &[0]).unwrap();
+
+    let branch = vm.observer().branch(8).unwrap();
+    assert_eq!((branch.taken, branch.not_taken), (2, 1));
+}
+
+#[test]
+fn coverage_of_resumed_run_is_rejected() {
+    let output = Command::new(env!("CARGO_BIN_EXE_virtual-machine"))
+        .args(["run", "--coverage", "out.info", "--resume", "state.knds"])
+        .output()
+        .unwrap();
+    assert!(!output.status.success());
+    let stderr = String::from_utf8(output.stderr).unwrap();
+    assert!(
+        stderr.contains("--coverage needs an image, not a snapshot"),
+        "{stderr}"
+    );
+}
@@ -0,0 +1,215 @@
+// debug subcommand driven through its console
+
+use std::{
+    fs,
+    io::Write,
+    path::PathBuf,
+    process::{Command, Stdio},
+};
+
+use virtual_machine::Assembler;
+
+const SOURCE: &str = "
+.entry
+main:   mov ax, 20
+        mov dx, double
+        call
+        inc
+        mov dx, ax
+        mov ax, 2
+        syscall
+double: mov bx, ax
+        add
+        ret
+";
+
+// reads line into buffer and prints it, ends with its length
+const ECHO: &str = "
+.data
+buffer: 0, 0, 0, 0, 0, 0, 0, 0
+
+.code
+.entry
+main:   mov dx, buffer
+        mov ax, 1
+        syscall
+        mov cx, ax
+        mov dx, buffer
+        mov ax, 0
+        syscall
+        mov dx, ax
+        mov ax, 2
+        syscall
+";
+
+/// runs debugger with commands on separate lines and returns
+/// location printed on start and replies to every command
+fn debug(name: &str, commands: &[&str]) -> Vec<String> {
+    debug_program(name, SOURCE, None, commands)
+}
+
+/// the same for source, which reads `input` given by --input
+fn debug_program(
+    name: &str,
+    source: &str,
+    input: Option<&str>,
+    commands: &[&str],
+) -> Vec<String> {
+    let file = |extension: &str| -> PathBuf {
+        [env!("CARGO_TARGET_TMPDIR"), &format!("{name}.{extension}")]
+            .iter()
+            .collect()
+    };
+    let path = file("kondra");
+    let image = Assembler::new().assemble(source).unwrap();
+    image.save_to_file(path.to_str().unwrap()).unwrap();
+
+    let mut command = Command::new(env!("CARGO_BIN_EXE_virtual-machine"));
+    command.arg("debug");
+    if let Some(input) = input {
+        let input_path = file("input");
+        fs::write(&input_path, input).unwrap();
+        command.arg("--input").arg(input_path);
+    }
+    let mut child = command
+        .arg(&path)
+        .stdin(Stdio::piped())
+        .stdout(Stdio::piped())
+        .spawn()
+        .unwrap();
+    let mut input = commands.join("\n");
+    input.push('\n');
+    child.stdin.take().unwrap().write_all(input.as_bytes()).unwrap();
+    let output = child.wait_with_output().unwrap();
+    assert!(output.status.success());
+
+    String::from_utf8(output.stdout)
+        .unwrap()
+        .split("(kdb) ")
+        .map(str::to_string)
+        .collect()
+}
+
+#[test]
+fn commands_are_parsed() {
+    let replies = debug("parsing", &[
+        "bogus",
+        "step x",
+        "step 0",
+        "break",
+        "break nowhere",
+        "delete 0x5",
+        "x",
+        "x main 3",
+        "  ",
+        "q",
+        "regs",
+    ]);
+    assert_eq!(replies, [
+        "=> 0x0000 <main>: mov ax, 0x14\n",
+        "unknown command `bogus`, try `help`\n",
+        "invalid count `x`\n",
+        "step count must be positive\n",
+        "usage: break <location>\n",
+        "unknown location `nowhere`\n",
+        "no breakpoint at 0x0005\n",
+        "usage: x <location> [count]\n",
+        "0x0000: 0x27 0x14 0x2a\n",
+        // empty line repeats `x main 3`
+        "0x0000: 0x27 0x14 0x2a\n",
+        // nothing is read after quit
+        "",
+    ]);
+}
+
+#[test]
+fn step_and_next() {
+    let replies = debug("step", &[
+        "step 0",
+        "regs",
+        "step 2",
+        "next",
+        "regs",
+        "s",
+        "",
+        "step 5",
+        "step",
+    ]);
+    assert_eq!(replies, [
+        "=> 0x0000 <main>: mov ax, 0x14\n",
+        "step count must be positive\n",
+        // `step 0` executes nothing
+        "ip=0x0 sp=0x2000 fp=0x2000 lp=0x1fff \
+         ax=0x0 bx=0x0 cx=0x0 dx=0x0\n",
+        "=> 0x0004: call\n",
+        // call of double is stepped over
+        "=> 0x0005: inc\n",
+        "ip=0x5 sp=0x2000 fp=0x2000 lp=0x1fff \
+         ax=0x28 bx=0x14 cx=0x0 dx=0xa\n",
+        "=> 0x0006: mov dx, ax\n",
+        "=> 0x0007: mov ax, 0x2\n",
+        "Program ended with code: 41\n",
+        "program is not running\n",
+        "",
+    ]);
+}
+
+#[test]
+fn breakpoints_and_finish() {
+    let replies = debug("break", &[
+        "break double",
+        "b 0x6",
+        "info",
+        "continue",
+        "next",
+        "finish",
+        "d double",
+        "c",
+        "c",
+    ]);
+    assert_eq!(replies, [
+        "=> 0x0000 <main>: mov ax, 0x14\n",
+        "Breakpoint at 0x000a <double>\n",
+        "Breakpoint at 0x0006\n",
+        "0x0006\n0x000a <double>\n",
+        "Breakpoint reached\n=> 0x000a <double>: mov bx, ax\n",
+        "=> 0x000b: add\n",
+        // returns to instruction after call
+        "=> 0x0005: inc\n",
+        "",
+        "Breakpoint reached\n=> 0x0006: mov dx, ax\n",
+        "Program ended with code: 41\n",
+        "",
+    ]);
+}
+
+#[test]
+fn program_reads_its_own_input() {
+    // commands after `continue` are not read by program
+    let replies = debug_program("echo", ECHO, Some("hi\n"), &[
+        "continue",
+        "regs",
+        "quit",
+    ]);
+    assert_eq!(replies, [
+        "=> 0x0008 <main>: mov dx, 0x0\n",
+        "hi\nProgram ended with code: 3\n",
+        "ip=0x2001 sp=0x2000 fp=0x2000 lp=0x1fff \
+         ax=0x3 bx=0x0 cx=0x0 dx=0x3\n",
+        "",
+    ]);
+
+    // without --input program gets end of input
+    let replies = debug_program("no-input", ECHO, None, &[
+        "step 3",
+        "regs",
+        "quit",
+    ]);
+    assert_eq!(replies, [
+        "=> 0x0008 <main>: mov dx, 0x0\n",
+        "=> 0x000d: mov cx, ax\n",
+        "ip=0xd sp=0x2000 fp=0x2000 lp=0x1fff \
+         ax=0xffffffffffffffff bx=0x0 cx=0x0 dx=0x0\n",
+        "",
+    ]);
+}
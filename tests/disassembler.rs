@@ -0,0 +1,213 @@
+// disassembled images assemble back into the same bytes
+
+use virtual_machine::{
+    disassemble,
+    image,
+    image::SymbolKind,
+    Assembler,
+    Image,
+    OpCode,
+    Word,
+};
+
+fn round_trip(image: &Image) -> String {
+    let source = disassemble(image);
+    let assembled = Assembler::new()
+        .assemble(&source)
+        .unwrap_or_else(|err| panic!("{err}\n{source}"));
+    assert_eq!(assembled.to_bytes(), image.to_bytes(), "\n{source}");
+    source
+}
+
+#[test]
+fn jump_targets_do_not_become_symbols() {
+    let mut image = Image::new();
+    let again = image.new_label();
+    image.emit_opcode_with_operand(OpCode::MOVE_OP_TO_AX, 3);
+    image.place_label(again).unwrap();
+    image.emit_opcode(OpCode::DEC);
+    image.emit_opcode_with_operand(OpCode::MOVE_OP_TO_BX, 0);
+    image.emit_opcode_with_label(OpCode::MOVE_OP_TO_DX, again);
+    image.emit_opcode(OpCode::JNE);
+    image.emit_opcode(OpCode::RET);
+    image.finalize().unwrap();
+
+    let source = round_trip(&image);
+    assert!(source.contains("loc_2:"), "{source}");
+    assert!(image.get_symbols().is_empty());
+}
+
+#[test]
+fn assembled_source_round_trips() {
+    let hello = Assembler::new()
+        .assemble(include_str!("../hello.kasm"))
+        .unwrap();
+    assert!(!hello.get_symbols().is_empty());
+    round_trip(&hello);
+}
+
+#[test]
+fn builder_images_round_trip() {
+    // data before entry point: string, words and address of code
+    let mut image = Image::new();
+    let function = image.new_label();
+    image.emit_str("Hello\n\t\"quoted\" \\");
+    image.emit_data(0);
+    image.emit_data(Word::MAX);
+    image.emit_label(function);
+    image.emit_data('x');
+    image.set_entry_point_here();
+    image.emit_opcode_with_operand(OpCode::LOAD_FP_OFF, (-2i64) as Word);
+    image.emit_opcode_with_operand(OpCode::STORE_LP_OFF, 3);
+    image.emit_opcode_with_operand(OpCode::LOAD_DX_OFF, Word::MAX);
+    image.emit_opcode_with_operand(OpCode::MOVE_OP_TO_CX, 1 << 63);
+    image.emit_opcode_with_label(OpCode::MOVE_OP_TO_DX, function);
+    image.emit_opcode(OpCode::CALL);
+    image.emit_opcode(OpCode::RET);
+    image.place_label(function).unwrap();
+    image.emit_opcode(OpCode::LEAVE);
+    image.emit_opcode(OpCode::RET);
+    image.finalize().unwrap();
+    image.set_bss_size(5);
+    image.set_memory_requirement(0x4000);
+
+    let source = round_trip(&image);
+    assert!(source.contains("load [fp-0x2]"), "{source}");
+    assert!(source.contains("load [dx-0x1]"), "{source}");
+
+    // the same image with symbols, two of them name one address
+    let main = image.get_entry_point();
+    let leave = image.get_label_address(function).unwrap();
+    let end = image.get_image().len() as Word;
+    image.define_symbol("greeting", 0, SymbolKind::Data).unwrap();
+    image.define_symbol("main", main, SymbolKind::Function).unwrap();
+    image.define_symbol("start", main, SymbolKind::Label).unwrap();
+    image.define_symbol("leave", leave, SymbolKind::Function).unwrap();
+    image.define_symbol("end", end, SymbolKind::Label).unwrap();
+    let source = round_trip(&image);
+    assert!(source.contains(".func main"), "{source}");
+    assert!(source.contains("mov dx, leave"), "{source}");
+}
+
+#[test]
+fn macro_images_round_trip() {
+    let image = image! {
+        data [
+            message: "a, b; c", 0,
+            table: @first, @second, (-1i64),
+        ];
+        entry:
+            mov dx, @table;
+            load [dx + 1];
+            mov dx, ax;
+            call;
+            store [lp - 1];
+            load [dx + bx];
+            store [dx];
+            ret;
+        first:
+            mov ax, 1;
+            ret;
+        second:
+            mov ax, (1.5f64.to_bits());
+            ret;
+    };
+    round_trip(&image);
+
+    // only code, which is unreachable from entry point
+    let image = image! {
+        entry:
+            ret;
+            mov ax, 2;
+            mov dx, 0;
+            jmp;
+    };
+    round_trip(&image);
+
+    round_trip(&Image::new());
+}
+
+#[test]
+fn call_target_survives_instructions_between_load_and_call() {
+    let image = Assembler::new()
+        .assemble("\
+.code
+.entry
+main:
+        mov dx, f
+        mov cx, 0x5
+        push
+        inc
+        enter 0x0
+        leave
+        call
+        pop
+        mov dx, ax
+        mov ax, 0x2
+        syscall
+f:
+        enter 0x0
+        load [fp+0x2]
+        leave
+        ret
+")
+        .unwrap();
+    let source = round_trip(&image);
+    assert!(!source.contains(".data"), "{source}");
+    assert!(source.contains("load [fp+0x2]"), "{source}");
+    assert!(source.contains("mov dx, f"), "{source}");
+}
+
+#[test]
+fn sections_of_source_round_trip() {
+    // procedure before entry point stays code, word between is data
+    let image = Assembler::new()
+        .assemble("\
+.code
+double: mov bx, ax
+        add
+        ret
+.data
+answer: 21
+.code
+.entry
+main:   mov dx, answer
+        load [dx+0]
+        mov dx, double
+        call
+        ret
+")
+        .unwrap();
+    let source = round_trip(&image);
+    assert!(source.starts_with(".code\n"), "{source}");
+    assert!(source.contains("    add\n"), "{source}");
+    assert_eq!(source.matches(".data").count(), 1, "{source}");
+}
+
+#[test]
+fn symbols_keep_kind_and_address() {
+    let mut image = Image::new();
+    image.emit_data(7);
+    image.emit_data(8);
+    image.set_entry_point_here();
+    let operand = image.emit_opcode_with_operand(OpCode::MOVE_OP_TO_AX, 1) + 1;
+    let ret = image.emit_opcode(OpCode::RET);
+    image.finalize().unwrap();
+    image.set_bss_size(4);
+    let end = image.get_image().len() as Word;
+
+    // kinds, which differ from section, and addresses, which can not
+    // be labeled: operand word and bss
+    image.define_symbol("table", 1, SymbolKind::Label).unwrap();
+    image.define_symbol("handler", 0, SymbolKind::Function).unwrap();
+    image.define_symbol("constant", operand, SymbolKind::Data).unwrap();
+    image.define_symbol("patched", operand, SymbolKind::Label).unwrap();
+    image.define_symbol("tail", ret, SymbolKind::Data).unwrap();
+    image.define_symbol("buffer", end + 1, SymbolKind::Data).unwrap();
+
+    let source = round_trip(&image);
+    assert!(source.contains("constant = 0x3\n.object constant"), "{source}");
+    assert!(source.contains("patched = 0x3\n"), "{source}");
+    assert!(source.contains(".label table"), "{source}");
+    assert!(source.contains(".object tail"), "{source}");
+}
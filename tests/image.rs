@@ -0,0 +1,270 @@
+// images: saved files, rejection of broken ones and of too large ones
+
+use virtual_machine::{
+    image::{SectionKind, FORMAT_VERSION, MAGIC},
+    op_codes::ISA_VERSION,
+    Assembler,
+    Image,
+    OpCode,
+    VirtualMachine,
+    VmError,
+    Word,
+};
+
+// header and section table of image file without payloads
+fn header(
+    format: u16,
+    isa: u16,
+    sections: &[(SectionKind, Word, Word)],
+) -> Vec<u8> {
+    let mut bytes = Vec::new();
+    bytes.extend_from_slice(&MAGIC);
+    bytes.extend_from_slice(&format.to_le_bytes());
+    bytes.extend_from_slice(&isa.to_le_bytes());
+    bytes.extend_from_slice(&0u32.to_le_bytes());
+    bytes.extend_from_slice(&0u64.to_le_bytes());
+    bytes.extend_from_slice(&0u64.to_le_bytes());
+    bytes.extend_from_slice(&(sections.len() as u32).to_le_bytes());
+    for &(kind, address, length) in sections {
+        bytes.extend_from_slice(&(kind as u32).to_le_bytes());
+        bytes.extend_from_slice(&0u32.to_le_bytes());
+        bytes.extend_from_slice(&address.to_le_bytes());
+        bytes.extend_from_slice(&length.to_le_bytes());
+    }
+    bytes
+}
+
+fn with_payload(mut bytes: Vec<u8>, words: &[Word]) -> Vec<u8> {
+    for word in words {
+        bytes.extend_from_slice(&word.to_le_bytes());
+    }
+    bytes
+}
+
+fn error(bytes: &[u8]) -> String {
+    Image::from_bytes(bytes).unwrap_err()
+}
+
+#[test]
+fn saved_image_is_loaded_back() {
+    let mut image = Image::new();
+    image.emit_str("hi");
+    image.set_entry_point_here();
+    image.emit_opcode_with_operand(OpCode::MOVE_OP_TO_AX, 7);
+    image.emit_opcode(OpCode::RET);
+    image.set_bss_size(3);
+    image.set_memory_requirement(0x100);
+
+    let loaded = Image::from_bytes(&image.to_bytes()).unwrap();
+    assert_eq!(loaded.get_image(), image.get_image());
+    assert_eq!(loaded.get_entry_point(), 2);
+    assert_eq!(loaded.get_bss_size(), 3);
+    assert_eq!(loaded.get_memory_requirement(), 0x100);
+    assert_eq!(loaded.to_bytes(), image.to_bytes());
+
+    let code = (SectionKind::Code, 0, 1);
+    let bytes = with_payload(header(FORMAT_VERSION, 0, &[code]), &[0]);
+    assert_eq!(Image::from_bytes(&bytes).unwrap().get_image(), &[0]);
+}
+
+#[test]
+fn sections_follow_directives_of_source() {
+    // helper before entry point is code, data between procedures
+    // is data, builder image without sections has data before entry
+    let image = Assembler::new()
+        .assemble("\
+.code
+double: mov bx, ax
+        add
+        ret
+.data
+answer: 21
+.code
+.entry
+main:   mov dx, answer
+        load [dx+0]
+        mov dx, double
+        call
+        ret
+")
+        .unwrap();
+    let sections = |image: &Image| -> Vec<(SectionKind, Word, Word)> {
+        image
+            .sections()
+            .iter()
+            .map(|section| (section.kind, section.address, section.length))
+            .collect()
+    };
+    let expected = [
+        (SectionKind::Code, 0, 3),
+        (SectionKind::Data, 3, 1),
+        (SectionKind::Code, 4, 8),
+    ];
+    assert_eq!(sections(&image), expected);
+    let loaded = Image::from_bytes(&image.to_bytes()).unwrap();
+    assert_eq!(sections(&loaded), expected);
+    assert_eq!(loaded.to_bytes(), image.to_bytes());
+
+    let mut image = Image::new();
+    image.emit_opcode(OpCode::RET);
+    image.set_entry_point_here();
+    image.emit_opcode(OpCode::RET);
+    assert_eq!(sections(&image), [
+        (SectionKind::Data, 0, 1),
+        (SectionKind::Code, 1, 1),
+    ]);
+    image.clear();
+    image.emit_opcode(OpCode::RET);
+    image.begin_section(SectionKind::Code);
+    image.set_entry_point_here();
+    image.emit_opcode(OpCode::RET);
+    assert_eq!(sections(&image), [(SectionKind::Code, 0, 2)]);
+}
+
+#[test]
+fn bad_headers_are_rejected() {
+    let mut bytes = header(FORMAT_VERSION, ISA_VERSION, &[]);
+    bytes[..4].copy_from_slice(b"ELF\0");
+    assert_eq!(error(&bytes), "not a kondra image: bad magic bytes");
+
+    for format in [0, FORMAT_VERSION + 1] {
+        let bytes = header(format, ISA_VERSION, &[]);
+        assert!(
+            error(&bytes).starts_with("unsupported image format version"),
+            "{format}"
+        );
+    }
+
+    let bytes = header(FORMAT_VERSION, ISA_VERSION + 1, &[]);
+    assert!(error(&bytes).starts_with(
+        &format!("image requires ISA version {}", ISA_VERSION + 1)
+    ));
+
+    let bytes = header(FORMAT_VERSION, ISA_VERSION, &[]);
+    for length in [0, 4, bytes.len() - 1] {
+        assert!(
+            error(&bytes[..length]).starts_with("file is too short"),
+            "{length}"
+        );
+    }
+}
+
+#[test]
+fn truncated_sections_are_rejected() {
+    let code = (SectionKind::Code, 0, 2);
+
+    // section table ends in the middle of entry
+    let bytes = header(FORMAT_VERSION, ISA_VERSION, &[code]);
+    let table = bytes.len() - 8;
+    assert!(error(&bytes[..table]).starts_with("unexpected end of file"));
+
+    let bytes = with_payload(header(FORMAT_VERSION, ISA_VERSION, &[code]), &[
+        OpCode::RET,
+    ]);
+    assert_eq!(error(&bytes), "truncated payload of section 0 (Code)");
+
+    let bytes = with_payload(
+        header(FORMAT_VERSION, ISA_VERSION, &[code]),
+        &[OpCode::RET, OpCode::RET, OpCode::RET],
+    );
+    assert_eq!(error(&bytes), "unexpected 8 trailing bytes");
+}
+
+#[test]
+fn sections_out_of_image_are_rejected() {
+    // small file with data far away from the start of image
+    let data = (SectionKind::Data, 1 << 40, 1);
+    let bytes = with_payload(header(FORMAT_VERSION, ISA_VERSION, &[data]), &[
+        7,
+    ]);
+    assert_eq!(
+        error(&bytes),
+        "section 0 (Data) at 0x10000000000 does not follow previous sections"
+    );
+
+    // overlapping sections
+    let sections = [(SectionKind::Data, 0, 2), (SectionKind::Code, 1, 1)];
+    let bytes = with_payload(
+        header(FORMAT_VERSION, ISA_VERSION, &sections),
+        &[1, 2, 3],
+    );
+    assert!(error(&bytes).starts_with("section 1 (Code) at 0x1"));
+
+    let code = (SectionKind::Code, Word::MAX, 2);
+    let bytes = header(FORMAT_VERSION, ISA_VERSION, &[code]);
+    assert_eq!(error(&bytes), "section 0 does not fit in address space");
+}
+
+#[test]
+fn bss_out_of_address_space_is_rejected() {
+    let code = (SectionKind::Code, 0, 1);
+
+    let sections = [code, (SectionKind::Bss, 1, Word::MAX)];
+    let bytes = with_payload(
+        header(FORMAT_VERSION, ISA_VERSION, &sections),
+        &[OpCode::RET],
+    );
+    assert_eq!(error(&bytes), "section 1 does not fit in address space");
+
+    // the second bss would end beyond the last address
+    let sections = [
+        code,
+        (SectionKind::Bss, 1, Word::MAX - 1),
+        (SectionKind::Bss, Word::MAX, 1),
+    ];
+    let bytes = with_payload(
+        header(FORMAT_VERSION, ISA_VERSION, &sections),
+        &[OpCode::RET],
+    );
+    assert_eq!(error(&bytes), "section 2 does not fit in address space");
+
+    let sections = [code, (SectionKind::Bss, 2, 1)];
+    let bytes = with_payload(
+        header(FORMAT_VERSION, ISA_VERSION, &sections),
+        &[OpCode::RET],
+    );
+    assert_eq!(error(&bytes), "bss section must follow code and data");
+}
+
+#[test]
+fn images_larger_than_memory_are_rejected() {
+    let load = |image: &Image| {
+        VirtualMachine::with_memory(0x800).load_image(image)
+    };
+
+    let mut image = Image::new();
+    image.emit_opcode(OpCode::RET);
+    image.set_bss_size(0xff);
+    assert!(load(&image).is_ok());
+
+    for bss in [0x100, Word::MAX / 8 - 1] {
+        image.set_bss_size(bss);
+        let err = load(&image).unwrap_err();
+        let required = (bss + 1) * 8;
+        assert!(
+            matches!(
+                err,
+                VmError::ImageTooLarge { required: r, available: 0x800 }
+                    if r == required
+            ),
+            "{err:?}"
+        );
+    }
+
+    // required bytes, which overflow word, are not reported saturated
+    for bss in [Word::MAX / 8, Word::MAX - 1, Word::MAX] {
+        image.set_bss_size(bss);
+        let err = load(&image).unwrap_err();
+        assert!(
+            matches!(err, VmError::BssTooLarge { words } if words == bss),
+            "{err:?}"
+        );
+    }
+
+    image.set_bss_size(0);
+    image.set_memory_requirement(0x801);
+    assert!(matches!(
+        load(&image),
+        Err(VmError::ImageTooLarge { required: 0x801, available: 0x800 })
+    ));
+}
@@ -0,0 +1,412 @@
+// compiled code gives the same results as interpreter
+#![cfg(feature = "jit")]
+
+mod common;
+
+use virtual_machine::{
+    image,
+    image::Image,
+    Limits,
+    OpCode,
+    Profiler,
+    VmError,
+    Word,
+};
+
+use common::Vm;
+
+const MEMORY: Word = 0x1000;
+
+fn vm(jit: bool, fuel: u64) -> Vm {
+    let mut vm = common::vm_with_memory(MEMORY, "");
+    vm.set_jit(jit).unwrap();
+    vm.set_limits(Limits {
+        fuel: Some(fuel),
+        ..Limits::none()
+    });
+    vm
+}
+
+// runs image with and without jit, compares everything
+// and returns instructions executed by compiled code
+fn compare(image: &Image, fuel: u64) -> u64 {
+    let results = [false, true].map(|jit| {
+        let mut vm = vm(jit, fuel);
+        vm.load_image(image).unwrap();
+        let result = vm.call(image.get_entry_point(), &[]);
+        (format!("{result:?}"), vm)
+    });
+    let (compiled, jitted) = &results[1];
+    let (interpreted, vm) = &results[0];
+    assert_eq!(compiled, interpreted, "{}", image.get_mnemonics());
+    assert_eq!(jitted.registers(), vm.registers());
+    assert_eq!(jitted.steps(), vm.steps());
+    assert_eq!(jitted.memory(), vm.memory());
+    assert_eq!(jitted.output(), vm.output());
+    jitted.jit_steps()
+}
+
+#[test]
+fn hot_loops_run_compiled() {
+    let countdown = image! {
+        entry:
+            mov ax, 100000;
+            mov bx, 0;
+            mov dx, @again;
+        again:
+            dec;
+            jne;
+            ret;
+    };
+    assert!(compare(&countdown, 1_000_000) > 100000);
+
+    // x = x * 0.5 + 1.0 in local variable, converges to 2
+    let reals = image! {
+        entry:
+            enter 2;
+            mov ax, 1000;
+            store [lp + 0];
+            mov ax, (0.0f64.to_bits());
+            store [lp + 1];
+        again:
+            load [lp + 1];
+            mov bx, (0.5f64.to_bits());
+            fmul;
+            mov bx, (1.0f64.to_bits());
+            fadd;
+            store [lp + 1];
+            load [lp + 0];
+            dec;
+            store [lp + 0];
+            mov bx, 0;
+            mov dx, @again;
+            jne;
+            load [lp + 1];
+            crtow;
+            leave;
+            ret;
+    };
+    assert!(compare(&reals, 1_000_000) > 10000);
+
+    // fuel runs out inside of compiled loop at the same step
+    assert!(compare(&countdown, 54321) > 0);
+}
+
+#[test]
+fn store_into_compiled_loop_drops_it() {
+    // counts to 1000 with inc, then patches it to dec and
+    // counts back to zero, stale inc would never get there
+    let image = image! {
+        entry:
+            mov ax, 0;
+            mov cx, 1000;
+        again:
+            mov bx, cx;
+        patch:
+            inc;
+            mov dx, @again;
+            jne;
+            mov bx, 0;
+            mov dx, @done;
+            je;
+            mov ax, (OpCode::DEC);
+            mov dx, @patch;
+            store [dx];
+            mov ax, 1000;
+            mov cx, 0;
+            mov dx, @again;
+            jmp;
+        done:
+            ret;
+    };
+    let mut vm = vm(true, 100_000);
+    vm.load_image(&image).unwrap();
+    assert_eq!(vm.call(0, &[]).unwrap(), 0);
+    assert!(vm.jit_steps() > 1000);
+    assert!(compare(&image, 100_000) > 1000);
+}
+
+#[test]
+fn compiled_stores_are_checked() {
+    // zeroes words below 300 till it overwrites its own jne
+    let overwrite = image! {
+        entry:
+            mov cx, 300;
+        again:
+            mov ax, cx;
+            dec;
+            mov cx, ax;
+            mov dx, ax;
+            mov ax, 0;
+            store [dx];
+            mov ax, cx;
+            mov bx, 0;
+            mov dx, @again;
+            jne;
+            ret;
+    };
+    assert!(compare(&overwrite, 100_000) > 1000);
+
+    // writes words from 300 till the end of memory
+    let overflow = image! {
+        entry:
+            mov cx, 300;
+        again:
+            mov dx, cx;
+            mov ax, 7;
+            store [dx];
+            mov ax, cx;
+            inc;
+            mov cx, ax;
+            mov bx, 0;
+            mov dx, @again;
+            jne;
+            ret;
+    };
+    assert!(compare(&overflow, 100_000) > 1000);
+
+    for image in [overwrite, overflow] {
+        let mut vm = vm(true, 100_000);
+        vm.load_image(&image).unwrap();
+        let result = vm.call(0, &[]);
+        assert!(
+            matches!(
+                result,
+                Err(VmError::InvalidOpcode { opcode: 0, .. } |
+                    VmError::BadAddress { address: 512, .. })
+            ),
+            "{result:?}"
+        );
+    }
+}
+
+#[test]
+fn returns_do_not_start_blocks() {
+    // sum of 1..=50 by recursion, every return lands on a straight
+    // block, which is long enough to compile, but it is not a loop
+    let image = image! {
+        entry:
+            mov cx, 50;
+            push;
+            mov dx, @sum;
+            call;
+            pop;
+            ret;
+        sum:
+            enter 0;
+            load [fp + 2];
+            mov bx, 0;
+            mov dx, @done;
+            je;
+            dec;
+            mov cx, ax;
+            push;
+            mov dx, @sum;
+            call;
+            mov bx, ax;
+            load [fp + 2];
+            add;
+            mov cx, ax;
+            mov ax, cx;
+            inc;
+            dec;
+            mov bx, ax;
+            mov ax, bx;
+            not;
+            not;
+        done:
+            leave;
+            ret;
+    };
+    assert_eq!(compare(&image, 100_000), 0);
+
+    let mut vm = vm(true, 100_000);
+    assert!(vm.predecode());
+    vm.load_image(&image).unwrap();
+    assert_eq!(vm.call(0, &[]).unwrap(), 1275);
+}
+
+// xorshift64
+struct Random(u64);
+
+impl Random {
+    fn next(&mut self) -> u64 {
+        self.0 ^= self.0 << 13;
+        self.0 ^= self.0 >> 7;
+        self.0 ^= self.0 << 17;
+        self.0
+    }
+
+    fn below(&mut self, n: u64) -> u64 {
+        self.next() % n
+    }
+
+    fn pick<T: Copy>(&mut self, items: &[T]) -> T {
+        items[self.below(items.len() as u64) as usize]
+    }
+}
+
+// instructions, which programs are mostly made of
+const COMMON: &[Word] = &[
+    OpCode::INC, OpCode::DEC, OpCode::NEG, OpCode::ADD, OpCode::SUB,
+    OpCode::MUL, OpCode::DIV, OpCode::FNEG, OpCode::FADD, OpCode::FSUB,
+    OpCode::FMUL, OpCode::FDIV, OpCode::AND, OpCode::OR, OpCode::XOR,
+    OpCode::NOT, OpCode::SHL, OpCode::SHR, OpCode::JMP, OpCode::JE,
+    OpCode::JNE, OpCode::JG, OpCode::JGE, OpCode::JL, OpCode::JLE,
+    OpCode::JA, OpCode::JAE, OpCode::JB, OpCode::JBE, OpCode::FJG,
+    OpCode::FJGE, OpCode::FJL, OpCode::FJLE, OpCode::MOVE_OP_TO_AX,
+    OpCode::MOVE_OP_TO_BX, OpCode::MOVE_OP_TO_CX, OpCode::MOVE_OP_TO_DX,
+    OpCode::MOVE_BX_TO_AX, OpCode::MOVE_CX_TO_AX, OpCode::MOVE_DX_TO_AX,
+    OpCode::MOVE_AX_TO_BX, OpCode::MOVE_CX_TO_BX, OpCode::MOVE_DX_TO_BX,
+    OpCode::MOVE_AX_TO_CX, OpCode::MOVE_BX_TO_CX, OpCode::MOVE_DX_TO_CX,
+    OpCode::MOVE_AX_TO_DX, OpCode::MOVE_BX_TO_DX, OpCode::MOVE_CX_TO_DX,
+    OpCode::CWTOR, OpCode::CSWTOR, OpCode::CRTOW, OpCode::CRTOSW,
+    OpCode::DEREF, OpCode::STORE, OpCode::LOAD_DX_OFF,
+    OpCode::STORE_DX_OFF, OpCode::LOAD_FP_OFF, OpCode::STORE_FP_OFF,
+    OpCode::LOAD_DX_BX, OpCode::STORE_DX_BX, OpCode::LOAD_LP_OFF,
+    OpCode::STORE_LP_OFF, OpCode::MOVE_SP_TO_AX, OpCode::MOVE_FP_TO_AX,
+    OpCode::MOVE_LP_TO_AX, OpCode::PUSH, OpCode::POP,
+];
+
+// words, which make registers interesting for every instruction
+fn value(random: &mut Random, code: &[Word]) -> Word {
+    let reals = [0.0, -0.0, 0.5, -1.5, 1e19, -1e19, 9.3e18, f64::NAN,
+                 f64::INFINITY, f64::NEG_INFINITY, 4096.0];
+    match random.below(8) {
+        0 => random.next(),
+        1 => random.pick(&reals).to_bits(),
+        2 => (random.next() as f64 / 7.0).to_bits(),
+        3 => random.pick(&[0, 1, 2, 63, 64, 1 << 63, Word::MAX]),
+        4 => MEMORY / 8 - random.below(32),
+        5 => random.below(8).wrapping_sub(4),
+        // code, for jumps and self-modification
+        _ => random.pick(code),
+    }
+}
+
+// random code, which mostly jumps within itself
+fn program(random: &mut Random) -> Image {
+    let count = 8 + random.below(40) as usize;
+    let opcodes: Vec<Word> = (0..count)
+        .map(|_| match random.below(20) {
+            0 => random.below(80),
+            _ => random.pick(COMMON),
+        })
+        .collect();
+    let mut starts = Vec::new();
+    let mut address = 0;
+    for &opcode in &opcodes {
+        starts.push(address);
+        address += 1 + OpCode::has_operand(opcode) as Word;
+    }
+
+    let mut image = Image::new();
+    for opcode in opcodes {
+        if OpCode::has_operand(opcode) {
+            let operand = match opcode {
+                OpCode::MOVE_OP_TO_DX if random.below(2) == 0 => {
+                    random.pick(&starts)
+                }
+                _ => value(random, &starts),
+            };
+            image.emit_opcode_with_operand(opcode, operand);
+        } else {
+            image.emit_opcode(opcode);
+        }
+    }
+    image
+}
+
+#[test]
+fn random_programs_match_interpreter() {
+    let mut random = Random(0x2545_f491_4f6c_dd1d);
+    let mut compiled = 0;
+    for _ in 0..2000 {
+        let image = program(&mut random);
+        compiled += compare(&image, 5000);
+    }
+    // most of programs trap soon, but some loop
+    assert!(compiled > 100_000, "{compiled}");
+}
+
+#[test]
+fn observed_code_is_interpreted() {
+    let image = image! {
+        entry:
+            mov ax, 1000;
+            mov bx, 0;
+            mov dx, @again;
+        again:
+            dec;
+            jne;
+            ret;
+    };
+    let mut vm = common::observed(Profiler::new());
+    vm.set_jit(true).unwrap();
+    vm.load_image(&image).unwrap();
+    vm.call(0, &[]).unwrap();
+    assert_eq!(vm.jit_steps(), 0);
+    assert_eq!(vm.observer().total(), vm.steps());
+}
+
+// instructions of dispatch benchmark, which run compiled,
+// timings of benchmark are not checked
+#[test]
+fn loops_run_compiled_and_recursion_predecoded() {
+    let countdown = image! {
+        entry:
+            mov ax, 100000;
+            mov bx, 0;
+            mov dx, @again;
+        again:
+            dec;
+            jne;
+            ret;
+    };
+    let mut vm = vm(true, 1_000_000);
+    vm.load_image(&countdown).unwrap();
+    assert_eq!(vm.call(0, &[]).unwrap(), 0);
+    // only setup and passes before loop gets hot run predecoded
+    let interpreted = vm.steps() - vm.jit_steps();
+    assert!(interpreted < 100, "{interpreted} of {}", vm.steps());
+
+    // without backward jumps compiled code is never entered
+    let fibonacci = image! {
+        entry:
+            mov cx, 15;
+            push;
+            mov dx, @fib;
+            call;
+            pop;
+            ret;
+
+        fib:
+            enter 1;
+            load [fp + 2];
+            mov bx, 2;
+            mov dx, @done;
+            jl;
+            dec;
+            mov cx, ax;
+            push;
+            mov dx, @fib;
+            call;
+            pop;
+            store [lp + 0];
+            load [fp + 2];
+            dec;
+            dec;
+            mov cx, ax;
+            push;
+            mov dx, @fib;
+            call;
+            pop;
+            mov bx, ax;
+            load [lp + 0];
+            add;
+        done:
+            leave;
+            ret;
+    };
+    assert_eq!(compare(&fibonacci, 1_000_000), 0);
+}
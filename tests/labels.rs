@@ -0,0 +1,77 @@
+// labels of image builder are patched when placed
+
+use virtual_machine::{Image, OpCode};
+
+#[test]
+fn forward_and_backward_references_are_patched() {
+    let mut image = Image::new();
+    let back = image.new_label();
+    let forward = image.named_label("forward");
+    image.place_label(back).unwrap();
+    image.emit_opcode_with_label(OpCode::MOVE_OP_TO_DX, forward);
+    image.emit_label(forward);
+    image.emit_opcode_with_label(OpCode::MOVE_OP_TO_DX, back);
+    assert_eq!(image.get_label_address(forward), None);
+    assert!(image.finalize().is_err());
+
+    image.place_label(forward).unwrap();
+    image.emit_opcode(OpCode::RET);
+    image.finalize().unwrap();
+    assert_eq!(image.get_label_address(forward), Some(5));
+    assert_eq!(image.named_label("forward"), forward);
+    assert_eq!(image.get_image(), &[
+        OpCode::MOVE_OP_TO_DX, 5,
+        5,
+        OpCode::MOVE_OP_TO_DX, 0,
+        OpCode::RET,
+    ]);
+}
+
+#[test]
+fn unplaced_labels_are_reported() {
+    let mut image = Image::new();
+    image.emit_opcode(OpCode::RET);
+    let label = image.named_label("missing");
+    image.emit_opcode_with_label(OpCode::MOVE_OP_TO_DX, label);
+    assert_eq!(
+        image.finalize(),
+        Err("label `missing` used at 0x2 is never placed".to_string())
+    );
+
+    // labels without name are reported by number
+    let mut image = Image::new();
+    let _ = image.new_label();
+    let label = image.new_label();
+    image.emit_label(label);
+    assert_eq!(
+        image.finalize(),
+        Err("label #1 used at 0x0 is never placed".to_string())
+    );
+
+    // label, which is created but never used, is no error
+    let mut image = Image::new();
+    let _ = image.named_label("unused");
+    assert_eq!(image.finalize(), Ok(()));
+}
+
+#[test]
+fn labels_are_placed_once() {
+    let mut image = Image::new();
+    let label = image.named_label("twice");
+    image.emit_opcode(OpCode::RET);
+    image.place_label(label).unwrap();
+    image.emit_opcode(OpCode::RET);
+    assert_eq!(
+        image.place_label(label),
+        Err("label `twice` is already placed at 0x1".to_string())
+    );
+    // the first address is kept
+    assert_eq!(image.get_label_address(label), Some(1));
+
+    let label = image.new_label();
+    image.place_label(label).unwrap();
+    assert_eq!(
+        image.place_label(label),
+        Err("label #1 is already placed at 0x2".to_string())
+    );
+}
@@ -0,0 +1,141 @@
+// sandbox limits, every limit traps with its own error
+
+use std::time::Duration;
+
+mod common;
+
+use virtual_machine::{
+    image,
+    image::Image,
+    syscall,
+    Limits,
+    StepOutcome,
+    VmError,
+};
+
+use common::Vm;
+
+fn vm(image: &Image, input: &str, limits: Limits) -> Vm {
+    let mut vm = common::vm(input);
+    vm.load_image(image).unwrap();
+    vm.set_limits(limits);
+    vm
+}
+
+fn endless() -> Image {
+    image! {
+        entry:
+            mov dx, @again;
+        again:
+            inc;
+            jmp;
+    }
+}
+
+#[test]
+fn fuel_stops_program_and_may_be_refilled() {
+    let limits = Limits { fuel: Some(100), ..Limits::none() };
+    let mut vm = vm(&endless(), "", limits);
+    let err = vm.execute().unwrap_err();
+    assert!(matches!(err, VmError::FuelExhausted { limit: 100, .. }));
+    assert_eq!(vm.steps(), 100);
+
+    // program continues from trapped instruction
+    vm.set_limits(Limits { fuel: Some(101), ..limits });
+    assert!(matches!(vm.step(), StepOutcome::Continued));
+    assert!(matches!(vm.step(), StepOutcome::Trapped(_)));
+}
+
+#[test]
+fn time_limit_stops_endless_program() {
+    let limits = Limits {
+        time: Some(Duration::from_millis(10)),
+        ..Limits::none()
+    };
+    let err = vm(&endless(), "", limits).execute().unwrap_err();
+    assert!(matches!(err, VmError::TimeLimitExceeded { .. }));
+}
+
+#[test]
+fn heap_limit_counts_allocated_blocks() {
+    let image = image! {
+        entry:
+            mov cx, 64;
+            malloc;
+            free;
+            malloc;
+            malloc;
+    };
+    let limits = Limits { heap_bytes: Some(100), ..Limits::none() };
+    let mut vm = vm(&image, "", limits);
+    let err = vm.execute().unwrap_err();
+    assert!(matches!(err, VmError::HeapLimitExceeded { limit: 100, .. }));
+    assert_eq!(err.ip(), Some(image.get_entry_point() + 5));
+}
+
+#[test]
+fn output_limit_truncates_print() {
+    let image = image! {
+        data [text: "hello"];
+        entry:
+            mov dx, @text;
+            mov cx, 5;
+            mov ax, syscall::PRINT;
+            syscall;
+    };
+    let limits = Limits { output_bytes: Some(3), ..Limits::none() };
+    let mut vm = vm(&image, "", limits);
+    let err = vm.execute().unwrap_err();
+    assert!(matches!(err, VmError::OutputLimitExceeded { limit: 3, .. }));
+    assert_eq!(vm.output().as_slice(), b"hel");
+    assert_eq!(vm.output_bytes(), 3);
+}
+
+#[test]
+fn output_limit_keeps_whole_characters() {
+    let image = image! {
+        data [text: "aé€"];
+        entry:
+            mov dx, @text;
+            mov cx, 3;
+            mov ax, syscall::PRINT;
+            syscall;
+    };
+    // limit falls inside of second and third characters
+    for (limit, output) in [(2, "a"), (4, "aé"), (5, "aé")] {
+        let limits = Limits { output_bytes: Some(limit), ..Limits::none() };
+        let mut vm = vm(&image, "", limits);
+        let err = vm.execute().unwrap_err();
+        assert!(matches!(err, VmError::OutputLimitExceeded { .. }));
+        assert_eq!(vm.output().as_slice(), output.as_bytes());
+        assert_eq!(vm.output_bytes(), output.len() as u64);
+    }
+}
+
+#[test]
+fn input_limit_stops_read_line() {
+    let image = image! {
+        entry:
+            mov dx, 0x100;
+            mov ax, syscall::READ_LINE;
+            syscall;
+            mov dx, 0x100;
+            mov ax, syscall::READ_LINE;
+            syscall;
+    };
+    let limits = Limits { input_bytes: Some(6), ..Limits::none() };
+    let mut vm = vm(&image, "ab\ncdef\n", limits);
+    let err = vm.execute().unwrap_err();
+    assert!(matches!(err, VmError::InputLimitExceeded { limit: 6, .. }));
+    assert_eq!(vm.input_bytes(), 6);
+}
+
+#[test]
+fn load_image_resets_counters() {
+    let image = endless();
+    let mut vm = vm(&image, "", Limits { fuel: Some(10), ..Limits::none() });
+    assert!(vm.execute().is_err());
+    vm.load_image(&image).unwrap();
+    assert_eq!(vm.steps(), 0);
+    assert!(matches!(vm.run_for(5), StepOutcome::Continued));
+}
@@ -0,0 +1,137 @@
+// grammar of image! macro
+
+use virtual_machine::{image, syscall, OpCode, Real, Word};
+
+const STEP: Word = 3;
+
+#[test]
+fn operands() {
+    let image = image! {
+        mov ax, 7;
+        mov bx, -1;
+        mov cx, 0xffff_ffff_ffff_ffffu64;
+        mov dx, STEP;
+        mov ax, syscall::EXIT;
+        mov bx, OpCode::RET;
+        mov cx, (STEP * 2 + 1);
+        mov dx, 1.5;
+        mov ax, 'z';
+        mov bx, (-0.5 as Real);
+        enter 2;
+        enter (STEP);
+    };
+    assert_eq!(image.get_image(), &[
+        OpCode::MOVE_OP_TO_AX, 7,
+        OpCode::MOVE_OP_TO_BX, Word::MAX,
+        OpCode::MOVE_OP_TO_CX, Word::MAX,
+        OpCode::MOVE_OP_TO_DX, 3,
+        OpCode::MOVE_OP_TO_AX, syscall::EXIT,
+        OpCode::MOVE_OP_TO_BX, OpCode::RET,
+        OpCode::MOVE_OP_TO_CX, 7,
+        OpCode::MOVE_OP_TO_DX, 1.5f64.to_bits(),
+        OpCode::MOVE_OP_TO_AX, 'z' as Word,
+        OpCode::MOVE_OP_TO_BX, (-0.5f64).to_bits(),
+        OpCode::ENTER, 2,
+        OpCode::ENTER, 3,
+    ]);
+}
+
+#[test]
+fn register_moves() {
+    let image = image! {
+        mov ax, bx; mov ax, cx; mov ax, dx;
+        mov bx, ax; mov bx, cx; mov bx, dx;
+        mov cx, ax; mov cx, bx; mov cx, dx;
+        mov dx, ax; mov dx, bx; mov dx, cx;
+        mov ax, sp; mov ax, fp; mov ax, lp;
+        mov sp, ax; mov fp, ax; mov lp, ax;
+    };
+    assert_eq!(image.get_image(), &[
+        OpCode::MOVE_BX_TO_AX, OpCode::MOVE_CX_TO_AX, OpCode::MOVE_DX_TO_AX,
+        OpCode::MOVE_AX_TO_BX, OpCode::MOVE_CX_TO_BX, OpCode::MOVE_DX_TO_BX,
+        OpCode::MOVE_AX_TO_CX, OpCode::MOVE_BX_TO_CX, OpCode::MOVE_DX_TO_CX,
+        OpCode::MOVE_AX_TO_DX, OpCode::MOVE_BX_TO_DX, OpCode::MOVE_CX_TO_DX,
+        OpCode::MOVE_SP_TO_AX, OpCode::MOVE_FP_TO_AX, OpCode::MOVE_LP_TO_AX,
+        OpCode::MOVE_AX_TO_SP, OpCode::MOVE_AX_TO_FP, OpCode::MOVE_AX_TO_LP,
+    ]);
+}
+
+#[test]
+fn addresses() {
+    let image = image! {
+        load [dx];
+        load [dx + 2];
+        load [dx - 2];
+        load [fp + 2];
+        load [fp - (STEP + 1)];
+        load [lp + STEP];
+        load [dx + bx];
+        store [dx];
+        store [dx + 1];
+        store [fp - 1];
+        store [lp];
+        store [lp + 0];
+        store [dx + bx];
+    };
+    let minus = |value: Word| value.wrapping_neg();
+    assert_eq!(image.get_image(), &[
+        OpCode::LOAD_DX_OFF, 0,
+        OpCode::LOAD_DX_OFF, 2,
+        OpCode::LOAD_DX_OFF, minus(2),
+        OpCode::LOAD_FP_OFF, 2,
+        OpCode::LOAD_FP_OFF, minus(4),
+        OpCode::LOAD_LP_OFF, 3,
+        OpCode::LOAD_DX_BX,
+        OpCode::STORE,
+        OpCode::STORE_DX_OFF, 1,
+        OpCode::STORE_FP_OFF, minus(1),
+        OpCode::STORE_LP_OFF, 0,
+        OpCode::STORE_LP_OFF, 0,
+        OpCode::STORE_DX_BX,
+    ]);
+}
+
+#[test]
+fn labels_and_data() {
+    let image = image! {
+        data [
+            text: "hi", 0,
+            table: @second, @text, (STEP * 2), -1, syscall::EXIT,
+        ];
+        entry:
+        first:
+            mov dx, @second;
+            jmp;
+        second: mov dx, @first; jmp;
+        data [@end];
+        end:
+    };
+    assert_eq!(image.get_entry_point(), 8);
+    assert_eq!(image.get_image(), &[
+        'h' as Word, 'i' as Word, 0,
+        11, 0, 6, Word::MAX, syscall::EXIT,
+        OpCode::MOVE_OP_TO_DX, 11,
+        OpCode::JMP,
+        OpCode::MOVE_OP_TO_DX, 8,
+        OpCode::JMP,
+        15,
+    ]);
+}
+
+#[test]
+#[should_panic(expected = "label `again` is already placed at 0x0")]
+fn label_placed_twice_panics() {
+    let _ = image! {
+        again: ret;
+        again: ret;
+    };
+}
+
+#[test]
+#[should_panic(expected = "label `nowhere` used at 0x1 is never placed")]
+fn label_never_placed_panics() {
+    let _ = image! {
+        mov dx, @nowhere;
+        jmp;
+    };
+}
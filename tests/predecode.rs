@@ -0,0 +1,180 @@
+// writes into code are seen by predecoded dispatch
+
+mod common;
+
+use std::io;
+
+use virtual_machine::{
+    image,
+    image::Image,
+    Limits,
+    OpCode,
+    SyscallContext,
+    Word,
+};
+
+// syscall, which writes bx to dx
+const POKE: Word = 10;
+
+fn run(image: &Image, predecode: bool) -> Word {
+    let mut vm = common::vm("");
+    vm.set_predecode(predecode);
+    assert_eq!(vm.predecode(), predecode);
+    vm.register_syscall(POKE, |context: &mut SyscallContext| {
+        let registers = *context.registers;
+        context.write(registers.dx, registers.bx)
+    });
+    vm.load_image(image).unwrap();
+    vm.call(image.get_entry_point(), &[]).unwrap()
+}
+
+#[test]
+fn store_replaces_decoded_opcode() {
+    // inc is replaced by dec before the second pass, 12 if it is not
+    let image = image! {
+        entry:
+            mov ax, 10;
+        patch:
+            inc;
+            mov cx, ax;
+            mov ax, (OpCode::DEC);
+            mov dx, @patch;
+            store [dx];
+            mov ax, cx;
+            mov bx, 11;
+            mov dx, @patch;
+            je;
+            ret;
+    };
+    assert_eq!(run(&image, false), 10);
+    assert_eq!(run(&image, true), 10);
+}
+
+#[test]
+fn store_replaces_decoded_operand() {
+    // operand 1 is replaced by 5 before the second pass, 2 if it is not
+    let image = image! {
+        entry:
+            mov bx, 0;
+        value:
+            mov ax, 1;
+            add;
+            mov bx, ax;
+            mov ax, 5;
+            mov dx, @value;
+            store [dx + 1];
+            mov ax, bx;
+            mov bx, 1;
+            mov dx, @value;
+            je;
+            ret;
+    };
+    assert_eq!(run(&image, false), 6);
+    assert_eq!(run(&image, true), 6);
+}
+
+#[test]
+fn syscall_replaces_decoded_opcode() {
+    let image = image! {
+        entry:
+            mov ax, 10;
+        patch:
+            inc;
+            mov cx, ax;
+            mov ax, POKE;
+            mov bx, (OpCode::DEC);
+            mov dx, @patch;
+            syscall;
+            mov ax, cx;
+            mov bx, 11;
+            mov dx, @patch;
+            je;
+            ret;
+    };
+    assert_eq!(run(&image, false), 10);
+    assert_eq!(run(&image, true), 10);
+}
+
+#[test]
+fn faults_and_steps_match_memory_dispatch() {
+    // counts down in called procedure, then reads out of memory
+    let image = image! {
+        entry:
+            mov ax, 50;
+        again:
+            mov dx, @step;
+            call;
+            mov bx, 0;
+            mov dx, @again;
+            jne;
+            mov dx, (Word::MAX);
+            load [dx + 0];
+            ret;
+        step:
+            dec;
+            ret;
+    };
+    let cases = [
+        (None, "BadAddress"),
+        (Some(7), "FuelExhausted"),
+        (Some(200), "FuelExhausted"),
+    ];
+    for (fuel, error) in cases {
+        let results = [false, true].map(|predecode| {
+            let mut vm = common::vm("");
+            vm.set_predecode(predecode);
+            vm.set_limits(Limits {
+                fuel,
+                ..Limits::none()
+            });
+            vm.load_image(&image).unwrap();
+            let result = vm.call(image.get_entry_point(), &[]);
+            (format!("{result:?}"), vm.registers(), vm.steps())
+        });
+        assert!(results[0].0.contains(error), "{}", results[0].0);
+        assert_eq!(results[0], results[1]);
+    }
+}
+
+#[test]
+fn failed_syscall_replaces_decoded_opcode() {
+    // syscalls write bx to dx, then fail or exceed output limit
+    const FAIL: Word = 11;
+    const FLOOD: Word = 12;
+    for code in [FAIL, FLOOD] {
+        let image = image! {
+                mov ax, 10;
+            patch:
+                inc;
+                ret;
+            entry:
+                mov ax, code;
+                mov bx, (OpCode::DEC);
+                mov dx, @patch;
+                syscall;
+                ret;
+        };
+        let mut vm = common::vm("");
+        vm.set_predecode(true);
+        vm.set_limits(Limits {
+            output_bytes: Some(1),
+            ..Limits::none()
+        });
+        vm.register_syscall(FAIL, |context: &mut SyscallContext| {
+            let registers = *context.registers;
+            context.write(registers.dx, registers.bx)?;
+            Err(context.io_error(io::Error::other("failed")))
+        });
+        vm.register_syscall(FLOOD, |context: &mut SyscallContext| {
+            let registers = *context.registers;
+            context.write(registers.dx, registers.bx)?;
+            write!(context.output, "flood").map_err(|e| context.io_error(e))
+        });
+        vm.load_image(&image).unwrap();
+        assert_eq!(vm.call(0, &[]).unwrap(), 11);
+        let err = vm.call(image.get_entry_point(), &[]).unwrap_err();
+        let expected = if code == FAIL { "Io" } else { "OutputLimitExceeded" };
+        assert!(format!("{err:?}").contains(expected), "{err:?}");
+        assert_eq!(vm.call(0, &[]).unwrap(), 9);
+    }
+}
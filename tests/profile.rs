@@ -0,0 +1,67 @@
+// profiler counts instructions and attributes them to procedures
+
+mod common;
+
+use virtual_machine::{
+    image,
+    image::SymbolKind,
+    OpCode,
+    Profiler,
+};
+
+#[test]
+fn recursive_calls_are_profiled() {
+    // main calls countdown, which calls itself till ax is zero
+    let mut image = image! {
+        entry:
+        main:
+            mov ax, 3;
+            mov dx, @countdown;
+            call;
+            ret;
+        countdown:
+            mov bx, 0;
+            mov dx, @done;
+            je;
+            dec;
+            mov dx, @countdown;
+            call;
+        done:
+            ret;
+    };
+    let label = image.named_label("countdown");
+    let countdown = image.get_label_address(label).unwrap();
+    image.define_symbol("main", 0, SymbolKind::Function).unwrap();
+    image
+        .define_symbol("countdown", countdown, SymbolKind::Function)
+        .unwrap();
+
+    let mut vm = common::observed(Profiler::new());
+    vm.load_image(&image).unwrap();
+    vm.call(0, &[]).unwrap();
+
+    let profiler = vm.observer();
+    // main 4, three countdowns of 7 and the last of 4
+    assert_eq!(profiler.total(), 29);
+    assert_eq!(profiler.count(countdown), 4);
+    assert_eq!(profiler.opcode_count(OpCode::CALL), 4);
+    assert_eq!(profiler.opcode_count(OpCode::RET), 5);
+
+    assert_eq!(
+        profiler.folded(vm.symbols()),
+        "main 4\n\
+         main;countdown 7\n\
+         main;countdown;countdown 7\n\
+         main;countdown;countdown;countdown 7\n\
+         main;countdown;countdown;countdown;countdown 4\n"
+    );
+
+    let report = profiler.report(vm.symbols(), vm.memory());
+    assert!(report.starts_with("instructions: 29\n"));
+    // recursive calls are counted once in total
+    assert!(report.contains(
+        "          25  86.21           25  86.21  countdown\n\
+         \x20          4  13.79           29 100.00  main\n"
+    ), "{report}");
+    assert!(report.contains("call                      countdown+0x8"));
+}
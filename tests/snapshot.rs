@@ -0,0 +1,246 @@
+// state of program saved to bytes and continued in other vm
+
+mod common;
+
+use std::{
+    io::Write,
+    process::{Command, Output, Stdio},
+};
+
+use virtual_machine::{
+    image,
+    image::Image,
+    snapshot::MAX_MEMORY_WORDS,
+    syscall,
+    Assembler,
+    Snapshot,
+    StepOutcome,
+    Word,
+};
+
+use common::vm;
+
+// reads line, allocates blocks and prints the line twice
+fn program() -> Image {
+    image! {
+        entry:
+            mov cx, 16;
+            malloc;
+            mov cx, 80;
+            malloc;
+            mov dx, ax;
+            mov bx, dx;
+            mov ax, syscall::READ_LINE;
+            syscall;
+            mov cx, ax;
+            mov dx, bx;
+            mov ax, syscall::PRINT;
+            syscall;
+            mov cx, 16;
+            malloc;
+            mov dx, bx;
+            mov cx, 3;
+            mov ax, syscall::PRINT;
+            syscall;
+            mov ax, syscall::EXIT;
+            syscall;
+    }
+}
+
+#[test]
+fn restored_program_continues_where_it_stopped() {
+    let image = program();
+    let mut whole = vm("abc\n");
+    whole.load_image(&image).unwrap();
+    let result = whole.execute().unwrap();
+
+    for steps in 1..20 {
+        let mut first = vm("abc\n");
+        first.load_image(&image).unwrap();
+        if !matches!(first.run_for(steps), StepOutcome::Continued) {
+            continue;
+        }
+        let bytes = first.snapshot().to_bytes();
+        let snapshot = Snapshot::from_bytes(&bytes).unwrap();
+        assert_eq!(snapshot.steps(), steps);
+
+        // host skips input, which was read before snapshot
+        let consumed = snapshot.input_bytes() as usize;
+        let mut second = vm(&"abc\n"[consumed..]);
+        second.restore(&snapshot);
+        assert_eq!(second.execute().unwrap(), result);
+        assert_eq!(second.registers(), whole.registers());
+        assert_eq!(second.memory(), whole.memory());
+
+        let mut output = first.output().clone();
+        output.truncate(snapshot.output_bytes() as usize);
+        output.extend_from_slice(second.output());
+        assert_eq!(output, *whole.output());
+    }
+}
+
+#[test]
+fn zero_memory_is_compressed() {
+    let mut vm = vm("");
+    vm.load_image(&program()).unwrap();
+    let bytes = vm.snapshot().to_bytes();
+    assert!(bytes.len() < 1024, "{} bytes", bytes.len());
+    assert_eq!(Snapshot::from_bytes(&bytes).unwrap().memory(), vm.memory());
+}
+
+#[test]
+fn corrupted_snapshots_are_rejected() {
+    let mut vm = vm("");
+    vm.load_image(&program()).unwrap();
+    let bytes = vm.snapshot().to_bytes();
+
+    let err = Snapshot::from_bytes(b"KNDR").unwrap_err();
+    assert!(err.contains("bad magic"), "{err}");
+
+    let err = Snapshot::from_bytes(&bytes[..bytes.len() - 1]).unwrap_err();
+    assert!(err.contains("unexpected end"), "{err}");
+
+    let mut longer = bytes.clone();
+    longer.push(0);
+    assert!(Snapshot::from_bytes(&longer).is_err());
+
+    // memory size follows header and 12 words of registers and counters,
+    // huge size is rejected before memory is allocated
+    let offset = 12 + 12 * 8;
+    let mut huge = bytes[..offset].to_vec();
+    huge.extend_from_slice(&Word::MAX.to_le_bytes());
+    huge.extend_from_slice(&0u64.to_le_bytes());
+    let err = Snapshot::from_bytes(&huge).unwrap_err();
+    assert!(err.contains("larger than"), "{err}");
+
+    let mut truncated = bytes[..offset].to_vec();
+    truncated.extend_from_slice(&MAX_MEMORY_WORDS.to_le_bytes());
+    let err = Snapshot::from_bytes(&truncated).unwrap_err();
+    assert!(err.contains("unexpected end"), "{err}");
+}
+
+// replaces heap words of snapshot, returns them and new bytes
+fn with_heap(bytes: &[u8], heap: &[Word]) -> (Vec<Word>, Vec<u8>) {
+    let word = |position: usize| {
+        Word::from_le_bytes(bytes[position..position + 8].try_into().unwrap())
+    };
+    // header, registers, depth, counters and memory size
+    let mut position = 12 + 13 * 8;
+    let runs = word(position);
+    position += 8;
+    for _ in 0..runs {
+        position += 16 + word(position + 8) as usize * 8;
+    }
+    let length = word(position) as usize;
+    let start = position + 8;
+    let end = start + length * 8;
+    let old = (start..end).step_by(8).map(word).collect();
+
+    let mut result = bytes[..position].to_vec();
+    result.extend_from_slice(&(heap.len() as Word).to_le_bytes());
+    for word in heap {
+        result.extend_from_slice(&word.to_le_bytes());
+    }
+    result.extend_from_slice(&bytes[end..]);
+    (old, result)
+}
+
+#[test]
+fn corrupted_heap_is_rejected() {
+    // stops after two blocks are allocated
+    let mut running = vm("");
+    running.load_image(&program()).unwrap();
+    assert!(matches!(running.run_for(4), StepOutcome::Continued));
+    let bytes = running.snapshot().to_bytes();
+    let size = running.memory().len() as Word;
+
+    let (heap, _) = with_heap(&bytes, &[]);
+    let (top, first, second) = (heap[0], heap[2], heap[4]);
+    assert_eq!(heap, [top, 2, first, 2, second, 10, 0, 0]);
+    assert_eq!(with_heap(&bytes, &heap).1, bytes);
+
+    let cases: [(&[Word], String); 6] = [
+        (&[size + 1, 0, 0, 0],
+         format!("heap top 0x{:x} is outside of memory", size + 1)),
+        (&[0, 0, 0, 0], "heap top 0x0 is outside of memory".to_string()),
+        // free block beyond memory would be reused by malloc
+        (&[size + 8, 0, 1, size, 8, 0],
+         format!("heap top 0x{:x} is outside of memory", size + 8)),
+        (&[top, 1, top, 1, 0, 0],
+         format!("heap block 0x{top:x} is outside of heap")),
+        (&[top, 1, 0, 2, 0, 0], "heap block 0x0 is outside of heap".into()),
+        (&[top, 1, first, 2, 1, first + 1, 1, 0],
+         format!("heap block 0x{:x} overlaps other block", first + 1)),
+    ];
+    for (heap, message) in cases {
+        let (_, corrupted) = with_heap(&bytes, heap);
+        assert_eq!(Snapshot::from_bytes(&corrupted).unwrap_err(), message);
+    }
+
+    // valid free list is kept
+    let heap = [top, 1, second, 10, 1, first, 2, 1, first];
+    let (_, freed) = with_heap(&bytes, &heap);
+    let mut restored = vm("");
+    restored.restore(&Snapshot::from_bytes(&freed).unwrap());
+    assert_eq!(restored.snapshot().to_bytes(), freed);
+}
+
+// runs vm binary with arguments and input
+fn run(args: &[&str], input: &str) -> Output {
+    let mut child = Command::new(env!("CARGO_BIN_EXE_virtual-machine"))
+        .arg("run")
+        .args(args)
+        .stdin(Stdio::piped())
+        .stdout(Stdio::piped())
+        .stderr(Stdio::piped())
+        .spawn()
+        .unwrap();
+    child.stdin.take().unwrap().write_all(input.as_bytes()).unwrap();
+    child.wait_with_output().unwrap()
+}
+
+#[test]
+fn resumed_run_skips_consumed_input() {
+    // reads two lines and prints the second one
+    let source = "
+.data
+buffer: 0, 0, 0, 0, 0, 0, 0, 0
+.code
+.entry
+main:   mov dx, buffer
+        mov ax, 1
+        syscall
+        mov dx, buffer
+        mov ax, 1
+        syscall
+        mov cx, ax
+        mov dx, buffer
+        mov ax, 0
+        syscall
+        mov dx, 0
+        mov ax, 2
+        syscall
+";
+    let dir = env!("CARGO_TARGET_TMPDIR");
+    let image = format!("{dir}/resume.kondra");
+    let snapshot = format!("{dir}/resume.knds");
+    Assembler::new()
+        .assemble(source)
+        .unwrap()
+        .save_to_file(&image)
+        .unwrap();
+
+    // fuel runs out after the first read
+    let output = run(
+        &["--fuel", "4", "--checkpoint", &snapshot, &image],
+        "one\ntwo\n",
+    );
+    assert!(!output.status.success());
+    let saved = Snapshot::load_from_file(&snapshot).unwrap();
+    assert_eq!(saved.input_bytes(), 4);
+
+    let output = run(&["--resume", &snapshot], "one\ntwo\n");
+    assert!(output.status.success());
+    let stdout = String::from_utf8(output.stdout).unwrap();
+    assert_eq!(stdout, "two\nProgram ended with code: 0\n");
+}
@@ -0,0 +1,84 @@
+// symbols of images are saved with them and called by name
+
+mod common;
+
+use virtual_machine::{
+    image::{Symbol, SymbolKind},
+    Assembler,
+    Image,
+    VmError,
+};
+
+const SOURCE: &str = "
+.data
+answer: 42
+.code
+.func add
+add:    enter 0
+        load [fp+0x2]
+        mov bx, ax
+        load [fp+0x3]
+        add
+done:   leave
+        ret
+";
+
+fn saved_and_loaded(image: &Image, name: &str) -> Image {
+    let path = format!("{}/{name}.kondra", env!("CARGO_TARGET_TMPDIR"));
+    image.save_to_file(&path).unwrap();
+    let mut loaded = Image::new();
+    loaded.load_from_file(&path).unwrap();
+    loaded
+}
+
+#[test]
+fn symbols_survive_save_and_load() {
+    let image = Assembler::new().assemble(SOURCE).unwrap();
+    let loaded = saved_and_loaded(&image, "symbols");
+    assert_eq!(loaded.get_symbols(), image.get_symbols());
+    let symbol = |kind, address| Some(Symbol { kind, address });
+    assert_eq!(loaded.get_symbols().len(), 3);
+    assert_eq!(loaded.get_symbol("answer"), symbol(SymbolKind::Data, 0));
+    assert_eq!(loaded.get_symbol("add"), symbol(SymbolKind::Function, 1));
+    assert_eq!(loaded.get_symbol("done"), symbol(SymbolKind::Label, 9));
+
+    // names which are not whole words are padded
+    let mut image = Image::new();
+    image.emit_data(0);
+    for (idx, name) in ["a", "eight_ch", "nine_char", "юникод"]
+        .iter()
+        .enumerate()
+    {
+        image.define_symbol(name, idx as u64, SymbolKind::Label).unwrap();
+    }
+    let loaded = Image::from_bytes(&image.to_bytes()).unwrap();
+    assert_eq!(loaded.get_symbols(), image.get_symbols());
+}
+
+#[test]
+fn procedures_are_called_by_name() {
+    let image = saved_and_loaded(
+        &Assembler::new().assemble(SOURCE).unwrap(),
+        "call",
+    );
+    let mut vm = common::vm("");
+    vm.load_image(&image).unwrap();
+
+    assert_eq!(vm.symbol("answer").map(|s| s.address), Some(0));
+    assert_eq!(vm.call_symbol("add", &[2, 3]).unwrap(), 5);
+    assert_eq!(vm.call_symbol("add", &[40, 2]).unwrap(), 42);
+
+    let err = vm.call_symbol("sub", &[2, 3]).unwrap_err();
+    assert!(
+        matches!(&err, VmError::UnknownSymbol { name } if name == "sub"),
+        "{err:?}"
+    );
+    assert_eq!(err.to_string(), "unknown symbol `sub`");
+
+    // symbols are replaced by the next image
+    vm.load_image(&Image::new()).unwrap();
+    assert!(matches!(
+        vm.call_symbol("add", &[]),
+        Err(VmError::UnknownSymbol { .. })
+    ));
+}
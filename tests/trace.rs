@@ -0,0 +1,100 @@
+// tracer observes executed instructions
+
+mod common;
+
+use virtual_machine::{
+    image,
+    image::Image,
+    syscall,
+    OpCode,
+    TraceFormat,
+    Tracer,
+    VmError,
+};
+
+fn trace(image: &Image, tracer: Tracer<Vec<u8>>)
+    -> (Result<u64, VmError>, String)
+{
+    let mut vm = common::observed(tracer);
+    vm.load_image(image).unwrap();
+    let result = vm.execute();
+    vm.observer_mut().flush().unwrap();
+    let text = String::from_utf8(vm.observer().output().clone()).unwrap();
+    (result, text)
+}
+
+fn program() -> Image {
+    image! {
+        entry:
+            mov cx, 7;
+            push;
+            mov ax, 5;
+            mov dx, 0x100;
+            store [dx];
+            pop;
+            pop;
+    }
+}
+
+#[test]
+fn text_trace_shows_changes_and_writes() {
+    let tracer = Tracer::new(Vec::new(), TraceFormat::Text);
+    let (result, text) = trace(&program(), tracer);
+    assert!(matches!(result, Err(VmError::StackUnderflow { .. })));
+
+    let lines: Vec<&str> = text.lines().collect();
+    assert_eq!(lines.len(), 7);
+    assert!(lines[0].ends_with("mov cx, 0x7               cx: 0x0 -> 0x7"));
+    assert!(lines[1].contains("sp: 0x2000 -> 0x1fff  [0x1fff] <- 0x7"));
+    assert!(lines[4].ends_with("store [dx]                [0x100] <- 0x5"));
+    assert!(lines[6].contains("! stack underflow"), "{}", lines[6]);
+}
+
+#[test]
+fn json_trace_is_filtered() {
+    let image = program();
+    let entry = image.get_entry_point();
+    let tracer = Tracer::new(Vec::new(), TraceFormat::Json)
+        .with_addresses(entry..entry + 5)
+        .with_opcodes([OpCode::PUSH, OpCode::STORE]);
+    let (_, text) = trace(&image, tracer);
+
+    let lines: Vec<&str> = text.lines().collect();
+    assert_eq!(lines.len(), 1);
+    assert!(lines[0].starts_with(
+        "{\"step\":1,\"ip\":2,\"instruction\":\"push\",\"before\":{\"ip\":2,"
+    ));
+    assert!(lines[0].ends_with(
+        "\"writes\":[{\"address\":8191,\"value\":7}],\"error\":null}"
+    ));
+}
+
+#[test]
+fn syscall_and_malloc_writes_are_traced() {
+    let image = image! {
+        entry:
+            mov dx, 0x100;
+            mov ax, syscall::READ_LINE;
+            syscall;
+            mov cx, 8;
+            malloc;
+            mov dx, 0;
+            mov ax, syscall::EXIT;
+            syscall;
+    };
+    let mut vm = common::vm("ab\n")
+        .with_observer(Tracer::new(Vec::new(), TraceFormat::Text));
+    vm.load_image(&image).unwrap();
+    vm.execute().unwrap();
+    vm.observer_mut().flush().unwrap();
+    let text = String::from_utf8(vm.observer().output().clone()).unwrap();
+
+    let lines: Vec<&str> = text.lines().collect();
+    assert!(
+        lines[2].ends_with("[0x100] <- 0x61  [0x101] <- 0x62  [0x102] <- 0xa"),
+        "{}",
+        lines[2]
+    );
+    assert!(lines[4].contains("malloc"), "{}", lines[4]);
+    assert!(lines[4].ends_with("] <- 0x0"), "{}", lines[4]);
+}